@@ -0,0 +1,109 @@
+//! Functions for compacting the cache index.
+use std::path::Path;
+
+use crate::errors::Result;
+use crate::index::{self, Metadata};
+use crate::lock;
+
+/// Compacts the cache index, rewriting each index bucket so it only holds
+/// the latest entry for each key. Removed keys are dropped entirely, as are
+/// any entries for which `filter` returns `false`. Returns the number of
+/// index entries that were dropped.
+///
+/// Buckets are also compacted automatically once they grow large enough,
+/// but only this function lets you decide what gets kept.
+///
+/// Each bucket is replaced atomically, and compaction waits for writes in
+/// progress to finish, so no entries written concurrently are lost.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     cacache::write("./my-cache", "my-key", b"hello").await?;
+///     cacache::write("./my-cache", "my-key", b"world").await?;
+///
+///     // Only the entry for "world" is kept.
+///     cacache::compact_index("./my-cache", |_| true).await?;
+///
+///     Ok(())
+/// }
+/// ```
+pub async fn compact_index<P, F>(cache: P, filter: F) -> Result<usize>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Metadata) -> bool + Send + 'static,
+{
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(compact_index_sync(&cache, filter))
+}
+
+/// Compacts the cache index synchronously. See [`compact_index`] for
+/// details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     cacache::write_sync("./my-cache", "my-key", b"world")?;
+///
+///     // Drop everything older than a day.
+///     let cutoff = std::time::SystemTime::now()
+///         .duration_since(std::time::UNIX_EPOCH)
+///         .unwrap()
+///         .as_millis()
+///         - 24 * 60 * 60 * 1000;
+///     cacache::compact_index_sync("./my-cache", |entry| entry.time > cutoff)?;
+///
+///     Ok(())
+/// }
+/// ```
+pub fn compact_index_sync<P, F>(cache: P, filter: F) -> Result<usize>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Metadata) -> bool,
+{
+    let _lock = lock::lock_exclusive(cache.as_ref())?;
+    index::compact(cache.as_ref(), filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_compact_index() {
+        task::block_on(async {
+            let tmp = tempfile::tempdir().unwrap();
+            let dir = tmp.path().to_owned();
+            crate::write(&dir, "key", b"my-data").await.unwrap();
+            crate::write(&dir, "key", b"new-data").await.unwrap();
+            crate::write(&dir, "other", b"other-data").await.unwrap();
+
+            let removed = crate::compact_index(&dir, |entry| entry.key != "other")
+                .await
+                .unwrap();
+            assert_eq!(removed, 2);
+
+            let data = crate::read(&dir, "key").await.unwrap();
+            assert_eq!(data, b"new-data");
+            let entry = crate::metadata(&dir, "other").await.unwrap();
+            assert_eq!(entry, None);
+        });
+    }
+
+    #[test]
+    fn test_compact_index_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "key", b"my-data").unwrap();
+        crate::remove_sync(&dir, "key").unwrap();
+
+        let removed = crate::compact_index_sync(&dir, |_| true).unwrap();
+        assert_eq!(removed, 2);
+        assert_eq!(crate::list_sync(&dir).count(), 0);
+    }
+}
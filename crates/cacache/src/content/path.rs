@@ -1,4 +1,4 @@
-use ssri::Integrity;
+use ssri::{Algorithm, Integrity};
 use std::path::{Path, PathBuf};
 
 const CONTENT_VERSION: &str = "2";
@@ -9,10 +9,8 @@ const CONTENT_VERSION: &str = "2";
 // ~/.my-cache/content-v2/sha512/ba/da/55deadbeefc0ffee
 //
 pub fn content_path(cache: &Path, sri: &Integrity) -> PathBuf {
-    let mut path = PathBuf::new();
+    let mut path = content_dir(cache);
     let (algo, hex) = sri.to_hex();
-    path.push(cache);
-    path.push(format!("content-v{}", CONTENT_VERSION));
     path.push(algo.to_string());
     path.push(&hex[0..2]);
     path.push(&hex[2..4]);
@@ -20,6 +18,59 @@ pub fn content_path(cache: &Path, sri: &Integrity) -> PathBuf {
     path
 }
 
+/// Every path the content for `sri` may be stored at, one per hash,
+/// strongest first. Content written with a multi-hash integrity is stored
+/// under each of them.
+pub fn content_paths(cache: &Path, sri: &Integrity) -> Vec<PathBuf> {
+    single_hashes(sri)
+        .iter()
+        .map(|sri| content_path(cache, sri))
+        .collect()
+}
+
+/// Splits `sri` into one single-hash integrity per hash, strongest first.
+pub fn single_hashes(sri: &Integrity) -> Vec<Integrity> {
+    let mut hashes = sri.hashes.clone();
+    hashes.sort_by_key(|hash| strength(hash.algorithm));
+    hashes.dedup();
+    hashes
+        .into_iter()
+        .map(|hash| Integrity { hashes: vec![hash] })
+        .collect()
+}
+
+fn strength(algorithm: Algorithm) -> usize {
+    match algorithm {
+        Algorithm::Sha512 => 0,
+        Algorithm::Sha384 => 1,
+        Algorithm::Sha256 => 2,
+        _ => 3,
+    }
+}
+
+pub fn content_dir(cache: &Path) -> PathBuf {
+    cache.join(format!("content-v{}", CONTENT_VERSION))
+}
+
+/// Which algorithm the content file at `cpath` is stored under, if it's a
+/// content path at all.
+pub fn content_algorithm(cache: &Path, cpath: &Path) -> Option<Algorithm> {
+    let rel = cpath.strip_prefix(content_dir(cache)).ok()?;
+    if rel.components().count() != 4 {
+        return None;
+    }
+    let name = rel.components().next()?.as_os_str().to_str()?;
+    [
+        Algorithm::Sha512,
+        Algorithm::Sha384,
+        Algorithm::Sha256,
+        Algorithm::Sha1,
+    ]
+    .iter()
+    .copied()
+    .find(|algo| algo.to_string() == name)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -42,4 +93,36 @@ mod tests {
         wanted.push("27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
         assert_eq!(cpath.to_str().unwrap(), wanted.to_str().unwrap());
     }
+
+    #[test]
+    fn multi_hash_paths() {
+        let sri: Integrity = format!(
+            "{} {}",
+            Integrity::from(b"hello world"),
+            ssri::IntegrityOpts::new()
+                .algorithm(Algorithm::Sha512)
+                .chain(b"hello world")
+                .result()
+        )
+        .parse()
+        .unwrap();
+        let paths = content_paths(Path::new("~/.my-cache"), &sri);
+        assert_eq!(paths.len(), 2);
+        assert!(paths[0].to_str().unwrap().contains("sha512"));
+        assert!(paths[1].to_str().unwrap().contains("sha256"));
+    }
+
+    #[test]
+    fn algorithm_from_path() {
+        let cache = Path::new("~/.my-cache");
+        let sri = Integrity::from(b"hello world");
+        assert_eq!(
+            content_algorithm(cache, &content_path(cache, &sri)),
+            Some(Algorithm::Sha256)
+        );
+        assert_eq!(
+            content_algorithm(cache, &content_dir(cache).join("tmp")),
+            None
+        );
+    }
 }
@@ -1,5 +1,5 @@
 use std::fs::{self, File};
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 
 use memmap::{Mmap, MmapMut};
@@ -7,6 +7,7 @@ use ssri::{Algorithm, Integrity, IntegrityChecker};
 
 use crate::content::path;
 use crate::errors::{Internal, Result};
+use crate::format::{known_content_format, ContentFormat};
 
 pub const MAX_MMAP_READ_SIZE: usize = 1024 * 1024 * 10;
 #[cfg(not(target_os = "windows"))]
@@ -36,8 +37,56 @@ impl std::io::Read for MaybeMmap {
     }
 }
 
+enum Source {
+    Raw(MaybeMmap),
+    Snappy(snap::read::FrameDecoder<MaybeMmap>),
+}
+
+impl std::io::Read for Source {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Source::Raw(fd) => fd.read(buf),
+            Source::Snappy(fd) => fd.read(buf),
+        }
+    }
+}
+
+impl Source {
+    // Opens the content file at `cpath` for decoding from `format`. Also
+    // returns the content's expected (decoded) size.
+    fn open(cpath: &Path, format: ContentFormat) -> Result<(Self, usize)> {
+        let mut reader = File::open(cpath).to_internal()?;
+        let (offset, expected_size) = match format {
+            ContentFormat::Raw => (0, reader.metadata().to_internal()?.len() as usize),
+            ContentFormat::Snappy => {
+                let mut size = [0u8; 8];
+                reader.read_exact(&mut size).to_internal()?;
+                (8, u64::from_be_bytes(size) as usize)
+            }
+        };
+        reader.seek(SeekFrom::Start(offset as u64)).to_internal()?;
+
+        let fd = MaybeMmap {
+            mmap: if expected_size >= MIN_MMAP_READ_SIZE && expected_size <= MAX_MMAP_READ_SIZE {
+                unsafe { Mmap::map(&reader) }
+                    .ok()
+                    .map(|mmap| (mmap, offset))
+            } else {
+                None
+            },
+            file: BufReader::new(reader),
+        };
+        let source = match format {
+            ContentFormat::Raw => Source::Raw(fd),
+            ContentFormat::Snappy => Source::Snappy(snap::read::FrameDecoder::new(fd)),
+        };
+        Ok((source, expected_size))
+    }
+}
+
 pub struct Reader {
-    fd: snap::read::FrameDecoder<MaybeMmap>,
+    fd: Source,
     checker: IntegrityChecker,
     expected_size: usize,
 }
@@ -56,44 +105,29 @@ impl Reader {
         Ok(self.checker.result()?)
     }
 
-    fn instantiate(cpath: PathBuf, sri: Integrity) -> Result<Self> {
-        let mut reader = File::open(cpath).to_internal()?;
-        let mut bytes = [0u8; 8];
-        reader.read_exact(&mut bytes).to_internal()?;
-        let expected_size = u64::from_be_bytes(bytes) as usize;
-
-        let fd = MaybeMmap {
-            mmap: if expected_size >= MIN_MMAP_READ_SIZE && expected_size <= MAX_MMAP_READ_SIZE {
-                unsafe { Mmap::map(&reader) }.ok().map(|mmap| (mmap, 8))
-            } else {
-                None
-            },
-            file: BufReader::new(reader),
-        };
-
+    fn instantiate(cpath: PathBuf, sri: Integrity, format: ContentFormat) -> Result<Self> {
+        let (fd, expected_size) = Source::open(&cpath, format)?;
         Ok(Reader {
-            fd: snap::read::FrameDecoder::new(fd),
+            fd,
             checker: IntegrityChecker::new(sri),
             expected_size,
         })
     }
 
     pub fn new(cache: &Path, sri: &Integrity) -> Result<Self> {
-        let cpath = path::content_path(&cache, &sri);
-        let sri = sri.clone();
-        Self::instantiate(cpath, sri)
+        let cpath = locate(&cache, &sri);
+        let format = file_format(&cpath, sri, known_content_format(cache)?)?;
+        Self::instantiate(cpath, sri.clone(), format)
     }
 
     pub async fn new_async(cache: &Path, sri: &Integrity) -> Result<Self> {
-        let cpath = path::content_path(&cache, &sri);
+        let cache = cache.to_owned();
         let sri = sri.clone();
-        smol::unblock!(Self::instantiate(cpath, sri))
+        smol::unblock!(Self::new(&cache, &sri))
     }
 
     pub fn consume(cache: &Path, sri: &Integrity) -> Result<Vec<u8>> {
-        let cpath = path::content_path(&cache, &sri);
-        let sri = sri.clone();
-        let mut reader = Self::instantiate(cpath, sri)?;
+        let mut reader = Self::new(cache, sri)?;
 
         let mut v = Vec::with_capacity(reader.expected_size);
         reader.read_to_end(&mut v).to_internal()?;
@@ -103,10 +137,10 @@ impl Reader {
 
     #[inline]
     pub async fn consume_async(cache: &Path, sri: &Integrity) -> Result<Vec<u8>> {
-        let cpath = path::content_path(&cache, &sri);
+        let cache = cache.to_owned();
         let sri = sri.clone();
-        async_std::task::spawn_blocking(|| {
-            let mut reader = Self::instantiate(cpath, sri)?;
+        async_std::task::spawn_blocking(move || {
+            let mut reader = Self::new(&cache, &sri)?;
 
             let mut v = Vec::with_capacity(reader.expected_size);
             reader.read_to_end(&mut v).to_internal()?;
@@ -121,6 +155,19 @@ pub fn open(cache: &Path, sri: Integrity) -> Result<Reader> {
     Reader::new(cache, &sri)
 }
 
+/// Opens the content file at `cpath` specifically, rather than wherever the
+/// content for `sri` is found first. `format` is the cache's recorded one.
+pub fn open_at(cpath: &Path, sri: &Integrity, format: ContentFormat) -> Result<Reader> {
+    let format = file_format(cpath, sri, format)?;
+    Reader::instantiate(cpath.to_owned(), sri.clone(), format)
+}
+
+/// Decodes the content file at `cpath` from `format`, without checking it
+/// against anything.
+pub fn decode_at(cpath: &Path, format: ContentFormat) -> Result<impl Read> {
+    Ok(Source::open(cpath, format)?.0)
+}
+
 pub async fn open_async(cache: &Path, sri: Integrity) -> Result<Reader> {
     Reader::new_async(cache, &sri).await
 }
@@ -157,21 +204,108 @@ pub async fn copy_async<'a>(cache: &'a Path, sri: &'a Integrity, to: &'a Path) -
     smol::unblock!(copy(&cache, &sri, &to))
 }
 
-pub fn has_content(cache: &Path, sri: &Integrity) -> Option<Integrity> {
-    if path::content_path(&cache, &sri).exists() {
-        Some(sri.clone())
-    } else {
-        None
+/// Makes cache content available at `to` without copying it, if possible.
+/// Raw content is reflinked or hard linked, while snappy content always gets
+/// decompressed into a regular copy. Linked files are verified before being
+/// accepted.
+pub fn link(cache: &Path, sri: &Integrity, to: &Path) -> Result<()> {
+    let cpath = locate(&cache, &sri);
+    if file_format(&cpath, sri, known_content_format(cache)?)? == ContentFormat::Raw
+        && (try_link(&cpath, sri, to, |from, to| reflink::reflink(from, to)).is_ok()
+            || try_link(&cpath, sri, to, |from, to| fs::hard_link(from, to)).is_ok())
+    {
+        return Ok(());
+    }
+    copy(cache, sri, to)?;
+    Ok(())
+}
+
+pub async fn link_async<'a>(cache: &'a Path, sri: &'a Integrity, to: &'a Path) -> Result<()> {
+    let cache = cache.to_owned();
+    let sri = sri.to_owned();
+    let to = to.to_owned();
+    smol::unblock!(link(&cache, &sri, &to))
+}
+
+fn try_link<F>(cpath: &Path, sri: &Integrity, to: &Path, linker: F) -> Result<()>
+where
+    F: FnOnce(&Path, &Path) -> std::io::Result<()>,
+{
+    linker(cpath, to).to_internal()?;
+    if let Err(e) = verify_file(to, sri) {
+        // Don't leave links to bad content around.
+        let _ = fs::remove_file(to);
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn verify_file(file: &Path, sri: &Integrity) -> Result<()> {
+    let mut reader = BufReader::new(File::open(file).to_internal()?);
+    let mut checker = IntegrityChecker::new(sri.clone());
+    let mut buf = [0u8; 1024 * 8];
+    loop {
+        let read = reader.read(&mut buf).to_internal()?;
+        if read == 0 {
+            break;
+        }
+        checker.input(&buf[..read]);
     }
+    checker.result()?;
+    Ok(())
+}
+
+// Snappy content starts with its size, and then the snappy stream
+// identifier, unless it's empty.
+const SNAPPY_STREAM_ID: &[u8] = b"\xff\x06\x00\x00sNaPpY";
+
+// Works out which format the content file at `cpath` is in. That's normally
+// the cache's recorded format, but a migration that hasn't finished leaves
+// some files in the other one.
+fn file_format(cpath: &Path, sri: &Integrity, recorded: ContentFormat) -> Result<ContentFormat> {
+    let head_len = 8 + SNAPPY_STREAM_ID.len();
+    let mut head = Vec::with_capacity(head_len);
+    File::open(cpath)
+        .and_then(|file| file.take(head_len as u64).read_to_end(&mut head))
+        .to_internal()?;
+    let could_be_snappy = match head.len() {
+        8 => head == [0u8; 8],
+        len if len == head_len => &head[8..] == SNAPPY_STREAM_ID,
+        _ => false,
+    };
+    Ok(match recorded {
+        _ if !could_be_snappy => ContentFormat::Raw,
+        ContentFormat::Snappy => ContentFormat::Snappy,
+        // Raw content can look like snappy content too, so only treat it as
+        // snappy once it turns out not to be valid as is.
+        ContentFormat::Raw if verify_file(cpath, sri).is_ok() => ContentFormat::Raw,
+        ContentFormat::Raw => ContentFormat::Snappy,
+    })
+}
+
+/// Finds where the content for `sri` is stored. Content may be stored under
+/// any of the integrity's hashes, and the strongest one that's there wins.
+pub fn find_content_path(cache: &Path, sri: &Integrity) -> Option<PathBuf> {
+    path::content_paths(cache, sri)
+        .into_iter()
+        .find(|cpath| cpath.exists())
+}
+
+// Where to read content from. If it's nowhere to be found, the strongest
+// hash's path is used, so errors point at something sensible.
+fn locate(cache: &Path, sri: &Integrity) -> PathBuf {
+    find_content_path(cache, sri).unwrap_or_else(|| path::content_path(cache, sri))
+}
+
+pub fn has_content(cache: &Path, sri: &Integrity) -> Option<Integrity> {
+    find_content_path(cache, sri).map(|_| sri.clone())
 }
 
 pub async fn has_content_async(cache: &Path, sri: &Integrity) -> Option<Integrity> {
-    if async_std::fs::metadata(path::content_path(&cache, &sri))
-        .await
-        .is_ok()
-    {
-        Some(sri.clone())
-    } else {
-        None
+    for cpath in path::content_paths(cache, sri) {
+        if async_std::fs::metadata(cpath).await.is_ok() {
+            return Some(sri.clone());
+        }
     }
+    None
 }
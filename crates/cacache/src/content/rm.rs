@@ -1,20 +1,48 @@
 use std::fs;
+use std::io::ErrorKind;
 use std::path::Path;
 
-use async_std::fs as afs;
 use ssri::Integrity;
 
 use crate::content::path;
-use crate::errors::{Internal, Result};
+use crate::errors::{Error, Internal, Result};
 
+/// Removes the content for `sri`, under every hash it's stored under.
 pub fn rm(cache: &Path, sri: &Integrity) -> Result<()> {
-    fs::remove_file(path::content_path(&cache, &sri)).to_internal()?;
-    Ok(())
+    let mut removed = false;
+    let mut missing = None;
+    for cpath in path::content_paths(cache, sri) {
+        match fs::remove_file(&cpath) {
+            Ok(()) => removed = true,
+            Err(err) if err.kind() == ErrorKind::NotFound => missing = Some(err),
+            Err(err) => return Err(err).to_internal().map_err(Error::from),
+        }
+    }
+    match missing {
+        Some(err) if !removed => Err(err).to_internal().map_err(Error::from),
+        _ => Ok(()),
+    }
 }
 
-pub async fn rm_async(cache: &Path, sri: &Integrity) -> Result<()> {
-    afs::remove_file(path::content_path(&cache, &sri))
-        .await
-        .to_internal()?;
-    Ok(())
+/// Removes the content file at `cpath`, returning how many bytes that
+/// actually freed. Content that's still hard linked somewhere else, like
+/// under another one of its hashes or into a `node_modules`, keeps taking
+/// up space until those links go away too.
+pub fn remove_file(cpath: &Path) -> Result<u64> {
+    let meta =
+        fs::metadata(cpath).with_context(|| format!("Failed to read metadata for {:?}", cpath))?;
+    fs::remove_file(cpath).with_context(|| format!("Failed to remove content at {:?}", cpath))?;
+    Ok(if is_last_link(&meta) { meta.len() } else { 0 })
+}
+
+/// Whether removing the file `meta` belongs to frees its data.
+#[cfg(unix)]
+fn is_last_link(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    meta.nlink() <= 1
+}
+
+#[cfg(not(unix))]
+fn is_last_link(_meta: &fs::Metadata) -> bool {
+    true
 }
@@ -8,6 +8,7 @@ use tempfile::NamedTempFile;
 
 use crate::content::path;
 use crate::errors::{Internal, Result};
+use crate::format::{known_content_format, ContentFormat};
 
 pub const MAX_MMAP_WRITE_SIZE: usize = 1024 * 1024 * 10;
 #[cfg(not(target_os = "windows"))]
@@ -18,11 +19,43 @@ pub const MIN_MMAP_WRITE_SIZE: usize = 1;
 pub struct Writer {
     cache: PathBuf,
     builder: IntegrityOpts,
-    target: snap::write::FrameEncoder<MaybeCursed>,
+    target: Target,
     expected_size: Option<usize>,
     written: usize,
 }
 
+enum Target {
+    Raw(MaybeCursed),
+    Snappy(snap::write::FrameEncoder<MaybeCursed>),
+}
+
+impl Target {
+    fn into_inner(self) -> Result<MaybeCursed> {
+        match self {
+            Target::Raw(inner) => Ok(inner),
+            Target::Snappy(encoder) => encoder.into_inner().to_internal().map_err(Into::into),
+        }
+    }
+}
+
+impl Write for Target {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Target::Raw(inner) => inner.write(buf),
+            Target::Snappy(encoder) => encoder.write(buf),
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Target::Raw(inner) => inner.flush(),
+            Target::Snappy(encoder) => encoder.flush(),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct MaybeCursed {
     cursor: Option<Cursor<Vec<u8>>>,
@@ -64,7 +97,19 @@ impl Write for MaybeCursed {
 }
 
 impl Writer {
-    pub fn new(cache: PathBuf, algo: Algorithm, size: Option<usize>) -> Result<Self> {
+    /// Creates a writer that hashes content with each of `algos`, storing it
+    /// under every resulting hash.
+    pub fn new(cache: PathBuf, algos: Vec<Algorithm>, size: Option<usize>) -> Result<Self> {
+        let format = known_content_format(&cache)?;
+        Self::with_format(cache, algos, size, format)
+    }
+
+    pub fn with_format(
+        cache: PathBuf,
+        algos: Vec<Algorithm>,
+        size: Option<usize>,
+        format: ContentFormat,
+    ) -> Result<Self> {
         let cache_path = cache;
 
         let cursor = size.and_then(|size| {
@@ -89,30 +134,106 @@ impl Writer {
 
         let mut writer = MaybeCursed { tmpfile, cursor };
 
-        size.and_then(|size| writer.write(&size.to_be_bytes()).ok())
-            .or_else(|| writer.write(&0u64.to_be_bytes()).ok());
+        let target = match format {
+            ContentFormat::Raw => Target::Raw(writer),
+            ContentFormat::Snappy => {
+                size.and_then(|size| writer.write(&size.to_be_bytes()).ok())
+                    .or_else(|| writer.write(&0u64.to_be_bytes()).ok());
+                Target::Snappy(snap::write::FrameEncoder::new(writer))
+            }
+        };
 
         Ok(Writer {
             cache: cache_path,
-            builder: IntegrityOpts::new().algorithm(algo),
-            target: snap::write::FrameEncoder::new(writer),
+            builder: algos
+                .into_iter()
+                .fold(IntegrityOpts::new(), |builder, algo| {
+                    builder.algorithm(algo)
+                }),
+            target,
             expected_size: size,
             written: 0,
         })
     }
 
     pub fn close(self) -> Result<Integrity> {
-        let sri = self.builder.result();
-        let cpath = path::content_path(&self.cache, &sri);
-        DirBuilder::new()
-            .recursive(true)
-            // Safe unwrap. cpath always has multiple segments
-            .create(cpath.parent().unwrap())
-            .to_internal()?;
+        self.stage()?.persist()
+    }
 
-        let mut maybe_mmap = self.target.into_inner().to_internal()?;
+    /// Like [`Writer::close`], but only puts the content in place if it
+    /// matches `expected`. As with [`Integrity::check`], it has to match one
+    /// of the hashes made with `expected`'s strongest algorithm. Otherwise,
+    /// it's thrown out and an integrity error is returned.
+    ///
+    /// Content is then only stored under hashes that matched, or that were
+    /// made with an algorithm `expected` has nothing for. A wrong hash in
+    /// `expected` never ends up pointing at it.
+    pub fn close_checked(self, expected: Option<&Integrity>) -> Result<Integrity> {
+        let mut staged = self.stage()?;
+        if let Some(expected) = expected {
+            let strongest = expected.pick_algorithm();
+            if !staged
+                .sri
+                .hashes
+                .iter()
+                .any(|hash| hash.algorithm == strongest && expected.hashes.contains(hash))
+            {
+                return Err(ssri::Error::IntegrityCheckError(expected.clone(), staged.sri).into());
+            }
+            staged.sri.hashes.retain(|hash| {
+                expected.hashes.contains(hash)
+                    || expected
+                        .hashes
+                        .iter()
+                        .all(|other| other.algorithm != hash.algorithm)
+            });
+        }
+        staged.persist()
+    }
+
+    /// Replaces the content file at `cpath` with what was written, as long as
+    /// it hashes to that same path. Returns `false`, leaving `cpath` alone,
+    /// if it doesn't. Unlike [`Writer::close`], failing to replace the file
+    /// is an error.
+    pub fn close_replacing(self, cpath: &Path) -> Result<bool> {
+        let staged = self.stage()?;
+        if path::content_path(&staged.cache, &staged.sri) != cpath {
+            return Ok(false);
+        }
+        let mut data = staged.data;
+        let tmpfile = match (data.tmpfile.take(), data.cursor.take()) {
+            (Some(tmpfile), _) => tmpfile,
+            // The content file may be hard linked elsewhere, so it can't be
+            // written over in place.
+            (None, Some(cursor)) => {
+                let tmp_path = staged.cache.join("tmp");
+                DirBuilder::new()
+                    .recursive(true)
+                    .create(&tmp_path)
+                    .to_internal()?;
+                let mut tmpfile = NamedTempFile::new_in(tmp_path).to_internal()?;
+                tmpfile.write_all(&cursor.into_inner()).to_internal()?;
+                tmpfile
+            }
+            (None, None) => unreachable!(),
+        };
+        set_content_permissions(&tmpfile)?;
+        tmpfile.persist(cpath).to_internal()?;
+        Ok(true)
+    }
+
+    // Finishes writing out the content, without putting it into place yet.
+    fn stage(self) -> Result<Staged> {
+        let sri = self.builder.result();
+        let is_raw = match self.target {
+            Target::Raw(_) => true,
+            Target::Snappy(_) => false,
+        };
+        let mut maybe_mmap = self.target.into_inner()?;
 
         match self.expected_size {
+            // Raw content has no size header to fill in.
+            None if is_raw => maybe_mmap.flush().to_internal()?,
             None => {
                 maybe_mmap.seek(std::io::SeekFrom::Start(0)).to_internal()?;
                 let bytes = (self.written as u64).to_be_bytes();
@@ -126,34 +247,107 @@ impl Writer {
                 }
             }
         };
+        Ok(Staged {
+            cache: self.cache,
+            sri,
+            data: maybe_mmap,
+        })
+    }
+
+    pub async fn new_async(
+        cache: PathBuf,
+        algos: Vec<Algorithm>,
+        size: Option<usize>,
+    ) -> Result<smol::Unblock<Self>> {
+        smol::unblock!(Writer::new(cache, algos, size)).map(smol::Unblock::new)
+    }
+
+    pub async fn close_async(self) -> Result<Integrity> {
+        smol::unblock!(self.close())
+    }
+
+    pub async fn close_checked_async(self, expected: Option<Integrity>) -> Result<Integrity> {
+        smol::unblock!(self.close_checked(expected.as_ref()))
+    }
+}
+
+/// Content that's been fully written out, but isn't in the cache yet.
+struct Staged {
+    cache: PathBuf,
+    sri: Integrity,
+    data: MaybeCursed,
+}
+
+impl Staged {
+    fn persist(self) -> Result<Integrity> {
+        let Staged {
+            cache,
+            sri,
+            data: mut maybe_mmap,
+        } = self;
+        let cpath = path::content_path(&cache, &sri);
+        DirBuilder::new()
+            .recursive(true)
+            // Safe unwrap. cpath always has multiple segments
+            .create(cpath.parent().unwrap())
+            .to_internal()?;
 
         if let Some(tmpfile) = maybe_mmap.tmpfile.take() {
+            set_content_permissions(&tmpfile)?;
             if tmpfile.persist(&cpath).to_internal().is_err() {
                 // We might run into conflicts sometimes when persisting files.
                 // This is ok. We can deal. Let's just make sure the destination
                 // file actually exists, and we can move on.
-                std::fs::metadata(cpath).to_internal()?;
+                std::fs::metadata(&cpath).to_internal()?;
             }
         } else if let Some(cursor) = maybe_mmap.cursor.take() {
             if persist_cursor(cursor, &cpath).is_err() {
                 // Same as above
-                std::fs::metadata(cpath).to_internal()?;
+                std::fs::metadata(&cpath).to_internal()?;
+            }
+        }
+        // Store the content under its other hashes too, so lookups by any
+        // one of them find it.
+        for other in path::content_paths(&cache, &sri) {
+            if other != cpath && !other.exists() {
+                link_or_copy(&cpath, &other)?;
             }
         }
         Ok(sri)
     }
+}
 
-    pub async fn new_async(
-        cache: PathBuf,
-        algo: Algorithm,
-        size: Option<usize>,
-    ) -> Result<smol::Unblock<Self>> {
-        smol::unblock!(Writer::new(cache, algo, size)).map(smol::Unblock::new)
+// Temp files are created owner-only, but content may end up hard-linked
+// elsewhere, so give it regular file permissions.
+fn set_content_permissions(tmpfile: &NamedTempFile) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tmpfile
+            .as_file()
+            .set_permissions(std::fs::Permissions::from_mode(0o644))
+            .to_internal()?;
     }
+    #[cfg(not(unix))]
+    let _ = tmpfile;
+    Ok(())
+}
 
-    pub async fn close_async(self) -> Result<Integrity> {
-        smol::unblock!(self.close())
+fn link_or_copy(from: &Path, to: &Path) -> Result<()> {
+    DirBuilder::new()
+        .recursive(true)
+        // Safe unwrap. Content paths always have multiple segments
+        .create(to.parent().unwrap())
+        .to_internal()?;
+    if std::fs::hard_link(from, to).is_err() {
+        // Copy to a temp file first, so nobody sees a partial file.
+        let tmp = NamedTempFile::new_in(to.parent().unwrap()).to_internal()?;
+        std::fs::copy(from, tmp.path()).to_internal()?;
+        if tmp.persist(to).is_err() {
+            std::fs::metadata(to).to_internal()?;
+        }
     }
+    Ok(())
 }
 
 fn persist_cursor(cursor: Cursor<Vec<u8>>, cpath: impl AsRef<Path>) -> Result<()> {
@@ -195,7 +389,7 @@ mod tests {
     fn basic_write() {
         let tmp = tempfile::tempdir().unwrap();
         let dir = tmp.path().to_owned();
-        let mut writer = Writer::new(dir.clone(), Algorithm::Sha256, None).unwrap();
+        let mut writer = Writer::new(dir.clone(), vec![Algorithm::Sha256], None).unwrap();
         writer.write_all(b"hello world").unwrap();
         let sri = writer.close().unwrap();
         assert_eq!(sri.to_string(), Integrity::from(b"hello world").to_string());
@@ -220,7 +414,8 @@ mod tests {
         let tmp = tempfile::tempdir().unwrap();
         let dir = tmp.path().to_owned();
         let input = b"hello world, how are you";
-        let mut writer = Writer::new(dir.clone(), Algorithm::Sha256, Some(input.len())).unwrap();
+        let mut writer =
+            Writer::new(dir.clone(), vec![Algorithm::Sha256], Some(input.len())).unwrap();
         writer.write_all(input).unwrap();
         let sri = writer.close().unwrap();
         assert_eq!(sri.to_string(), Integrity::from(input).to_string());
@@ -38,6 +38,10 @@ pub enum Error {
     #[error("Entry not found for key {1:?} in cache {0:?}")]
     EntryNotFound(PathBuf, String),
 
+    /// Returned when content could not be found for an integrity hash.
+    #[error("Content not found for integrity {0}")]
+    ContentNotFound(ssri::Integrity),
+
     /// Returned when a size check has failed.
     #[error("Size check failed.\n\tWanted: {0}\n\tActual: {1}")]
     SizeError(usize, usize),
@@ -50,6 +54,14 @@ pub enum Error {
         source: ssri::Error,
     },
 
+    /// Returned when a content format name isn't one cacache knows about.
+    #[error("Unknown content format {0:?}. Expected `raw` or `snappy`.")]
+    UnknownContentFormat(String),
+
+    /// Returned when an index format name isn't one cacache knows about.
+    #[error("Unknown index format {0:?}. Expected `cacache` or `npm`.")]
+    UnknownIndexFormat(String),
+
     /// Returned if an internal (e.g. io) operation has failed.
     #[error(transparent)]
     InternalError {
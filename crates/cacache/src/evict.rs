@@ -0,0 +1,374 @@
+//! Functions for keeping a cache under a maximum size.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use walkdir::WalkDir;
+
+use crate::content::{path, rm};
+use crate::errors::{Internal, Result};
+use crate::index;
+use crate::lock;
+
+/// Statistics about the work done by an [`evict`] run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EvictStats {
+    /// Number of index entries that were evicted.
+    pub evicted_entries: usize,
+    /// Number of content blobs removed.
+    pub reclaimed_count: usize,
+    /// Total size, in bytes, of removed content blobs that were actually
+    /// freed. Blobs that are still hard linked somewhere else, like into a
+    /// `node_modules`, keep taking up space until those links go away too,
+    /// so they're left out.
+    pub reclaimed_size: u64,
+    /// Total size, in bytes, of content left in the cache.
+    pub kept_size: u64,
+}
+
+/// Evicts least-recently-used entries until the cache's content takes up no
+/// more than `max_size` bytes. Only the cache's own content counts towards
+/// `max_size`: hard links to it from outside the cache aren't followed.
+///
+/// Entries count as used when they're written or read by key, but not when
+/// only their metadata is looked up. Content
+/// is only removed once no remaining entry refers to it, either directly or
+/// through [`WriteOpts::references`](crate::WriteOpts::references), and
+/// content that no entry refers to at all is the first to go. Like
+/// [`verify`], eviction lets writes carry on while it works out what to
+/// evict, and only holds them off while actually removing anything (see
+/// [`CacheLock`]). Entries written in the meantime, and
+/// content they refer to, are kept.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     cacache::write("./my-cache", "my-key", b"hello").await?;
+///
+///     // Keep the cache under 1GB.
+///     let stats = cacache::evict("./my-cache", 1_000_000_000).await?;
+///     println!("evicted {} entries", stats.evicted_entries);
+///
+///     Ok(())
+/// }
+/// ```
+pub async fn evict<P: AsRef<Path>>(cache: P, max_size: u64) -> Result<EvictStats> {
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(evict_sync(cache, max_size))
+}
+
+/// Evicts least-recently-used entries until the cache's content takes up no
+/// more than `max_size` bytes, synchronously. See [`evict`] for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///
+///     // Keep the cache under 1GB.
+///     let stats = cacache::evict_sync("./my-cache", 1_000_000_000)?;
+///     println!("evicted {} entries", stats.evicted_entries);
+///
+///     Ok(())
+/// }
+/// ```
+pub fn evict_sync<P: AsRef<Path>>(cache: P, max_size: u64) -> Result<EvictStats> {
+    let cache = cache.as_ref();
+    let started = SystemTime::now();
+    let mut stats = EvictStats::default();
+
+    // Work out what to evict while writes carry on as usual, keeping out
+    // only anything else that removes data.
+    let shared = lock::lock_shared_sync(cache)?;
+    let content_dir = path::content_dir(cache);
+    let mut sizes = HashMap::new();
+    if content_dir.exists() {
+        for file in WalkDir::new(&content_dir) {
+            let file = file.to_internal()?;
+            if file.file_type().is_file() {
+                let meta = file
+                    .metadata()
+                    .with_context(|| format!("Failed to read metadata for {:?}", file.path()))?;
+                stats.kept_size += meta.len();
+                sizes.insert(file.path().to_owned(), (meta.len(), meta.modified().ok()));
+            }
+        }
+    }
+    if stats.kept_size <= max_size {
+        return Ok(stats);
+    }
+
+    let mut entries = Vec::new();
+    for entry in live_entries(cache)? {
+        let paths = content_paths(cache, &entry);
+        entries.push((index::last_access(cache, &entry), entry.key, paths));
+    }
+    let mut refcounts = HashMap::new();
+    for (_, _, paths) in entries.iter() {
+        for path in paths {
+            *refcounts.entry(path.clone()).or_insert(0usize) += 1;
+        }
+    }
+
+    // Content nobody refers to goes first, oldest first. Anything written
+    // after we started might be about to get an index entry, so leave it be.
+    let mut size = stats.kept_size;
+    let mut doomed = Vec::new();
+    let mut unreferenced = sizes
+        .iter()
+        .filter(|(path, _)| !refcounts.contains_key(*path))
+        .filter_map(|(path, (_, modified))| {
+            modified
+                .filter(|time| *time <= started)
+                .map(|time| (time, path.clone()))
+        })
+        .collect::<Vec<_>>();
+    unreferenced.sort();
+    for (_, path) in unreferenced {
+        if size <= max_size {
+            break;
+        }
+        size -= sizes[&path].0;
+        doomed.push(path);
+    }
+
+    // Then entries, least recently used first.
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut evicted = HashSet::new();
+    for (_, key, paths) in entries {
+        if size <= max_size {
+            break;
+        }
+        for path in paths {
+            let count = refcounts.get_mut(&path).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                if let Some((len, _)) = sizes.get(&path) {
+                    size -= len;
+                    doomed.push(path);
+                }
+            }
+        }
+        evicted.insert(key);
+    }
+    drop(shared);
+
+    // Entries rewritten while we were busy stay, even if their key was
+    // evicted, and so does anything they or any other entry refer to now.
+    let _lock = lock::lock_exclusive(cache)?;
+    let started_millis = started
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or(0);
+    let mut live = HashSet::new();
+    for entry in live_entries(cache)? {
+        if evicted.contains(&entry.key) && entry.time <= started_millis {
+            continue;
+        }
+        evicted.remove(&entry.key);
+        live.extend(content_paths(cache, &entry));
+    }
+    for path in doomed {
+        if !live.contains(&path) {
+            remove_content(&path, started, &mut stats)?;
+        }
+    }
+    stats.evicted_entries = evicted.len();
+    index::compact(cache, |entry| !evicted.contains(&entry.key))?;
+
+    Ok(stats)
+}
+
+/// Lists the cache's live index entries.
+fn live_entries(cache: &Path) -> Result<Vec<index::Metadata>> {
+    let mut entries = Vec::new();
+    if index::index_dir(cache).exists() {
+        for entry in index::ls(cache) {
+            entries.push(entry?);
+        }
+    }
+    Ok(entries)
+}
+
+/// Every content path `entry` keeps alive.
+fn content_paths(cache: &Path, entry: &index::Metadata) -> HashSet<PathBuf> {
+    std::iter::once(&entry.integrity)
+        .chain(entry.references.iter())
+        .flat_map(|sri| path::content_paths(cache, sri))
+        .collect()
+}
+
+/// Removes content that was picked for eviction, unless something else
+/// already removed it, or it's been written again since eviction started.
+fn remove_content(path: &Path, started: SystemTime, stats: &mut EvictStats) -> Result<()> {
+    let meta = match fs::metadata(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        meta => meta.with_context(|| format!("Failed to read metadata for {:?}", path))?,
+    };
+    if meta.modified().map(|time| time > started).unwrap_or(true) {
+        return Ok(());
+    }
+    // Links can go away while we evict, like content's other hashes, so
+    // check whether this one frees anything now rather than going by what
+    // it looked like when we started.
+    stats.reclaimed_size += rm::remove_file(path)?;
+    stats.kept_size -= meta.len();
+    stats.reclaimed_count += 1;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+
+    use async_std::task;
+    use filetime::FileTime;
+    use pretty_assertions::assert_eq;
+
+    use crate::content::path;
+
+    fn content_size(dir: &std::path::Path, sri: &ssri::Integrity) -> u64 {
+        fs::metadata(path::content_path(dir, sri)).unwrap().len()
+    }
+
+    #[test]
+    fn test_evict_under_budget() {
+        task::block_on(async {
+            let tmp = tempfile::tempdir().unwrap();
+            let dir = tmp.path().to_owned();
+            let sri = crate::write(&dir, "key", b"my-data").await.unwrap();
+
+            let stats = crate::evict(&dir, 1_000_000).await.unwrap();
+            assert_eq!(stats.evicted_entries, 0);
+            assert_eq!(stats.kept_size, content_size(&dir, &sri));
+            assert!(crate::exists(&dir, &sri).await);
+        });
+    }
+
+    fn set_last_access(dir: &std::path::Path, key: &str, secs: i64) {
+        let bucket = crate::index::bucket_path(dir, key);
+        filetime::set_file_mtime(bucket, FileTime::from_unix_time(secs, 0)).unwrap();
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let old = crate::write_sync(&dir, "old", b"old-data").unwrap();
+        let new = crate::write_sync(&dir, "new", b"new-data").unwrap();
+        set_last_access(&dir, "old", 2);
+        set_last_access(&dir, "new", 1);
+        // Reading "new" makes it the most recently used entry.
+        crate::read_sync(&dir, "new").unwrap();
+
+        let old_size = content_size(&dir, &old);
+        let stats = crate::evict_sync(&dir, content_size(&dir, &new)).unwrap();
+        assert_eq!(stats.evicted_entries, 1);
+        assert_eq!(stats.reclaimed_size, old_size);
+        assert_eq!(crate::metadata_sync(&dir, "old").unwrap(), None);
+        assert!(!crate::exists_sync(&dir, &old));
+        assert_eq!(crate::read_sync(&dir, "new").unwrap(), b"new-data");
+    }
+
+    #[test]
+    fn test_evict_ignores_metadata_lookups() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let old = crate::write_sync(&dir, "old", b"old-data").unwrap();
+        let new = crate::write_sync(&dir, "new", b"new-data").unwrap();
+        set_last_access(&dir, "old", 1);
+        set_last_access(&dir, "new", 2);
+        // Checking whether "old" is there doesn't count as using it.
+        assert!(crate::metadata_sync(&dir, "old").unwrap().is_some());
+
+        crate::evict_sync(&dir, content_size(&dir, &new)).unwrap();
+        assert_eq!(crate::metadata_sync(&dir, "old").unwrap(), None);
+        assert!(!crate::exists_sync(&dir, &old));
+        assert_eq!(crate::read_sync(&dir, "new").unwrap(), b"new-data");
+    }
+
+    #[test]
+    fn test_evict_keeps_shared_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let shared = crate::write_sync(&dir, "a", b"same-data").unwrap();
+        crate::write_sync(&dir, "b", b"same-data").unwrap();
+        let other = crate::write_sync(&dir, "c", b"other-data").unwrap();
+        set_last_access(&dir, "a", 1);
+        set_last_access(&dir, "c", 2);
+        set_last_access(&dir, "b", 3);
+
+        let stats = crate::evict_sync(&dir, content_size(&dir, &shared)).unwrap();
+        assert_eq!(stats.evicted_entries, 2);
+        assert_eq!(stats.reclaimed_count, 1);
+        assert!(!crate::exists_sync(&dir, &other));
+        assert_eq!(crate::metadata_sync(&dir, "a").unwrap(), None);
+        assert_eq!(crate::read_sync(&dir, "b").unwrap(), b"same-data");
+    }
+
+    #[test]
+    fn test_evict_referenced_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let file = crate::write_hash_sync(&dir, b"file-data").unwrap();
+        let mut writer = crate::WriteOpts::new()
+            .references(vec![file.clone()])
+            .open_sync(&dir, "key")
+            .unwrap();
+        writer.write_all(b"my-data").unwrap();
+        writer.commit().unwrap();
+
+        let stats = crate::evict_sync(&dir, 0).unwrap();
+        assert_eq!(stats.evicted_entries, 1);
+        assert_eq!(stats.reclaimed_count, 2);
+        assert_eq!(stats.kept_size, 0);
+        assert!(!crate::exists_sync(&dir, &file));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_evict_counts_multi_hash_content_once() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = ssri::IntegrityOpts::new()
+            .algorithm(ssri::Algorithm::Sha256)
+            .algorithm(ssri::Algorithm::Sha512)
+            .chain(b"my-data")
+            .result();
+        let mut writer = crate::WriteOpts::new()
+            .integrity(sri.clone())
+            .open_sync(&dir, "key")
+            .unwrap();
+        writer.write_all(b"my-data").unwrap();
+        writer.commit().unwrap();
+        let size = content_size(&dir, &sri);
+
+        // Both hashes are links to the same data, which only gets freed once.
+        let stats = crate::evict_sync(&dir, 0).unwrap();
+        assert_eq!(stats.reclaimed_count, 2);
+        assert_eq!(stats.reclaimed_size, size);
+        assert_eq!(stats.kept_size, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_evict_linked_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("cache");
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        fs::hard_link(path::content_path(&dir, &sri), tmp.path().join("linked")).unwrap();
+
+        let stats = crate::evict_sync(&dir, 0).unwrap();
+        assert_eq!(stats.reclaimed_count, 1);
+        assert_eq!(stats.kept_size, 0);
+        // The data's still there, through the other link.
+        assert_eq!(stats.reclaimed_size, 0);
+        assert_eq!(fs::read(tmp.path().join("linked")).unwrap(), b"my-data");
+    }
+}
@@ -0,0 +1,629 @@
+//! Functions for choosing and migrating the on-disk format of the cache.
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+use walkdir::WalkDir;
+
+use crate::content::{path, read, write};
+use crate::errors::{Error, Internal, Result};
+use crate::index;
+use crate::lock;
+
+const FORMAT_FILE: &str = "content-format";
+const INDEX_FORMAT_FILE: &str = "index-format";
+
+/// How content data is stored on disk.
+///
+/// New content is written in the format recorded for the cache, and
+/// changing the format converts all existing content (see
+/// [`migrate_content`]). Reads work out which format each file is in, so
+/// content left in the old format by an interrupted migration stays
+/// readable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentFormat {
+    /// Content is stored exactly as written. Raw content can be hard linked
+    /// or reflinked out of the cache, and can be shared with other
+    /// cacache-compatible tools.
+    Raw,
+    /// Content is compressed with snappy, behind a size header. This is the
+    /// default, and what caches without a recorded format contain.
+    Snappy,
+}
+
+impl Default for ContentFormat {
+    fn default() -> Self {
+        ContentFormat::Snappy
+    }
+}
+
+impl fmt::Display for ContentFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentFormat::Raw => write!(f, "raw"),
+            ContentFormat::Snappy => write!(f, "snappy"),
+        }
+    }
+}
+
+impl FromStr for ContentFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "raw" => Ok(ContentFormat::Raw),
+            "snappy" => Ok(ContentFormat::Snappy),
+            other => Err(Error::UnknownContentFormat(other.into())),
+        }
+    }
+}
+
+/// How index buckets are laid out on disk.
+///
+/// Lookups check buckets in both formats, so caches can switch between them
+/// at any time without losing entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexFormat {
+    /// cacache's own layout. This is the default.
+    Cacache,
+    /// The layout npm uses for its own cache (`~/.npm/_cacache`). Combined
+    /// with [`ContentFormat::Raw`] content, this lets cacache and npm share
+    /// a single cache directory.
+    Npm,
+}
+
+impl Default for IndexFormat {
+    fn default() -> Self {
+        IndexFormat::Cacache
+    }
+}
+
+impl fmt::Display for IndexFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexFormat::Cacache => write!(f, "cacache"),
+            IndexFormat::Npm => write!(f, "npm"),
+        }
+    }
+}
+
+impl FromStr for IndexFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "cacache" => Ok(IndexFormat::Cacache),
+            "npm" => Ok(IndexFormat::Npm),
+            other => Err(Error::UnknownIndexFormat(other.into())),
+        }
+    }
+}
+
+/// Returns the format new content in this cache gets written in.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let format = cacache::content_format("./my-cache").await?;
+///     println!("content is written as {}", format);
+///     Ok(())
+/// }
+/// ```
+pub async fn content_format<P: AsRef<Path>>(cache: P) -> Result<ContentFormat> {
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(content_format_sync(cache))
+}
+
+/// Returns the format new content in this cache gets written in,
+/// synchronously.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let format = cacache::content_format_sync("./my-cache")?;
+///     println!("content is written as {}", format);
+///     Ok(())
+/// }
+/// ```
+pub fn content_format_sync<P: AsRef<Path>>(cache: P) -> Result<ContentFormat> {
+    let cache = cache.as_ref();
+    let format = read_record(&format_file(cache))?;
+    remember_content_format(cache, format);
+    Ok(format)
+}
+
+// Content formats we've already read, by cache path, so reading and writing
+// content doesn't have to go to disk for it every time. Formats changed by
+// other processes are picked up the next time `content_format_sync` runs,
+// and until then, reads still find content in whichever format it's in.
+static CONTENT_FORMATS: Lazy<RwLock<HashMap<PathBuf, ContentFormat>>> = Lazy::new(Default::default);
+
+/// The format new content in this cache gets written in, as last read.
+pub(crate) fn known_content_format(cache: &Path) -> Result<ContentFormat> {
+    if let Some(format) = CONTENT_FORMATS.read().unwrap().get(cache) {
+        return Ok(*format);
+    }
+    content_format_sync(cache)
+}
+
+fn remember_content_format(cache: &Path, format: ContentFormat) {
+    CONTENT_FORMATS
+        .write()
+        .unwrap()
+        .insert(cache.to_owned(), format);
+}
+
+/// Records the format content in this cache should be stored in, converting
+/// any existing content. See [`migrate_content`] for details.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     cacache::set_content_format("./my-cache", cacache::ContentFormat::Raw).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn set_content_format<P: AsRef<Path>>(cache: P, format: ContentFormat) -> Result<()> {
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(set_content_format_sync(cache, format))
+}
+
+/// Records the format content in this cache should be stored in, converting
+/// any existing content, synchronously. See [`migrate_content`] for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::set_content_format_sync("./my-cache", cacache::ContentFormat::Raw)?;
+///     Ok(())
+/// }
+/// ```
+pub fn set_content_format_sync<P: AsRef<Path>>(cache: P, format: ContentFormat) -> Result<()> {
+    migrate_content_sync(cache, format).map(|_| ())
+}
+
+/// Records `format` as the format content in this cache should be stored in,
+/// if the cache doesn't have one recorded and doesn't hold any entries yet.
+/// Existing caches are never converted behind anyone's back, so they keep
+/// using whatever format they already have: use [`migrate_content`] for
+/// that. Returns the format the cache ends up using.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let format = cacache::init_content_format("./my-cache", cacache::ContentFormat::Raw).await?;
+///     println!("content is written as {}", format);
+///     Ok(())
+/// }
+/// ```
+pub async fn init_content_format<P: AsRef<Path>>(
+    cache: P,
+    format: ContentFormat,
+) -> Result<ContentFormat> {
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(init_content_format_sync(cache, format))
+}
+
+/// Records `format` as the format content in this cache should be stored in,
+/// unless the cache already has one recorded, synchronously. See
+/// [`init_content_format`] for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let format = cacache::init_content_format_sync("./my-cache", cacache::ContentFormat::Raw)?;
+///     println!("content is written as {}", format);
+///     Ok(())
+/// }
+/// ```
+pub fn init_content_format_sync<P: AsRef<Path>>(
+    cache: P,
+    format: ContentFormat,
+) -> Result<ContentFormat> {
+    let cache = cache.as_ref();
+    if format_file(cache).exists() || index::index_dir(cache).exists() {
+        return content_format_sync(cache);
+    }
+    set_content_format_sync(cache, format)?;
+    Ok(format)
+}
+
+/// Returns the format new index entries in this cache get written in.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let format = cacache::index_format("./my-cache").await?;
+///     println!("index entries are written as {}", format);
+///     Ok(())
+/// }
+/// ```
+pub async fn index_format<P: AsRef<Path>>(cache: P) -> Result<IndexFormat> {
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(index_format_sync(cache))
+}
+
+/// Returns the format new index entries in this cache get written in,
+/// synchronously.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let format = cacache::index_format_sync("./my-cache")?;
+///     println!("index entries are written as {}", format);
+///     Ok(())
+/// }
+/// ```
+pub fn index_format_sync<P: AsRef<Path>>(cache: P) -> Result<IndexFormat> {
+    read_record(&cache.as_ref().join(INDEX_FORMAT_FILE))
+}
+
+/// Records the format new index entries in this cache should be written in.
+/// Existing entries stay where they are, and can still be looked up.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     // Share a cache with npm.
+///     let cache = std::path::Path::new(&std::env::var("HOME").unwrap()).join(".npm/_cacache");
+///     cacache::set_index_format(&cache, cacache::IndexFormat::Npm).await?;
+///     cacache::set_content_format(&cache, cacache::ContentFormat::Raw).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn set_index_format<P: AsRef<Path>>(cache: P, format: IndexFormat) -> Result<()> {
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(set_index_format_sync(cache, format))
+}
+
+/// Records the format new index entries in this cache should be written in,
+/// synchronously. See [`set_index_format`] for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::set_index_format_sync("./my-cache", cacache::IndexFormat::Npm)?;
+///     Ok(())
+/// }
+/// ```
+pub fn set_index_format_sync<P: AsRef<Path>>(cache: P, format: IndexFormat) -> Result<()> {
+    write_record(cache.as_ref(), INDEX_FORMAT_FILE, format)
+}
+
+/// Switches the cache to `format` and rewrites all of its content in that
+/// format. Returns the number of content files that were rewritten.
+///
+/// Migration waits for any writes in progress, in this or any other process,
+/// and holds off new ones until it's done (see [`CacheLock`]). Content files
+/// are replaced atomically, and reads work out which format each one is in,
+/// so content stays readable during a migration and after an interrupted
+/// one. A migration that gets interrupted picks up where it left off when
+/// run again.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     cacache::migrate_content("./my-cache", cacache::ContentFormat::Raw).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn migrate_content<P: AsRef<Path>>(cache: P, format: ContentFormat) -> Result<usize> {
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(migrate_content_sync(cache, format))
+}
+
+/// Switches the cache to `format` and rewrites all of its content in that
+/// format, synchronously. See [`migrate_content`] for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::migrate_content_sync("./my-cache", cacache::ContentFormat::Raw)?;
+///     Ok(())
+/// }
+/// ```
+pub fn migrate_content_sync<P: AsRef<Path>>(cache: P, format: ContentFormat) -> Result<usize> {
+    let cache = cache.as_ref();
+    let _lock = lock::lock_exclusive(cache)?;
+    let current = content_format_sync(cache)?;
+    let content_dir = path::content_dir(cache);
+    let mut migrated = 0;
+    if current != format && content_dir.exists() {
+        for file in WalkDir::new(&content_dir) {
+            let file = file.to_internal()?;
+            if file.file_type().is_file() && migrate_one(cache, file.path(), current, format)? {
+                migrated += 1;
+            }
+        }
+    }
+    // Only now is the content actually in its new format.
+    write_record(cache, FORMAT_FILE, format)?;
+    remember_content_format(cache, format);
+    Ok(migrated)
+}
+
+// Rewrites the content file at `cpath` from `from` to `to`. Files that don't
+// decode to their own hash as `from` are skipped: either an earlier,
+// interrupted migration already got to them, or they're corrupted, which is
+// for `verify` to deal with.
+fn migrate_one(cache: &Path, cpath: &Path, from: ContentFormat, to: ContentFormat) -> Result<bool> {
+    let algo = match path::content_algorithm(cache, cpath) {
+        Some(algo) => algo,
+        None => return Ok(false),
+    };
+    let mut writer = write::Writer::with_format(cache.to_owned(), vec![algo], None, to)?;
+    let copied = read::decode_at(cpath, from)
+        .and_then(|mut reader| std::io::copy(&mut reader, &mut writer).to_internal());
+    if copied.is_err() {
+        return Ok(false);
+    }
+    writer.flush().to_internal()?;
+    writer.close_replacing(cpath)
+}
+
+pub(crate) fn format_file(cache: &Path) -> PathBuf {
+    cache.join(FORMAT_FILE)
+}
+
+/// Files that hold cache settings rather than data.
+pub(crate) fn is_settings_file(cache: &Path, file: &Path) -> bool {
+    file == cache.join(FORMAT_FILE) || file == cache.join(INDEX_FORMAT_FILE)
+}
+
+fn read_record<T>(file: &Path) -> Result<T>
+where
+    T: FromStr<Err = Error> + Default,
+{
+    match fs::read_to_string(&file) {
+        Ok(format) => format.parse(),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(T::default()),
+        Err(err) => Err(err)
+            .with_context(|| format!("Failed to read cache format from {:?}", file))
+            .map_err(Error::from),
+    }
+}
+
+fn write_record<T: fmt::Display>(cache: &Path, name: &str, format: T) -> Result<()> {
+    fs::create_dir_all(cache)
+        .with_context(|| format!("Failed to create cache directory at {:?}", cache))?;
+    let file = cache.join(name);
+    fs::write(&file, format.to_string())
+        .with_context(|| format!("Failed to write cache format to {:?}", file))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use digest::Digest;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_default_format() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(
+            content_format_sync(tmp.path()).unwrap(),
+            ContentFormat::Snappy
+        );
+    }
+
+    #[test]
+    fn test_init_content_format() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        assert_eq!(
+            init_content_format_sync(&dir, ContentFormat::Raw).unwrap(),
+            ContentFormat::Raw
+        );
+
+        // Caches that already have entries keep the format they have.
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "key", b"hello world").unwrap();
+        assert_eq!(
+            init_content_format_sync(&dir, ContentFormat::Raw).unwrap(),
+            ContentFormat::Snappy
+        );
+        assert!(!format_file(&dir).exists());
+        assert_eq!(crate::read_sync(&dir, "key").unwrap(), b"hello world");
+
+        // Once a format is recorded, it sticks.
+        set_content_format_sync(&dir, ContentFormat::Snappy).unwrap();
+        assert_eq!(
+            init_content_format_sync(&dir, ContentFormat::Raw).unwrap(),
+            ContentFormat::Snappy
+        );
+    }
+
+    #[test]
+    fn test_raw_content_is_stored_as_is() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        set_content_format_sync(&dir, ContentFormat::Raw).unwrap();
+        let sri = crate::write_sync(&dir, "key", b"hello world").unwrap();
+
+        let stored = fs::read(path::content_path(&dir, &sri)).unwrap();
+        assert_eq!(stored, b"hello world");
+        assert_eq!(crate::read_sync(&dir, "key").unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn test_reads_mixed_formats() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let snappy = crate::write_sync(&dir, "snappy", b"snappy data").unwrap();
+        set_content_format_sync(&dir, ContentFormat::Raw).unwrap();
+        let raw = crate::write_sync(&dir, "raw", b"raw data").unwrap();
+        let empty = crate::write_sync(&dir, "empty", b"").unwrap();
+
+        assert_eq!(
+            crate::read_hash_sync(&dir, &snappy).unwrap(),
+            b"snappy data"
+        );
+        assert_eq!(crate::read_hash_sync(&dir, &raw).unwrap(), b"raw data");
+        assert_eq!(crate::read_hash_sync(&dir, &empty).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_clear_keeps_format() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        set_content_format_sync(&dir, ContentFormat::Raw).unwrap();
+        crate::write_sync(&dir, "key", b"hello world").unwrap();
+
+        crate::clear_sync(&dir).unwrap();
+        assert_eq!(content_format_sync(&dir).unwrap(), ContentFormat::Raw);
+    }
+
+    #[test]
+    fn test_npm_index_format() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let old = crate::write_sync(&dir, "old", b"old data").unwrap();
+        set_index_format_sync(&dir, IndexFormat::Npm).unwrap();
+        set_content_format_sync(&dir, ContentFormat::Raw).unwrap();
+        let sri = crate::write_sync(&dir, "key", b"hello world").unwrap();
+
+        // npm puts buckets at the sha256 of the key, with sha1-hashed lines.
+        let bucket = dir
+            .join("index-v5")
+            .join("2c")
+            .join("70")
+            .join("e12b7a0646f92279f427c7b38e7334d8e5389cff167a1dc30e73f826b683");
+        let contents = fs::read_to_string(bucket).unwrap();
+        let (hash, entry) = contents.trim_start().split_at(40);
+        let entry = &entry[1..];
+        assert_eq!(hash, hex::encode(sha1::Sha1::digest(entry.as_bytes())));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(entry).unwrap()["integrity"],
+            sri.to_string()
+        );
+
+        assert_eq!(crate::read_sync(&dir, "key").unwrap(), b"hello world");
+        assert_eq!(crate::read_sync(&dir, "old").unwrap(), b"old data");
+        assert_eq!(crate::list_sync(&dir).count(), 2);
+
+        crate::remove_sync(&dir, "old").unwrap();
+        assert_eq!(crate::metadata_sync(&dir, "old").unwrap(), None);
+        assert!(crate::exists_sync(&dir, &old));
+        assert_eq!(crate::list_sync(&dir).count(), 1);
+    }
+
+    #[test]
+    fn test_migrate_content() {
+        async_std::task::block_on(async {
+            let tmp = tempfile::tempdir().unwrap();
+            let dir = tmp.path().to_owned();
+            let sri = crate::write(&dir, "key", b"hello world").await.unwrap();
+
+            let migrated = migrate_content(&dir, ContentFormat::Raw).await.unwrap();
+            assert_eq!(migrated, 1);
+            assert_eq!(content_format(&dir).await.unwrap(), ContentFormat::Raw);
+            let stored = fs::read(path::content_path(&dir, &sri)).unwrap();
+            assert_eq!(stored, b"hello world");
+
+            let migrated = migrate_content(&dir, ContentFormat::Snappy).await.unwrap();
+            assert_eq!(migrated, 1);
+            assert_eq!(crate::read(&dir, "key").await.unwrap(), b"hello world");
+            let stored = fs::read(path::content_path(&dir, &sri)).unwrap();
+            assert_ne!(stored, b"hello world");
+        });
+    }
+
+    #[test]
+    fn test_migration_resumes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let done = crate::write_sync(&dir, "done", b"already migrated").unwrap();
+        crate::write_sync(&dir, "pending", b"not migrated yet").unwrap();
+        // As if an earlier migration got through one file before stopping.
+        fs::write(path::content_path(&dir, &done), b"already migrated").unwrap();
+
+        assert_eq!(migrate_content_sync(&dir, ContentFormat::Raw).unwrap(), 1);
+        assert_eq!(crate::read_sync(&dir, "done").unwrap(), b"already migrated");
+        assert_eq!(
+            crate::read_sync(&dir, "pending").unwrap(),
+            b"not migrated yet"
+        );
+    }
+
+    #[test]
+    fn test_reads_interrupted_migrations() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let done = crate::write_sync(&dir, "done", b"already migrated").unwrap();
+        crate::write_sync(&dir, "pending", b"not migrated yet").unwrap();
+        // As if a migration to raw got through one file before stopping.
+        fs::write(path::content_path(&dir, &done), b"already migrated").unwrap();
+
+        assert_eq!(crate::read_sync(&dir, "done").unwrap(), b"already migrated");
+        assert_eq!(
+            crate::read_sync(&dir, "pending").unwrap(),
+            b"not migrated yet"
+        );
+
+        // And the other way around, from raw back to snappy.
+        let other = tempfile::tempdir().unwrap();
+        let snappy = crate::write_sync(other.path(), "done", b"already migrated").unwrap();
+        migrate_content_sync(&dir, ContentFormat::Raw).unwrap();
+        let cpath = path::content_path(&dir, &done);
+        fs::remove_file(&cpath).unwrap();
+        fs::copy(path::content_path(other.path(), &snappy), &cpath).unwrap();
+        assert_eq!(crate::read_sync(&dir, "done").unwrap(), b"already migrated");
+    }
+
+    #[test]
+    fn test_format_changes_apply_to_new_writes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let snappy = crate::write_sync(&dir, "snappy", b"snappy data").unwrap();
+        assert_ne!(
+            fs::read(path::content_path(&dir, &snappy)).unwrap(),
+            b"snappy data"
+        );
+
+        set_content_format_sync(&dir, ContentFormat::Raw).unwrap();
+        let raw = crate::write_sync(&dir, "raw", b"raw data").unwrap();
+        assert_eq!(
+            fs::read(path::content_path(&dir, &raw)).unwrap(),
+            b"raw data"
+        );
+    }
+
+    #[test]
+    fn test_reads_use_recorded_format() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        set_content_format_sync(&dir, ContentFormat::Raw).unwrap();
+        // Raw data that happens to look like snappy content.
+        let mut data = 5u64.to_be_bytes().to_vec();
+        data.extend_from_slice(b"\xff\x06\x00\x00sNaPpY");
+        data.extend_from_slice(b"hello");
+        crate::write_sync(&dir, "key", &data).unwrap();
+        assert_eq!(crate::read_sync(&dir, "key").unwrap(), data);
+    }
+}
@@ -10,6 +10,7 @@ use ssri::{Algorithm, Integrity};
 use crate::content::read;
 use crate::errors::{Error, Result};
 use crate::index::{self, Metadata};
+use crate::store::{CacheStore, FsStore};
 
 // ---------
 // Async API
@@ -84,7 +85,9 @@ impl Reader {
         K: AsRef<str>,
     {
         if let Some(entry) = index::find_async(cache.as_ref(), key.as_ref()).await? {
-            Reader::open_hash(cache, entry.integrity).await
+            let reader = Reader::open_hash(cache.as_ref(), entry.integrity).await?;
+            index::touch(cache.as_ref(), key.as_ref());
+            Ok(reader)
         } else {
             Err(Error::EntryNotFound(
                 cache.as_ref().to_path_buf(),
@@ -141,7 +144,9 @@ where
     K: AsRef<str>,
 {
     if let Some(entry) = index::find_async(cache.as_ref(), key.as_ref()).await? {
-        read_hash(cache, &entry.integrity).await
+        let data = read_hash(cache.as_ref(), &entry.integrity).await?;
+        index::touch(cache.as_ref(), key.as_ref());
+        Ok(data)
     } else {
         Err(Error::EntryNotFound(
             cache.as_ref().to_path_buf(),
@@ -193,7 +198,9 @@ where
     Q: AsRef<Path>,
 {
     if let Some(entry) = index::find_async(cache.as_ref(), key.as_ref()).await? {
-        copy_hash(cache, &entry.integrity, to).await
+        let size = copy_hash(cache.as_ref(), &entry.integrity, to).await?;
+        index::touch(cache.as_ref(), key.as_ref());
+        Ok(size)
     } else {
         Err(Error::EntryNotFound(
             cache.as_ref().to_path_buf(),
@@ -225,6 +232,35 @@ where
     read::copy_async(cache.as_ref(), sri, to.as_ref()).await
 }
 
+/// Links cache data by hash to a specified location, avoiding a full copy
+/// whenever possible: a reflink is tried first, then a hard link, with a
+/// regular copy as the final fallback. The linked data is always verified.
+/// Only [`ContentFormat::Raw`](crate::ContentFormat::Raw) content can be
+/// linked; snappy content is always copied.
+///
+/// Since hard links share their underlying file with the cache, the data at
+/// `to` should be treated as read-only.
+///
+/// ## Example
+/// ```no_run
+/// use async_std::prelude::*;
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let sri = cacache::write("./my-cache", "my-key", b"hello world").await?;
+///     cacache::link_hash("./my-cache", &sri, "./data.txt").await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn link_hash<P, Q>(cache: P, sri: &Integrity, to: Q) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    read::link_async(cache.as_ref(), sri, to.as_ref()).await
+}
+
 /// Gets the metadata entry for a certain key.
 ///
 /// Note that the existence of a metadata entry is not a guarantee that the
@@ -308,7 +344,9 @@ impl SyncReader {
         K: AsRef<str>,
     {
         if let Some(entry) = index::find(cache.as_ref(), key.as_ref())? {
-            SyncReader::open_hash(cache, entry.integrity)
+            let reader = SyncReader::open_hash(cache.as_ref(), entry.integrity)?;
+            index::touch(cache.as_ref(), key.as_ref());
+            Ok(reader)
         } else {
             Err(Error::EntryNotFound(
                 cache.as_ref().to_path_buf(),
@@ -361,7 +399,9 @@ where
     K: AsRef<str>,
 {
     if let Some(entry) = index::find(cache.as_ref(), key.as_ref())? {
-        read_hash_sync(cache, &entry.integrity)
+        let data = read_hash_sync(cache.as_ref(), &entry.integrity)?;
+        index::touch(cache.as_ref(), key.as_ref());
+        Ok(data)
     } else {
         Err(Error::EntryNotFound(
             cache.as_ref().to_path_buf(),
@@ -387,7 +427,7 @@ pub fn read_hash_sync<P>(cache: P, sri: &Integrity) -> Result<Vec<u8>>
 where
     P: AsRef<Path>,
 {
-    Ok(read::Reader::consume(cache.as_ref(), sri)?)
+    FsStore::new(cache).read_hash(sri)
 }
 
 /// Copies a cache entry by key to a specified location. Returns the number of
@@ -409,7 +449,9 @@ where
     Q: AsRef<Path>,
 {
     if let Some(entry) = index::find(cache.as_ref(), key.as_ref())? {
-        copy_hash_sync(cache, &entry.integrity, to)
+        let size = copy_hash_sync(cache.as_ref(), &entry.integrity, to)?;
+        index::touch(cache.as_ref(), key.as_ref());
+        Ok(size)
     } else {
         Err(Error::EntryNotFound(
             cache.as_ref().to_path_buf(),
@@ -439,6 +481,33 @@ where
     read::copy(cache.as_ref(), sri, to.as_ref())
 }
 
+/// Links cache data by hash to a specified location synchronously, avoiding a
+/// full copy whenever possible: a reflink is tried first, then a hard link,
+/// with a regular copy as the final fallback. The linked data is always
+/// verified. Only [`ContentFormat::Raw`](crate::ContentFormat::Raw) content
+/// can be linked; snappy content is always copied.
+///
+/// Since hard links share their underlying file with the cache, the data at
+/// `to` should be treated as read-only.
+///
+/// ## Example
+/// ```no_run
+/// use std::io::Read;
+///
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     cacache::link_hash_sync("./my-cache", &sri, "./my-hello.txt")?;
+///     Ok(())
+/// }
+/// ```
+pub fn link_hash_sync<P, Q>(cache: P, sri: &Integrity, to: Q) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    read::link(cache.as_ref(), sri, to.as_ref())
+}
+
 /// Gets metadata for a certain key.
 ///
 /// Note that the existence of a metadata entry is not a guarantee that the
@@ -449,12 +518,12 @@ where
     P: AsRef<Path>,
     K: AsRef<str>,
 {
-    Ok(index::find(cache.as_ref(), key.as_ref())?)
+    FsStore::new(cache).find(key.as_ref())
 }
 
 /// Returns true if the given hash exists in the cache.
 pub fn exists_sync<P: AsRef<Path>>(cache: P, sri: &Integrity) -> bool {
-    read::has_content(cache.as_ref(), &sri).is_some()
+    FsStore::new(cache).has_hash(sri)
 }
 
 #[cfg(test)]
@@ -606,4 +675,27 @@ mod tests {
         let data = fs::read(&dest).unwrap();
         assert_eq!(data, b"hello world");
     }
+    #[async_attributes::test]
+    async fn test_link_hash() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let dest = dir.join("data");
+        let sri = crate::write(&dir, "my-key", b"hello world").await.unwrap();
+
+        crate::link_hash(&dir, &sri, &dest).await.unwrap();
+        let data = afs::read(&dest).await.unwrap();
+        assert_eq!(data, b"hello world");
+    }
+
+    #[test]
+    fn test_link_hash_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let dest = dir.join("data");
+        let sri = crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+
+        crate::link_hash_sync(&dir, &sri, &dest).unwrap();
+        let data = fs::read(&dest).unwrap();
+        assert_eq!(data, b"hello world");
+    }
 }
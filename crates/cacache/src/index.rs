@@ -1,5 +1,5 @@
-use std::collections::HashSet;
-use std::fs::{self, OpenOptions};
+use std::collections::HashMap;
+use std::fs;
 use std::hash::{Hash, Hasher};
 use std::io::{ErrorKind, Write};
 use std::path::{Path, PathBuf};
@@ -9,22 +9,29 @@ use async_std::fs as afs;
 use async_std::io::BufReader;
 use digest::Digest;
 use either::{Left, Right};
-use futures::io::{AsyncBufReadExt, AsyncWriteExt};
+use filetime::FileTime;
+use futures::io::AsyncBufReadExt;
 use futures::stream::StreamExt;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use sha1::Sha1;
 use sha2::Sha256;
 use ssri::Integrity;
+use tempfile::NamedTempFile;
 use walkdir::WalkDir;
 
 use crate::errors::{Internal, InternalResult, Result};
+use crate::format::{index_format_sync, IndexFormat};
+use crate::lock;
 use crate::put::WriteOpts;
 
 const INDEX_VERSION: &str = "5";
 
+// Buckets larger than this get compacted the next time they're written to.
+const COMPACTION_THRESHOLD: u64 = 64 * 1024;
+
 /// Represents a cache index entry, which points to content.
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct Metadata {
     /// Key this entry is stored under.
     pub key: String,
@@ -36,6 +43,9 @@ pub struct Metadata {
     pub size: usize,
     /// Arbitrary JSON  associated with this entry.
     pub metadata: Value,
+    /// Additional content this entry depends on. Referenced content is kept
+    /// alive by the entry, just like the content at `integrity` is.
+    pub references: Vec<Integrity>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -45,6 +55,8 @@ struct SerializableMetadata {
     time: u128,
     size: usize,
     metadata: Value,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    references: Vec<String>,
 }
 
 impl PartialEq for SerializableMetadata {
@@ -62,7 +74,16 @@ impl Hash for SerializableMetadata {
 }
 
 pub fn insert(cache: &Path, key: &str, opts: WriteOpts) -> Result<Integrity> {
-    let bucket = bucket_path(&cache, &key);
+    insert_with_format(cache, key, opts, index_format_sync(cache)?)
+}
+
+fn insert_with_format(
+    cache: &Path,
+    key: &str,
+    opts: WriteOpts,
+    format: IndexFormat,
+) -> Result<Integrity> {
+    let bucket = bucket_path_for(&cache, &key, format);
     fs::create_dir_all(bucket.parent().unwrap()).with_context(|| {
         format!(
             "Failed to create index bucket directory: {:?}",
@@ -75,20 +96,29 @@ pub fn insert(cache: &Path, key: &str, opts: WriteOpts) -> Result<Integrity> {
         time: opts.time.unwrap_or_else(now),
         size: opts.size.unwrap_or(0),
         metadata: opts.metadata.unwrap_or(json!(null)),
+        references: opts
+            .references
+            .iter()
+            .flatten()
+            .map(|sri| sri.to_string())
+            .collect(),
     })
     .with_context(|| format!("Failed to serialize entry with key `{}`", key))?;
 
-    let mut buck = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&bucket)
-        .with_context(|| format!("Failed to create or open index bucket at {:?}", bucket))?;
+    // Garbage collection can't run while we're holding this, and nobody
+    // else can append to (or compact) the bucket until we're done with it.
+    let _lock = lock::lock_shared_sync(cache)?;
+    let mut buck = lock::lock_bucket(&bucket)?;
 
-    let out = format!("\n{}\t{}", hash_entry(&stringified), stringified);
+    let out = format!("\n{}\t{}", hash_entry(format, &stringified), stringified);
     buck.write_all(out.as_bytes())
         .with_context(|| format!("Failed to write to index bucket at {:?}", bucket))?;
     buck.flush()
         .with_context(|| format!("Failed to flush bucket at {:?}", bucket))?;
+    if needs_compaction(buck.metadata().ok().map(|m| m.len())) {
+        // Best-effort: a failed compaction leaves a perfectly valid bucket.
+        let _ = compact_bucket(&bucket, &mut |_: &Metadata| true);
+    }
     Ok(opts
         .sri
         .or_else(|| "sha1-deadbeef".parse::<Integrity>().ok())
@@ -96,133 +126,78 @@ pub fn insert(cache: &Path, key: &str, opts: WriteOpts) -> Result<Integrity> {
 }
 
 pub async fn insert_async<'a>(cache: &'a Path, key: &'a str, opts: WriteOpts) -> Result<Integrity> {
-    let bucket = bucket_path(&cache, &key);
-    afs::create_dir_all(bucket.parent().unwrap())
-        .await
-        .with_context(|| {
-            format!(
-                "Failed to create index bucket directory: {:?}",
-                bucket.parent().unwrap()
-            )
-        })?;
-    let stringified = serde_json::to_string(&SerializableMetadata {
-        key: key.to_owned(),
-        integrity: opts.sri.clone().map(|x| x.to_string()),
-        time: opts.time.unwrap_or_else(now),
-        size: opts.size.unwrap_or(0),
-        metadata: opts.metadata.unwrap_or(json!(null)),
-    })
-    .with_context(|| format!("Failed to serialize entry with key `{}`", key))?;
-
-    let mut buck = async_std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&bucket)
-        .await
-        .with_context(|| format!("Failed to create or open index bucket at {:?}", bucket))?;
-
-    let out = format!("\n{}\t{}", hash_entry(&stringified), stringified);
-    buck.write_all(out.as_bytes())
-        .await
-        .with_context(|| format!("Failed to write to index bucket at {:?}", bucket))?;
-    buck.flush()
-        .await
-        .with_context(|| format!("Failed to flush bucket at {:?}", bucket))?;
-    Ok(opts
-        .sri
-        .or_else(|| "sha1-deadbeef".parse::<Integrity>().ok())
-        .unwrap())
+    let cache = cache.to_owned();
+    let key = key.to_owned();
+    smol::unblock!(insert(&cache, &key, opts))
 }
 
 pub fn find(cache: &Path, key: &str) -> Result<Option<Metadata>> {
-    let bucket = bucket_path(cache, &key);
-    Ok(bucket_entries(&bucket)
-        .with_context(|| format!("Failed to read index bucket entries from {:?}", bucket))?
-        .into_iter()
-        .fold(None, |acc, entry| {
-            if entry.key == key {
-                if let Some(integrity) = entry.integrity {
-                    let integrity: Integrity = match integrity.parse() {
-                        Ok(sri) => sri,
-                        _ => return acc,
-                    };
-                    Some(Metadata {
-                        key: entry.key,
-                        integrity,
-                        size: entry.size,
-                        time: entry.time,
-                        metadata: entry.metadata,
-                    })
-                } else {
-                    None
-                }
-            } else {
-                acc
-            }
-        }))
+    for bucket in bucket_paths(cache, key)? {
+        let entries = bucket_entries(&bucket)
+            .with_context(|| format!("Failed to read index bucket entries from {:?}", bucket))?;
+        if let Some(found) = latest_entry(entries, key) {
+            return Ok(found);
+        }
+    }
+    Ok(None)
 }
 
 pub async fn find_async(cache: &Path, key: &str) -> Result<Option<Metadata>> {
-    let bucket = bucket_path(cache, &key);
-    Ok(bucket_entries_async(&bucket)
-        .await
-        .with_context(|| format!("Failed to read index bucket entries from {:?}", bucket))?
+    for bucket in bucket_paths(cache, key)? {
+        let entries = bucket_entries_async(&bucket)
+            .await
+            .with_context(|| format!("Failed to read index bucket entries from {:?}", bucket))?;
+        if let Some(found) = latest_entry(entries, key) {
+            return Ok(found);
+        }
+    }
+    Ok(None)
+}
+
+// Returns `None` if the bucket has nothing at all for `key`, and `Some(None)`
+// if the key was removed.
+fn latest_entry(entries: Vec<SerializableMetadata>, key: &str) -> Option<Option<Metadata>> {
+    entries
         .into_iter()
+        .filter(|entry| entry.key == key)
         .fold(None, |acc, entry| {
-            if entry.key == key {
-                if let Some(integrity) = entry.integrity {
-                    let integrity: Integrity = match integrity.parse() {
-                        Ok(sri) => sri,
-                        _ => return acc,
-                    };
-                    Some(Metadata {
-                        key: entry.key,
-                        integrity,
-                        size: entry.size,
-                        time: entry.time,
-                        metadata: entry.metadata,
-                    })
-                } else {
-                    None
-                }
+            if let Some(integrity) = entry.integrity {
+                let integrity: Integrity = match integrity.parse() {
+                    Ok(sri) => sri,
+                    _ => return acc,
+                };
+                Some(Some(Metadata {
+                    key: entry.key,
+                    integrity,
+                    size: entry.size,
+                    time: entry.time,
+                    metadata: entry.metadata,
+                    references: parse_references(&entry.references),
+                }))
             } else {
-                acc
+                Some(None)
             }
-        }))
+        })
 }
 
 pub fn delete(cache: &Path, key: &str) -> Result<()> {
-    insert(
-        cache,
-        key,
-        WriteOpts {
-            algorithm: None,
-            size: None,
-            sri: None,
-            time: None,
-            metadata: None,
-        },
-    )
-    .map(|_| ())
+    let format = index_format_sync(cache)?;
+    insert_with_format(cache, key, WriteOpts::new(), format)?;
+    // The key might also have entries in a bucket written in the other index
+    // format, which would otherwise resurface.
+    let other = other_format(format);
+    if bucket_path_for(cache, key, other).exists() {
+        insert_with_format(cache, key, WriteOpts::new(), other)?;
+    }
+    Ok(())
 }
 
 pub async fn delete_async(cache: &Path, key: &str) -> Result<()> {
-    insert(
-        cache,
-        key,
-        WriteOpts {
-            algorithm: None,
-            size: None,
-            sri: None,
-            time: None,
-            metadata: None,
-        },
-    )
-    .map(|_| ())
+    delete(cache, key)
 }
 
 pub fn ls(cache: &Path) -> impl Iterator<Item = Result<Metadata>> {
-    WalkDir::new(cache.join(format!("index-v{}", INDEX_VERSION)))
+    WalkDir::new(index_dir(cache))
         .into_iter()
         .map(|bucket| {
             let bucket = bucket.to_internal()?;
@@ -231,23 +206,16 @@ pub fn ls(cache: &Path) -> impl Iterator<Item = Result<Metadata>> {
                 return Ok(Vec::new());
             }
 
+            // Later lines win, so a key that was removed or rewritten only
+            // shows up with its most recent entry, if any. Entries with an
+            // integrity we can't make sense of are skipped, like `find`
+            // does.
             Ok(bucket_entries(bucket.path())?
                 .into_iter()
-                .collect::<HashSet<SerializableMetadata>>()
-                .into_iter()
-                .filter_map(|se| {
-                    if let Some(i) = se.integrity {
-                        Some(Metadata {
-                            key: se.key,
-                            integrity: i.parse().unwrap(),
-                            time: se.time,
-                            size: se.size,
-                            metadata: se.metadata,
-                        })
-                    } else {
-                        None
-                    }
-                })
+                .map(|se| (se.key.clone(), se))
+                .collect::<HashMap<String, SerializableMetadata>>()
+                .values()
+                .filter_map(to_metadata)
                 .collect())
         })
         .flat_map(|res| match res {
@@ -256,28 +224,214 @@ pub fn ls(cache: &Path) -> impl Iterator<Item = Result<Metadata>> {
         })
 }
 
-fn bucket_path(cache: &Path, key: &str) -> PathBuf {
-    let hashed = hash_key(&key);
-    cache
-        .join(format!("index-v{}", INDEX_VERSION))
+/// Rewrites every index bucket so it only holds the latest entry for each
+/// key, dropping removed keys and any entries `filter` rejects. Returns the
+/// number of bucket entries that were dropped.
+pub fn compact<F>(cache: &Path, mut filter: F) -> Result<usize>
+where
+    F: FnMut(&Metadata) -> bool,
+{
+    let index = index_dir(cache);
+    if !index.exists() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for bucket in WalkDir::new(index) {
+        let bucket = bucket.to_internal()?;
+        if bucket.file_type().is_file() {
+            removed += compact_bucket(bucket.path(), &mut filter)?;
+        }
+    }
+    Ok(removed)
+}
+
+fn needs_compaction(bucket_size: Option<u64>) -> bool {
+    bucket_size
+        .map(|size| size > COMPACTION_THRESHOLD)
+        .unwrap_or(false)
+}
+
+fn compact_bucket<F>(bucket: &Path, filter: &mut F) -> InternalResult<usize>
+where
+    F: FnMut(&Metadata) -> bool,
+{
+    let entries = bucket_entries(bucket)?;
+    let total = entries.len();
+
+    // Keep the latest entry for each key, in the order keys first appeared.
+    let mut order = Vec::new();
+    let mut latest = HashMap::new();
+    for entry in entries {
+        if !latest.contains_key(&entry.key) {
+            order.push(entry.key.clone());
+        }
+        latest.insert(entry.key.clone(), entry);
+    }
+    let kept = order
+        .into_iter()
+        .filter_map(|key| latest.remove(&key))
+        .filter(|se| match to_metadata(se) {
+            Some(entry) => filter(&entry),
+            None => false,
+        })
+        .collect::<Vec<_>>();
+
+    if kept.len() == total {
+        return Ok(0);
+    }
+    if kept.is_empty() {
+        if let Err(err) = fs::remove_file(bucket) {
+            if err.kind() != ErrorKind::NotFound {
+                return Err(err)
+                    .with_context(|| format!("Failed to remove index bucket at {:?}", bucket));
+            }
+        }
+        return Ok(total);
+    }
+
+    // Write the new bucket next to the old one, then swap it in atomically.
+    let format = bucket_format(bucket, &kept[0].key);
+    let mut tmp = NamedTempFile::new_in(bucket.parent().unwrap())
+        .with_context(|| format!("Failed to create temporary bucket for {:?}", bucket))?;
+    for se in kept.iter() {
+        let stringified = serde_json::to_string(se)
+            .with_context(|| format!("Failed to serialize entry with key `{}`", se.key))?;
+        write!(
+            tmp,
+            "\n{}\t{}",
+            hash_entry(format, &stringified),
+            stringified
+        )
+        .with_context(|| format!("Failed to write compacted bucket for {:?}", bucket))?;
+    }
+    let accessed = fs::metadata(bucket).map(|meta| FileTime::from_last_modification_time(&meta));
+    tmp.persist(bucket)
+        .with_context(|| format!("Failed to replace index bucket at {:?}", bucket))?;
+    // Compacting a bucket doesn't count as using it.
+    if let Ok(accessed) = accessed {
+        let _ = filetime::set_file_mtime(bucket, accessed);
+    }
+    Ok(total - kept.len())
+}
+
+fn to_metadata(se: &SerializableMetadata) -> Option<Metadata> {
+    let integrity = se.integrity.as_ref()?.parse().ok()?;
+    Some(Metadata {
+        key: se.key.clone(),
+        integrity,
+        time: se.time,
+        size: se.size,
+        metadata: se.metadata.clone(),
+        references: parse_references(&se.references),
+    })
+}
+
+fn parse_references(references: &[String]) -> Vec<Integrity> {
+    references
+        .iter()
+        .filter_map(|sri| sri.parse().ok())
+        .collect()
+}
+
+/// Returns when an entry was last used, in unix milliseconds. Writes and
+/// lookups both bump the modification time of the entry's bucket, so that's
+/// what gets used, falling back to the time the entry was written.
+pub fn last_access(cache: &Path, entry: &Metadata) -> u128 {
+    [IndexFormat::Cacache, IndexFormat::Npm]
+        .iter()
+        .filter_map(|format| {
+            fs::metadata(bucket_path_for(cache, &entry.key, *format))
+                .and_then(|meta| meta.modified())
+                .ok()
+        })
+        .filter_map(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|time| time.as_millis())
+        .max()
+        .unwrap_or(entry.time)
+}
+
+/// Records that `key`'s entry was just used, for LRU eviction.
+///
+/// Best-effort: failing to record an access only makes eviction a little
+/// less accurate.
+pub fn touch(cache: &Path, key: &str) {
+    if let Ok(buckets) = bucket_paths(cache, key) {
+        for bucket in buckets.iter().filter(|bucket| bucket.exists()) {
+            let _ = filetime::set_file_mtime(bucket, FileTime::now());
+        }
+    }
+}
+
+pub fn index_dir(cache: &Path) -> PathBuf {
+    cache.join(format!("index-v{}", INDEX_VERSION))
+}
+
+#[cfg(test)]
+pub fn bucket_path(cache: &Path, key: &str) -> PathBuf {
+    bucket_path_for(cache, key, index_format_sync(cache).unwrap_or_default())
+}
+
+// Buckets to look for `key` in, starting with the one new entries go to.
+fn bucket_paths(cache: &Path, key: &str) -> Result<Vec<PathBuf>> {
+    let format = index_format_sync(cache)?;
+    Ok(vec![
+        bucket_path_for(cache, key, format),
+        bucket_path_for(cache, key, other_format(format)),
+    ])
+}
+
+fn bucket_path_for(cache: &Path, key: &str, format: IndexFormat) -> PathBuf {
+    let hashed = hash_key(format, &key);
+    index_dir(cache)
         .join(&hashed[0..2])
         .join(&hashed[2..4])
         .join(&hashed[4..])
 }
 
-fn hash_key(key: &str) -> String {
-    let mut hasher = Sha1::new();
-    hasher.input(&key);
-    hex::encode(hasher.result())
+fn other_format(format: IndexFormat) -> IndexFormat {
+    match format {
+        IndexFormat::Cacache => IndexFormat::Npm,
+        IndexFormat::Npm => IndexFormat::Cacache,
+    }
+}
+
+// Works out which format a bucket was written in from its path.
+fn bucket_format(bucket: &Path, key: &str) -> IndexFormat {
+    let npm_hash = hash_key(IndexFormat::Npm, key);
+    if bucket.file_name() == Some(std::ffi::OsStr::new(&npm_hash[4..])) {
+        IndexFormat::Npm
+    } else {
+        IndexFormat::Cacache
+    }
+}
+
+// npm's cacache hashes keys with sha256 and entries with sha1. We do the
+// opposite.
+fn hash_key(format: IndexFormat, key: &str) -> String {
+    match format {
+        IndexFormat::Cacache => hex::encode(Sha1::digest(key.as_bytes())),
+        IndexFormat::Npm => hex::encode(Sha256::digest(key.as_bytes())),
+    }
 }
 
-fn hash_entry(key: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.input(&key);
-    hex::encode(hasher.result())
+fn hash_entry(format: IndexFormat, entry: &str) -> String {
+    match format {
+        IndexFormat::Cacache => hex::encode(Sha256::digest(entry.as_bytes())),
+        IndexFormat::Npm => hex::encode(Sha1::digest(entry.as_bytes())),
+    }
+}
+
+// Lines can be in either format, so go by the length of their hash.
+fn entry_hash_matches(hash: &str, entry: &str) -> bool {
+    let format = if hash.len() == 40 {
+        IndexFormat::Npm
+    } else {
+        IndexFormat::Cacache
+    };
+    hash_entry(format, entry) == hash
 }
 
-fn now() -> u128 {
+pub(crate) fn now() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
@@ -293,7 +447,7 @@ fn bucket_entries(bucket: &Path) -> InternalResult<Vec<SerializableMetadata>> {
                 .filter_map(std::result::Result::ok)
                 .filter_map(|entry| {
                     let entry_str = match entry.split('\t').collect::<Vec<&str>>()[..] {
-                        [hash, entry_str] if hash_entry(entry_str) == hash => entry_str,
+                        [hash, entry_str] if entry_hash_matches(hash, entry_str) => entry_str,
                         // Something's wrong with the entry. Abort.
                         _ => return None,
                     };
@@ -326,7 +480,7 @@ async fn bucket_entries_async(bucket: &Path) -> InternalResult<Vec<SerializableM
     while let Some(line) = lines.next().await {
         if let Ok(entry) = line {
             let entry_str = match entry.split('\t').collect::<Vec<&str>>()[..] {
-                [hash, entry_str] if hash_entry(entry_str) == hash => entry_str,
+                [hash, entry_str] if entry_hash_matches(hash, entry_str) => entry_str,
                 // Something's wrong with the entry. Abort.
                 _ => continue,
             };
@@ -389,7 +543,8 @@ mod tests {
                 integrity: sri,
                 time,
                 size: 0,
-                metadata: json!(null)
+                metadata: json!(null),
+                references: Vec::new(),
             }
         );
     }
@@ -401,6 +556,53 @@ mod tests {
         assert_eq!(find(&dir, "hello").unwrap(), None);
     }
 
+    #[test]
+    fn compact_keeps_latest_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        for time in 0..10 {
+            let opts = WriteOpts::new().integrity(sri.clone()).time(time);
+            insert(&dir, "hello", opts).unwrap();
+        }
+        let removed = compact(&dir, |_| true).unwrap();
+        assert_eq!(removed, 9);
+        let entries = bucket_entries(&bucket_path(&dir, "hello")).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(find(&dir, "hello").unwrap().unwrap().time, 9);
+    }
+
+    #[test]
+    fn compact_drops_deleted_and_filtered() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        insert(&dir, "hello", WriteOpts::new().integrity(sri.clone())).unwrap();
+        delete(&dir, "hello").unwrap();
+        insert(&dir, "world", WriteOpts::new().integrity(sri.clone())).unwrap();
+        insert(&dir, "keep", WriteOpts::new().integrity(sri)).unwrap();
+
+        compact(&dir, |entry| entry.key != "world").unwrap();
+
+        assert!(!bucket_path(&dir, "hello").exists());
+        assert_eq!(find(&dir, "world").unwrap(), None);
+        assert!(find(&dir, "keep").unwrap().is_some());
+    }
+
+    #[test]
+    fn insert_compacts_large_buckets() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        for time in 0..1000 {
+            let opts = WriteOpts::new().integrity(sri.clone()).time(time);
+            insert(&dir, "hello", opts).unwrap();
+        }
+        let size = fs::metadata(bucket_path(&dir, "hello")).unwrap().len();
+        assert!(size <= COMPACTION_THRESHOLD);
+        assert_eq!(find(&dir, "hello").unwrap().unwrap().time, 999);
+    }
+
     #[test]
     fn delete_basic() {
         let tmp = tempfile::tempdir().unwrap();
@@ -443,7 +645,8 @@ mod tests {
                 integrity: sri,
                 time,
                 size: 0,
-                metadata: json!(null)
+                metadata: json!(null),
+                references: Vec::new(),
             }
         );
     }
@@ -466,7 +669,8 @@ mod tests {
                 integrity: sri,
                 time,
                 size: 0,
-                metadata: json!(null)
+                metadata: json!(null),
+                references: Vec::new(),
             }
         );
     }
@@ -489,4 +693,26 @@ mod tests {
         entries.sort();
         assert_eq!(entries, vec![String::from("hello"), String::from("world")])
     }
+
+    #[test]
+    fn ls_skips_bad_integrity() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        insert(&dir, "hello", WriteOpts::new().integrity(sri)).unwrap();
+        let bad = r#"{"key":"world","integrity":"not-an-integrity","time":1234567,"size":0,"metadata":null}"#;
+        let bucket = bucket_path(&dir, "world");
+        fs::create_dir_all(bucket.parent().unwrap()).unwrap();
+        fs::write(
+            bucket,
+            format!("\n{}\t{}", hash_entry(IndexFormat::Cacache, bad), bad),
+        )
+        .unwrap();
+
+        let entries = ls(&dir)
+            .map(|x| Ok(x?.key))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(entries, vec![String::from("hello")]);
+    }
 }
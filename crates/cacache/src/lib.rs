@@ -130,15 +130,29 @@ mod content;
 mod errors;
 mod index;
 
+mod compact;
+mod evict;
+mod format;
 mod get;
+mod lock;
 mod ls;
 mod put;
 mod rm;
+mod store;
+mod upgrade;
+mod verify;
 
 pub use errors::{Error, Result};
 pub use index::Metadata;
 
+pub use compact::*;
+pub use evict::*;
+pub use format::*;
 pub use get::*;
+pub use lock::*;
 pub use ls::*;
 pub use put::*;
 pub use rm::*;
+pub use store::*;
+pub use upgrade::*;
+pub use verify::*;
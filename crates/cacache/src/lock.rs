@@ -0,0 +1,181 @@
+//! Functions for coordinating cache access between processes.
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use fs2::FileExt;
+use same_file::Handle;
+
+use crate::errors::{Internal, InternalResult, Result};
+
+const LOCK_FILE: &str = "cache.lock";
+
+/// An advisory lock on a cache, released when dropped.
+///
+/// While any process holds a shared lock, nothing that removes data from the
+/// cache ([`verify`], [`evict`], [`compact_index`], [`clear`],
+/// [`remove_hash`] and content migrations) can remove anything, and while
+/// one of those does, nobody can get a shared lock. cacache takes these
+/// locks itself whenever it writes to a cache, so you only need
+/// [`lock_shared`] if you write content with [`write_hash`] and only
+/// reference it from an index entry later on.
+///
+/// Locks belong to the lock file they were taken through rather than to the
+/// process, so they don't nest: calling any of the functions above while
+/// the same thread or task holds a `CacheLock` on that cache waits forever.
+/// Drop the lock first.
+#[derive(Debug)]
+pub struct CacheLock {
+    _file: File,
+}
+
+/// Takes a shared lock on the cache, waiting for any garbage collection in
+/// progress to finish first. Content written while the lock is held can't be
+/// garbage collected until the lock is dropped.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let lock = cacache::lock_shared("./my-cache").await?;
+///     let sri = cacache::write_hash("./my-cache", b"hello").await?;
+///     cacache::WriteOpts::new()
+///         .references(vec![sri])
+///         .open("./my-cache", "my-key")
+///         .await?
+///         .commit()
+///         .await?;
+///     drop(lock);
+///     Ok(())
+/// }
+/// ```
+pub async fn lock_shared<P: AsRef<Path>>(cache: P) -> Result<CacheLock> {
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(lock_shared_sync(cache))
+}
+
+/// Takes a shared lock on the cache synchronously. See [`lock_shared`] for
+/// details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let lock = cacache::lock_shared_sync("./my-cache")?;
+///     let sri = cacache::write_hash_sync("./my-cache", b"hello")?;
+///     cacache::WriteOpts::new()
+///         .references(vec![sri])
+///         .open_sync("./my-cache", "my-key")?
+///         .commit()?;
+///     drop(lock);
+///     Ok(())
+/// }
+/// ```
+pub fn lock_shared_sync<P: AsRef<Path>>(cache: P) -> Result<CacheLock> {
+    let file = open_lock_file(cache.as_ref())?;
+    file.lock_shared()
+        .with_context(|| format!("Failed to lock cache at {:?}", cache.as_ref()))?;
+    Ok(CacheLock { _file: file })
+}
+
+/// Takes the cache's exclusive lock, for anything that removes data. This
+/// opens the lock file anew, so it waits forever if the caller already holds
+/// a lock on the same cache, shared or not.
+pub(crate) fn lock_exclusive(cache: &Path) -> Result<CacheLock> {
+    let file = open_lock_file(cache)?;
+    file.lock_exclusive()
+        .with_context(|| format!("Failed to lock cache at {:?}", cache))?;
+    Ok(CacheLock { _file: file })
+}
+
+/// Opens an index bucket for appending, holding an exclusive lock on it
+/// until the returned file is closed.
+pub(crate) fn lock_bucket(bucket: &Path) -> InternalResult<File> {
+    loop {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&bucket)
+            .with_context(|| format!("Failed to create or open index bucket at {:?}", bucket))?;
+        file.lock_exclusive()
+            .with_context(|| format!("Failed to lock index bucket at {:?}", bucket))?;
+        // Compaction may have replaced or removed the bucket while we were
+        // waiting, in which case we're holding a file nobody will read.
+        let ours = Handle::from_file(
+            file.try_clone()
+                .with_context(|| format!("Failed to inspect index bucket at {:?}", bucket))?,
+        )
+        .with_context(|| format!("Failed to inspect index bucket at {:?}", bucket))?;
+        match Handle::from_path(&bucket) {
+            Ok(current) if current == ours => return Ok(file),
+            _ => continue,
+        }
+    }
+}
+
+pub(crate) fn lock_file(cache: &Path) -> PathBuf {
+    cache.join(LOCK_FILE)
+}
+
+fn open_lock_file(cache: &Path) -> InternalResult<File> {
+    fs::create_dir_all(cache)
+        .with_context(|| format!("Failed to create cache directory at {:?}", cache))?;
+    let file = lock_file(cache);
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(&file)
+        .with_context(|| format!("Failed to open cache lock file at {:?}", file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_locks_coexist() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let first = lock_shared_sync(&dir).unwrap();
+        let second = lock_shared_sync(&dir).unwrap();
+        // Both held at once, so nobody can take the lock exclusively.
+        let probe = open_lock_file(&dir).unwrap();
+        assert!(probe.try_lock_exclusive().is_err());
+        drop(first);
+        drop(second);
+        assert!(probe.try_lock_exclusive().is_ok());
+    }
+
+    #[test]
+    fn test_exclusive_blocks_shared() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let lock = lock_exclusive(&dir).unwrap();
+        let probe = open_lock_file(&dir).unwrap();
+        assert!(probe.try_lock_shared().is_err());
+        drop(lock);
+        assert!(probe.try_lock_shared().is_ok());
+    }
+
+    #[test]
+    fn test_lock_bucket_follows_replaced_bucket() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bucket = tmp.path().join("bucket");
+        let stale = lock_bucket(&bucket).unwrap();
+        fs::write(tmp.path().join("new"), b"").unwrap();
+        fs::rename(tmp.path().join("new"), &bucket).unwrap();
+        drop(stale);
+        let file = lock_bucket(&bucket).unwrap();
+        assert!(Handle::from_file(file).unwrap() == Handle::from_path(&bucket).unwrap());
+    }
+
+    #[test]
+    fn test_clear_keeps_lock_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "my-key", b"hello").unwrap();
+        crate::clear_sync(&dir).unwrap();
+        assert!(lock_file(&dir).exists());
+    }
+}
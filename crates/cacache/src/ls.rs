@@ -1,5 +1,9 @@
 //! Functions for iterating over the cache.
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::stream::Stream;
+use serde_json::Value;
 
 use crate::errors::Result;
 use crate::index;
@@ -9,10 +13,144 @@ pub fn list_sync<P: AsRef<Path>>(cache: P) -> impl Iterator<Item = Result<index:
     index::ls(cache.as_ref())
 }
 
+/// Returns a stream of all cache index entries. The index is read on a
+/// background thread, so listing even a very large cache won't block the
+/// executor.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+/// use futures::stream::StreamExt;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let mut entries = cacache::list("./my-cache");
+///     while let Some(entry) = entries.next().await {
+///         println!("{}", entry?.key);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn list<P: AsRef<Path>>(cache: P) -> impl Stream<Item = Result<index::Metadata>> {
+    ListOpts::new().list(cache)
+}
+
+/// Builder for listing only the cache index entries that match some
+/// criteria. Entries are filtered as the index is read, so only matching
+/// entries are ever collected.
+///
+/// ## Example
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use async_attributes;
+/// use futures::stream::StreamExt;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let week = Duration::from_secs(7 * 24 * 60 * 60);
+///     let mut stale = cacache::ListOpts::new()
+///         .prefix("my-app::")
+///         .older_than(week)
+///         .list("./my-cache");
+///     while let Some(entry) = stale.next().await {
+///         cacache::remove("./my-cache", &entry?.key).await?;
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ListOpts {
+    prefix: Option<String>,
+    older_than: Option<Duration>,
+    newer_than: Option<Duration>,
+    metadata: Vec<(String, Value)>,
+}
+
+impl ListOpts {
+    /// Creates a set of options that matches every entry.
+    pub fn new() -> ListOpts {
+        Default::default()
+    }
+
+    /// Only lists entries whose keys start with `prefix`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Only lists entries written more than `age` ago.
+    pub fn older_than(mut self, age: Duration) -> Self {
+        self.older_than = Some(age);
+        self
+    }
+
+    /// Only lists entries written less than `age` ago.
+    pub fn newer_than(mut self, age: Duration) -> Self {
+        self.newer_than = Some(age);
+        self
+    }
+
+    /// Only lists entries whose metadata has `value` at `pointer`, a JSON
+    /// pointer such as `/url` or `/headers/etag`. Can be given more than
+    /// once, in which case all of them have to match.
+    pub fn metadata(mut self, pointer: impl Into<String>, value: Value) -> Self {
+        self.metadata.push((pointer.into(), value));
+        self
+    }
+
+    /// Returns a stream of the matching cache index entries. See [`list`].
+    pub fn list<P: AsRef<Path>>(self, cache: P) -> impl Stream<Item = Result<index::Metadata>> {
+        smol::Unblock::new(self.list_sync(cache))
+    }
+
+    /// Returns a synchronous iterator over the matching cache index entries.
+    pub fn list_sync<P: AsRef<Path>>(
+        self,
+        cache: P,
+    ) -> impl Iterator<Item = Result<index::Metadata>> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        index::ls(cache.as_ref()).filter(move |entry| match entry {
+            Ok(entry) => self.matches(entry, now),
+            // Errors always come through.
+            Err(_) => true,
+        })
+    }
+
+    fn matches(&self, entry: &index::Metadata, now: u128) -> bool {
+        let age = now.saturating_sub(entry.time);
+        self.prefix
+            .as_ref()
+            .map(|prefix| entry.key.starts_with(prefix))
+            .unwrap_or(true)
+            && self
+                .older_than
+                .map(|min| age > min.as_millis())
+                .unwrap_or(true)
+            && self
+                .newer_than
+                .map(|max| age < max.as_millis())
+                .unwrap_or(true)
+            && self
+                .metadata
+                .iter()
+                .all(|(pointer, value)| entry.metadata.pointer(pointer) == Some(value))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use async_std::task;
+    use futures::stream::StreamExt;
+    use serde_json::json;
+
+    use crate::WriteOpts;
+
     #[test]
     fn test_list_sync() {
         // check that the public interface to list elements can actually use the
@@ -25,4 +163,73 @@ mod tests {
             .collect::<Result<Vec<_>>>()
             .is_err())
     }
+
+    #[test]
+    fn test_list_stream() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "one", b"1").unwrap();
+        crate::write_sync(&dir, "two", b"2").unwrap();
+        let mut keys = task::block_on(async {
+            list(&dir)
+                .map(|entry| entry.unwrap().key)
+                .collect::<Vec<_>>()
+                .await
+        });
+        keys.sort();
+        assert_eq!(keys, vec!["one", "two"]);
+    }
+
+    #[test]
+    fn test_list_opts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let write = |key: &str, time: u128, metadata: Value| {
+            let mut fd = WriteOpts::new()
+                .time(time)
+                .metadata(metadata)
+                .open_sync(&dir, key)
+                .unwrap();
+            std::io::Write::write_all(&mut fd, b"data").unwrap();
+            fd.commit().unwrap();
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let day = Duration::from_secs(24 * 60 * 60);
+        write(
+            "pkg::old",
+            now - 10 * day.as_millis(),
+            json!({"kind": "tarball"}),
+        );
+        write("pkg::new", now, json!({"kind": "packument"}));
+        write("other", now - 10 * day.as_millis(), json!(null));
+
+        let keys = |opts: ListOpts| {
+            let mut keys = opts
+                .list_sync(&dir)
+                .map(|entry| entry.unwrap().key)
+                .collect::<Vec<_>>();
+            keys.sort();
+            keys
+        };
+        assert_eq!(
+            keys(ListOpts::new().prefix("pkg::")),
+            vec!["pkg::new", "pkg::old"]
+        );
+        assert_eq!(
+            keys(ListOpts::new().older_than(day)),
+            vec!["other", "pkg::old"]
+        );
+        assert_eq!(keys(ListOpts::new().newer_than(day)), vec!["pkg::new"]);
+        assert_eq!(
+            keys(ListOpts::new().metadata("/kind", json!("tarball"))),
+            vec!["pkg::old"]
+        );
+        assert_eq!(
+            keys(ListOpts::new().prefix("pkg::").older_than(day)),
+            vec!["pkg::old"]
+        );
+    }
 }
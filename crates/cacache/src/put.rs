@@ -11,6 +11,8 @@ use ssri::{Algorithm, Integrity};
 use crate::content::write;
 use crate::errors::{Error, Internal, Result};
 use crate::index;
+use crate::lock;
+use crate::store::{CacheStore, FsStore};
 
 use std::task::{Context as TaskContext, Poll};
 
@@ -141,19 +143,25 @@ impl Writer {
     /// otherwise everything will be thrown out.
     pub async fn commit(mut self) -> Result<Integrity> {
         let cache = self.cache;
-        let writer_sri = self.writer.into_inner().await.close_async().await?;
-        if let Some(sri) = &self.opts.sri {
-            if sri.matches(&writer_sri).is_none() {
-                return Err(ssri::Error::IntegrityCheckError(sri.clone(), writer_sri).into());
-            }
-        } else {
-            self.opts.sri = Some(writer_sri.clone());
-        }
+        // Hold off garbage collection until the content is in the index.
+        let _lock = lock::lock_shared(&cache).await?;
+        // Content that doesn't match the strongest expected hash never makes
+        // it into the cache.
+        let writer_sri = self
+            .writer
+            .into_inner()
+            .await
+            .close_checked_async(self.opts.sri.clone())
+            .await?;
+        // Record every hash the content was stored under.
+        self.opts.sri = Some(writer_sri.clone());
         if let Some(size) = self.opts.size {
             if size != self.written {
                 return Err(Error::SizeError(size, self.written));
             }
         }
+        // Entries record how much data they point to, which npm relies on.
+        self.opts.size = Some(self.written);
         if let Some(key) = self.key {
             index::insert_async(&cache, &key, self.opts).await
         } else {
@@ -208,18 +216,7 @@ where
     P: AsRef<Path>,
     D: AsRef<[u8]>,
 {
-    let mut writer = WriteOpts::new()
-        .algorithm(Algorithm::Sha256)
-        .size(data.as_ref().len())
-        .open_hash_sync(cache.as_ref())?;
-    writer.write_all(data.as_ref()).with_context(|| {
-        format!(
-            "Failed to write to cache data for cache at {:?}",
-            cache.as_ref()
-        )
-    })?;
-    writer.written = data.as_ref().len();
-    writer.commit()
+    FsStore::new(cache).write_hash(data.as_ref(), Algorithm::Sha256)
 }
 
 /// Builder for options and flags for opening a new cache file to write data into.
@@ -230,6 +227,7 @@ pub struct WriteOpts {
     pub(crate) size: Option<usize>,
     pub(crate) time: Option<u128>,
     pub(crate) metadata: Option<Value>,
+    pub(crate) references: Option<Vec<Integrity>>,
 }
 
 impl WriteOpts {
@@ -248,12 +246,8 @@ impl WriteOpts {
             cache: cache.as_ref().to_path_buf(),
             key: Some(String::from(key.as_ref())),
             written: 0,
-            writer: write::Writer::new_async(
-                cache.as_ref().to_owned(),
-                *self.algorithm.as_ref().unwrap_or(&Algorithm::Sha256),
-                None,
-            )
-            .await?,
+            writer: write::Writer::new_async(cache.as_ref().to_owned(), self.algorithms(), None)
+                .await?,
             opts: self,
         })
     }
@@ -269,7 +263,7 @@ impl WriteOpts {
             written: 0,
             writer: write::Writer::new_async(
                 cache.as_ref().to_owned(),
-                *self.algorithm.as_ref().unwrap_or(&Algorithm::Sha256),
+                self.algorithms(),
                 self.size,
             )
             .await?,
@@ -287,11 +281,7 @@ impl WriteOpts {
             cache: cache.as_ref().to_path_buf(),
             key: Some(String::from(key.as_ref())),
             written: 0,
-            writer: write::Writer::new(
-                cache.as_ref().to_owned(),
-                *self.algorithm.as_ref().unwrap_or(&Algorithm::Sha256),
-                self.size,
-            )?,
+            writer: write::Writer::new(cache.as_ref().to_owned(), self.algorithms(), self.size)?,
             opts: self,
         })
     }
@@ -305,16 +295,14 @@ impl WriteOpts {
             cache: cache.as_ref().to_path_buf(),
             key: None,
             written: 0,
-            writer: write::Writer::new(
-                cache.as_ref().to_owned(),
-                *self.algorithm.as_ref().unwrap_or(&Algorithm::Sha256),
-                self.size,
-            )?,
+            writer: write::Writer::new(cache.as_ref().to_owned(), self.algorithms(), self.size)?,
             opts: self,
         })
     }
 
-    /// Configures the algorithm to write data under.
+    /// Configures the algorithm to write data under. If an expected
+    /// [`integrity`](WriteOpts::integrity) is set, data is also hashed with,
+    /// and stored under, every algorithm it has a hash for.
     pub fn algorithm(mut self, algo: Algorithm) -> Self {
         self.algorithm = Some(algo);
         self
@@ -341,13 +329,39 @@ impl WriteOpts {
         self
     }
 
-    /// Sets the expected integrity hash of the written data. If there's a
-    /// mismatch between this Integrity and the one calculated by the write,
-    /// `put.commit()` will error.
+    /// Sets additional content that the index entry depends on, such as
+    /// data written with `write_hash`. Referenced content is kept around by
+    /// [`verify`](crate::verify) and [`evict`](crate::evict) for as long
+    /// as the entry itself is.
+    pub fn references(mut self, references: impl IntoIterator<Item = Integrity>) -> Self {
+        self.references = Some(references.into_iter().collect());
+        self
+    }
+
+    /// Sets the expected integrity hash of the written data. If the data
+    /// doesn't match this Integrity's strongest hash, `put.commit()` will
+    /// error. Data is stored under each of the integrity's hashes it matches,
+    /// so it can later be looked up by any of them.
     pub fn integrity(mut self, sri: Integrity) -> Self {
         self.sri = Some(sri);
         self
     }
+
+    fn algorithms(&self) -> Vec<Algorithm> {
+        let mut algos = self
+            .sri
+            .iter()
+            .flat_map(|sri| sri.hashes.iter().map(|hash| hash.algorithm))
+            .collect::<Vec<_>>();
+        if let Some(algo) = self.algorithm {
+            algos.push(algo);
+        } else if algos.is_empty() {
+            algos.push(Algorithm::Sha256);
+        }
+        algos.sort_by_key(|algo| algo.to_string());
+        algos.dedup();
+        algos
+    }
 }
 
 /// A reference to an open file writing to the cache.
@@ -427,19 +441,20 @@ impl SyncWriter {
     /// otherwise everything will be thrown out.
     pub fn commit(mut self) -> Result<Integrity> {
         let cache = self.cache;
-        let writer_sri = self.writer.close()?;
-        if let Some(sri) = &self.opts.sri {
-            if sri.matches(&writer_sri).is_none() {
-                return Err(ssri::Error::IntegrityCheckError(sri.clone(), writer_sri).into());
-            }
-        } else {
-            self.opts.sri = Some(writer_sri.clone());
-        }
+        // Hold off garbage collection until the content is in the index.
+        let _lock = lock::lock_shared_sync(&cache)?;
+        // Content that doesn't match the strongest expected hash never makes
+        // it into the cache.
+        let writer_sri = self.writer.close_checked(self.opts.sri.as_ref())?;
+        // Record every hash the content was stored under.
+        self.opts.sri = Some(writer_sri.clone());
         if let Some(size) = self.opts.size {
             if size != self.written {
                 return Err(Error::SizeError(size, self.written));
             }
         }
+        // Entries record how much data they point to, which npm relies on.
+        self.opts.size = Some(self.written);
         if let Some(key) = self.key {
             index::insert(&cache, &key, self.opts)
         } else {
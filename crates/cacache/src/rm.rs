@@ -6,9 +6,11 @@ use async_std::fs as afs;
 
 use ssri::Integrity;
 
-use crate::content::rm;
 use crate::errors::{Internal, Result};
+use crate::format;
 use crate::index;
+use crate::lock;
+use crate::store::{CacheStore, FsStore};
 
 /// Removes an individual index metadata entry. The associated content will be
 /// left in the cache.
@@ -66,7 +68,9 @@ where
 /// }
 /// ```
 pub async fn remove_hash<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<()> {
-    Ok(rm::rm_async(cache.as_ref(), &sri).await?)
+    let cache = cache.as_ref().to_owned();
+    let sri = sri.clone();
+    smol::unblock!(remove_hash_sync(cache, &sri))
 }
 
 /// Removes entire contents of the cache, including temporary files, the entry
@@ -92,9 +96,17 @@ pub async fn remove_hash<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<()
 /// }
 /// ```
 pub async fn clear<P: AsRef<Path>>(cache: P) -> Result<()> {
+    let lock_cache = cache.as_ref().to_owned();
+    let _lock = smol::unblock!(lock::lock_exclusive(&lock_cache))?;
     for entry in cache.as_ref().read_dir().to_internal()? {
         if let Ok(entry) = entry {
-            afs::remove_dir_all(entry.path()).await.to_internal()?;
+            // Cache formats are settings, not data, so they stay. So does
+            // the lock file, since other processes may be waiting on it.
+            if !format::is_settings_file(cache.as_ref(), &entry.path())
+                && entry.path() != lock::lock_file(cache.as_ref())
+            {
+                afs::remove_dir_all(entry.path()).await.to_internal()?;
+            }
         }
     }
     Ok(())
@@ -126,7 +138,7 @@ where
     P: AsRef<Path>,
     K: AsRef<str>,
 {
-    index::delete(cache.as_ref(), key.as_ref())
+    FsStore::new(cache).delete(key.as_ref())
 }
 
 /// Removes an individual content entry synchronously. Any index entries
@@ -152,7 +164,7 @@ where
 /// }
 /// ```
 pub fn remove_hash_sync<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<()> {
-    Ok(rm::rm(cache.as_ref(), &sri)?)
+    FsStore::new(cache).remove_hash(sri)
 }
 
 /// Removes entire contents of the cache synchronously, including temporary
@@ -176,9 +188,16 @@ pub fn remove_hash_sync<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<()>
 /// }
 /// ```
 pub fn clear_sync<P: AsRef<Path>>(cache: P) -> Result<()> {
+    let _lock = lock::lock_exclusive(cache.as_ref())?;
     for entry in cache.as_ref().read_dir().to_internal()? {
         if let Ok(entry) = entry {
-            fs::remove_dir_all(entry.path()).to_internal()?;
+            // Cache formats are settings, not data, so they stay. So does
+            // the lock file, since other processes may be waiting on it.
+            if !format::is_settings_file(cache.as_ref(), &entry.path())
+                && entry.path() != lock::lock_file(cache.as_ref())
+            {
+                fs::remove_dir_all(entry.path()).to_internal()?;
+            }
         }
     }
     Ok(())
@@ -0,0 +1,370 @@
+//! Pluggable storage backends.
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use ssri::{Algorithm, Integrity, IntegrityOpts};
+
+use crate::content::{read, rm};
+use crate::errors::{Error, Internal, Result};
+use crate::index::{self, Metadata};
+use crate::lock;
+use crate::put::WriteOpts;
+
+/// Storage for a cache's content and index.
+///
+/// The free functions in this crate all work directly on a cache directory,
+/// and the basic synchronous ones go through [`FsStore`]. `CacheStore` lets
+/// code that only needs those operations work against other kinds of
+/// storage too: [`MemoryStore`] keeps everything in memory, and
+/// [`LayeredStore`] reads through one store into another.
+///
+/// Stores are synchronous. Async code can share one behind an `Arc` and
+/// call it from a blocking task.
+pub trait CacheStore: Send + Sync {
+    /// Reads the content for `sri`, verifying it along the way. Errors if
+    /// the content isn't there.
+    fn read_hash(&self, sri: &Integrity) -> Result<Vec<u8>>;
+
+    /// Returns true if content for `sri` is present.
+    fn has_hash(&self, sri: &Integrity) -> bool;
+
+    /// Writes `data` as content hashed with `algorithm`, returning its
+    /// integrity.
+    fn write_hash(&self, data: &[u8], algorithm: Algorithm) -> Result<Integrity>;
+
+    /// Removes the content for `sri`. Index entries pointing at it are left
+    /// alone.
+    fn remove_hash(&self, sri: &Integrity) -> Result<()>;
+
+    /// Adds `entry` to the index, replacing any existing entry for its key.
+    fn insert(&self, entry: Metadata) -> Result<()>;
+
+    /// Looks up the index entry for `key`.
+    fn find(&self, key: &str) -> Result<Option<Metadata>>;
+
+    /// Removes the index entry for `key`. Its content is left alone.
+    fn delete(&self, key: &str) -> Result<()>;
+
+    /// Returns every live index entry.
+    fn entries(&self) -> Result<Vec<Metadata>>;
+
+    /// Writes `data` and indexes it under `key`.
+    fn write(&self, key: &str, data: &[u8]) -> Result<Integrity> {
+        let sri = self.write_hash(data, Algorithm::Sha256)?;
+        self.insert(Metadata {
+            key: key.into(),
+            integrity: sri.clone(),
+            time: index::now(),
+            size: data.len(),
+            metadata: serde_json::Value::Null,
+            references: Vec::new(),
+        })?;
+        Ok(sri)
+    }
+
+    /// Reads the data indexed under `key`, if there is any.
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.find(key)? {
+            Some(entry) => self.read_hash(&entry.integrity).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The standard on-disk cache layout, as used by every other function in
+/// this crate.
+///
+/// Removing content takes the cache's exclusive lock, so it waits for every
+/// [`CacheLock`](crate::CacheLock) on the cache to be dropped, including any
+/// the caller is holding itself.
+#[derive(Clone, Debug)]
+pub struct FsStore {
+    cache: PathBuf,
+}
+
+impl FsStore {
+    /// Creates a store for the cache at `cache`.
+    pub fn new<P: AsRef<Path>>(cache: P) -> Self {
+        FsStore {
+            cache: cache.as_ref().to_owned(),
+        }
+    }
+
+    /// The cache directory this store writes to.
+    pub fn path(&self) -> &Path {
+        &self.cache
+    }
+}
+
+impl CacheStore for FsStore {
+    fn read_hash(&self, sri: &Integrity) -> Result<Vec<u8>> {
+        Ok(read::Reader::consume(&self.cache, sri)?)
+    }
+
+    fn has_hash(&self, sri: &Integrity) -> bool {
+        read::has_content(&self.cache, sri).is_some()
+    }
+
+    fn write_hash(&self, data: &[u8], algorithm: Algorithm) -> Result<Integrity> {
+        let mut writer = WriteOpts::new()
+            .algorithm(algorithm)
+            .size(data.len())
+            .open_hash_sync(&self.cache)?;
+        writer
+            .write_all(data)
+            .with_context(|| format!("Failed to write content to {:?}", self.cache))?;
+        writer.commit()
+    }
+
+    fn remove_hash(&self, sri: &Integrity) -> Result<()> {
+        let _lock = lock::lock_exclusive(&self.cache)?;
+        Ok(rm::rm(&self.cache, sri)?)
+    }
+
+    fn insert(&self, entry: Metadata) -> Result<()> {
+        let opts = WriteOpts::new()
+            .integrity(entry.integrity)
+            .size(entry.size)
+            .time(entry.time)
+            .metadata(entry.metadata)
+            .references(entry.references);
+        index::insert(&self.cache, &entry.key, opts)?;
+        Ok(())
+    }
+
+    fn find(&self, key: &str) -> Result<Option<Metadata>> {
+        index::find(&self.cache, key)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        index::delete(&self.cache, key)
+    }
+
+    fn entries(&self) -> Result<Vec<Metadata>> {
+        if !index::index_dir(&self.cache).exists() {
+            return Ok(Vec::new());
+        }
+        index::ls(&self.cache).collect()
+    }
+}
+
+/// A cache that lives entirely in memory. Handy for tests, or for data that
+/// shouldn't outlive the process.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    // Content is stored once per hash, so any of an integrity's hashes finds
+    // it.
+    content: RwLock<HashMap<String, Vec<u8>>>,
+    index: RwLock<HashMap<String, Metadata>>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn read_hash(&self, sri: &Integrity) -> Result<Vec<u8>> {
+        let content = self.content.read().unwrap();
+        let data = sri
+            .hashes
+            .iter()
+            .find_map(|hash| content.get(&hash.to_string()))
+            .ok_or_else(|| Error::ContentNotFound(sri.clone()))?;
+        sri.check(data)?;
+        Ok(data.clone())
+    }
+
+    fn has_hash(&self, sri: &Integrity) -> bool {
+        let content = self.content.read().unwrap();
+        sri.hashes
+            .iter()
+            .any(|hash| content.contains_key(&hash.to_string()))
+    }
+
+    fn write_hash(&self, data: &[u8], algorithm: Algorithm) -> Result<Integrity> {
+        let sri = IntegrityOpts::new()
+            .algorithm(algorithm)
+            .chain(data)
+            .result();
+        let mut content = self.content.write().unwrap();
+        for hash in sri.hashes.iter() {
+            content.insert(hash.to_string(), data.to_vec());
+        }
+        Ok(sri)
+    }
+
+    fn remove_hash(&self, sri: &Integrity) -> Result<()> {
+        let mut content = self.content.write().unwrap();
+        for hash in sri.hashes.iter() {
+            content.remove(&hash.to_string());
+        }
+        Ok(())
+    }
+
+    fn insert(&self, entry: Metadata) -> Result<()> {
+        self.index.write().unwrap().insert(entry.key.clone(), entry);
+        Ok(())
+    }
+
+    fn find(&self, key: &str) -> Result<Option<Metadata>> {
+        Ok(self.index.read().unwrap().get(key).cloned())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.index.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<Metadata>> {
+        Ok(self.index.read().unwrap().values().cloned().collect())
+    }
+}
+
+/// A store that reads through a `local` store into a `fallback` one, such as
+/// a local cache in front of a read-only shared cache on a network drive.
+///
+/// Lookups try `local` first. Content found only in `fallback` is copied
+/// into `local` as it's read, so it's only fetched from `fallback` once.
+/// Writes and removals only ever touch `local`, which means entries that
+/// are also in `fallback` show through again once removed locally.
+#[derive(Debug)]
+pub struct LayeredStore<L, F> {
+    local: L,
+    fallback: F,
+}
+
+impl<L: CacheStore, F: CacheStore> LayeredStore<L, F> {
+    /// Layers `local` over `fallback`.
+    pub fn new(local: L, fallback: F) -> Self {
+        LayeredStore { local, fallback }
+    }
+
+    /// The store that's read first and written to.
+    pub fn local(&self) -> &L {
+        &self.local
+    }
+
+    /// The store that's only read from.
+    pub fn fallback(&self) -> &F {
+        &self.fallback
+    }
+}
+
+impl<L: CacheStore, F: CacheStore> CacheStore for LayeredStore<L, F> {
+    fn read_hash(&self, sri: &Integrity) -> Result<Vec<u8>> {
+        if self.local.has_hash(sri) {
+            return self.local.read_hash(sri);
+        }
+        let data = self.fallback.read_hash(sri)?;
+        self.local.write_hash(&data, sri.pick_algorithm())?;
+        Ok(data)
+    }
+
+    fn has_hash(&self, sri: &Integrity) -> bool {
+        self.local.has_hash(sri) || self.fallback.has_hash(sri)
+    }
+
+    fn write_hash(&self, data: &[u8], algorithm: Algorithm) -> Result<Integrity> {
+        self.local.write_hash(data, algorithm)
+    }
+
+    fn remove_hash(&self, sri: &Integrity) -> Result<()> {
+        self.local.remove_hash(sri)
+    }
+
+    fn insert(&self, entry: Metadata) -> Result<()> {
+        self.local.insert(entry)
+    }
+
+    fn find(&self, key: &str) -> Result<Option<Metadata>> {
+        match self.local.find(key)? {
+            Some(entry) => Ok(Some(entry)),
+            None => self.fallback.find(key),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.local.delete(key)
+    }
+
+    fn entries(&self) -> Result<Vec<Metadata>> {
+        let mut entries = self
+            .fallback
+            .entries()?
+            .into_iter()
+            .map(|entry| (entry.key.clone(), entry))
+            .collect::<HashMap<_, _>>();
+        for entry in self.local.entries()? {
+            entries.insert(entry.key.clone(), entry);
+        }
+        Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(store: &dyn CacheStore) {
+        let sri = store.write("my-key", b"hello world").unwrap();
+        assert!(store.has_hash(&sri));
+        assert_eq!(store.read("my-key").unwrap().unwrap(), b"hello world");
+        assert_eq!(store.find("my-key").unwrap().unwrap().integrity, sri);
+        assert_eq!(store.entries().unwrap().len(), 1);
+
+        store.delete("my-key").unwrap();
+        assert!(store.find("my-key").unwrap().is_none());
+        assert!(store.read_hash(&sri).is_ok());
+
+        store.remove_hash(&sri).unwrap();
+        assert!(!store.has_hash(&sri));
+        assert!(store.read_hash(&sri).is_err());
+    }
+
+    #[test]
+    fn test_fs_store() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = FsStore::new(tmp.path());
+        round_trip(&store);
+    }
+
+    #[test]
+    fn test_memory_store() {
+        round_trip(&MemoryStore::new());
+    }
+
+    #[test]
+    fn test_memory_store_matches_any_hash() {
+        let store = MemoryStore::new();
+        let sri = store.write_hash(b"hello", Algorithm::Sha512).unwrap();
+        let other = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha1)
+            .chain(b"hello")
+            .result();
+        let multi: Integrity = format!("{} {}", other, sri).parse().unwrap();
+        assert_eq!(store.read_hash(&multi).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_layered_store_reads_through() {
+        let tmp = tempfile::tempdir().unwrap();
+        let fallback = FsStore::new(tmp.path());
+        let sri = fallback.write("shared", b"from fallback").unwrap();
+
+        let store = LayeredStore::new(MemoryStore::new(), fallback);
+        assert!(!store.local().has_hash(&sri));
+        assert_eq!(store.read("shared").unwrap().unwrap(), b"from fallback");
+        // Read content gets copied into the local layer.
+        assert!(store.local().has_hash(&sri));
+
+        // Writes never reach the fallback.
+        store.write("mine", b"local only").unwrap();
+        assert!(store.fallback().find("mine").unwrap().is_none());
+        assert_eq!(store.entries().unwrap().len(), 2);
+    }
+}
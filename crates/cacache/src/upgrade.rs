@@ -0,0 +1,257 @@
+//! Functions for upgrading the integrity of existing cache entries.
+use std::io::{self, Write};
+use std::path::Path;
+
+use ssri::{Algorithm, Integrity};
+
+use crate::content::{read, write};
+use crate::errors::{Error, Internal, Result};
+use crate::index;
+use crate::lock;
+use crate::put::WriteOpts;
+
+/// Upgrades the index entry for `key` so its integrity includes a hash made
+/// with `algorithm`, such as adding `sha512` to an entry that only has a
+/// `sha1` hash. The content is read and verified against its existing
+/// integrity first, then stored under the new hash as well, so it can be
+/// looked up by either. Returns the entry's new integrity.
+///
+/// Entries that already have a hash made with `algorithm` are left alone.
+///
+/// Nothing upgrades entries on its own. Reads leave entries as they are, so
+/// call this on entries you find that only have a weak hash, such as `sha1`
+/// integrities from older npm lockfiles, to stop relying on it.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+/// use async_std::prelude::*;
+/// use cacache::{Algorithm, WriteOpts};
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let mut fd = WriteOpts::new()
+///         .algorithm(Algorithm::Sha1)
+///         .open("./my-cache", "my-key")
+///         .await?;
+///     fd.write_all(b"hello").await.expect("Failed to write to cache");
+///     fd.commit().await?;
+///
+///     let sri = cacache::upgrade_integrity("./my-cache", "my-key", Algorithm::Sha512).await?;
+///     assert_eq!(sri.pick_algorithm(), Algorithm::Sha512);
+///     Ok(())
+/// }
+/// ```
+pub async fn upgrade_integrity<P, K>(cache: P, key: K, algorithm: Algorithm) -> Result<Integrity>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    let cache = cache.as_ref().to_owned();
+    let key = key.as_ref().to_owned();
+    smol::unblock!(upgrade_integrity_sync(cache, key, algorithm))
+}
+
+/// Upgrades the index entry for `key` synchronously. See
+/// [`upgrade_integrity`] for details.
+///
+/// ## Example
+/// ```no_run
+/// use cacache::Algorithm;
+///
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::upgrade_integrity_sync("./my-cache", "my-key", Algorithm::Sha512)?;
+///     println!("now stored as {}", sri);
+///     Ok(())
+/// }
+/// ```
+pub fn upgrade_integrity_sync<P, K>(cache: P, key: K, algorithm: Algorithm) -> Result<Integrity>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    let cache = cache.as_ref();
+    let key = key.as_ref();
+    let entry = index::find(cache, key)?
+        .ok_or_else(|| Error::EntryNotFound(cache.to_owned(), key.into()))?;
+    if entry
+        .integrity
+        .hashes
+        .iter()
+        .any(|hash| hash.algorithm == algorithm)
+    {
+        return Ok(entry.integrity);
+    }
+
+    // The new copy isn't referenced until the entry is rewritten.
+    let _lock = lock::lock_shared_sync(cache)?;
+    let mut reader = read::open(cache, entry.integrity.clone())?;
+    let mut writer = write::Writer::new(cache.to_owned(), vec![algorithm], None)?;
+    io::copy(&mut reader, &mut writer).to_internal()?;
+    reader.check()?;
+    writer.flush().to_internal()?;
+    let added = writer.close()?;
+
+    // Parsing puts the strongest hash first.
+    let upgraded: Integrity = format!("{} {}", added, entry.integrity).parse()?;
+    let opts = WriteOpts::new()
+        .integrity(upgraded.clone())
+        .size(entry.size)
+        .time(entry.time)
+        .metadata(entry.metadata)
+        .references(entry.references);
+    index::insert(cache, key, opts)?;
+    Ok(upgraded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use ssri::IntegrityOpts;
+
+    fn write_sha1(dir: &Path, key: &str, data: &[u8]) -> Integrity {
+        let mut fd = WriteOpts::new()
+            .algorithm(Algorithm::Sha1)
+            .open_sync(dir, key)
+            .unwrap();
+        fd.write_all(data).unwrap();
+        fd.commit().unwrap()
+    }
+
+    #[test]
+    fn test_upgrade_integrity() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sha1 = write_sha1(&dir, "my-key", b"hello");
+        let sha512 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain(b"hello")
+            .result();
+        assert!(!crate::exists_sync(&dir, &sha512));
+
+        let upgraded = upgrade_integrity_sync(&dir, "my-key", Algorithm::Sha512).unwrap();
+        assert_eq!(upgraded.pick_algorithm(), Algorithm::Sha512);
+        assert_eq!(
+            crate::metadata_sync(&dir, "my-key")
+                .unwrap()
+                .unwrap()
+                .integrity,
+            upgraded
+        );
+        // Both the old and the new hash find the content.
+        assert_eq!(crate::read_hash_sync(&dir, &sha1).unwrap(), b"hello");
+        assert_eq!(crate::read_hash_sync(&dir, &sha512).unwrap(), b"hello");
+        assert_eq!(crate::read_sync(&dir, "my-key").unwrap(), b"hello");
+
+        // Upgrading again is a no-op.
+        assert_eq!(
+            upgrade_integrity_sync(&dir, "my-key", Algorithm::Sha512).unwrap(),
+            upgraded
+        );
+    }
+
+    #[test]
+    fn test_multi_hash_writes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sha1 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha1)
+            .chain(b"hello")
+            .result();
+        let sha512 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain(b"hello")
+            .result();
+        let both: Integrity = format!("{} {}", sha1, sha512).parse().unwrap();
+
+        let mut fd = WriteOpts::new()
+            .integrity(both.clone())
+            .open_hash_sync(&dir)
+            .unwrap();
+        fd.write_all(b"hello").unwrap();
+        fd.commit().unwrap();
+
+        // The content can be found by either hash on its own.
+        assert_eq!(crate::read_hash_sync(&dir, &sha1).unwrap(), b"hello");
+        assert_eq!(crate::read_hash_sync(&dir, &sha512).unwrap(), b"hello");
+
+        // And removing it by both removes every copy.
+        crate::remove_hash_sync(&dir, &both).unwrap();
+        assert!(!crate::exists_sync(&dir, &sha1));
+        assert!(!crate::exists_sync(&dir, &sha512));
+    }
+
+    fn write_expecting(dir: &Path, expected: &Integrity, data: &[u8]) -> Result<Integrity> {
+        let mut fd = WriteOpts::new()
+            .integrity(expected.clone())
+            .open_hash_sync(dir)
+            .unwrap();
+        fd.write_all(data).unwrap();
+        fd.commit()
+    }
+
+    #[test]
+    fn test_strongest_hash_must_match() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sha1 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha1)
+            .chain(b"hello")
+            .result();
+        let sha512 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain(b"goodbye")
+            .result();
+        let expected: Integrity = format!("{} {}", sha1, sha512).parse().unwrap();
+
+        // A matching sha1 isn't enough when the sha512 is wrong.
+        assert!(write_expecting(&dir, &expected, b"hello").is_err());
+        assert!(!crate::exists_sync(&dir, &sha1));
+        assert!(!crate::exists_sync(&dir, &sha512));
+    }
+
+    #[test]
+    fn test_only_matching_hashes_are_stored() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let wrong_sha1 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha1)
+            .chain(b"goodbye")
+            .result();
+        let actual_sha1 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha1)
+            .chain(b"hello")
+            .result();
+        let sha512 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain(b"hello")
+            .result();
+        let expected: Integrity = format!("{} {}", wrong_sha1, sha512).parse().unwrap();
+
+        let sri = write_expecting(&dir, &expected, b"hello").unwrap();
+        assert_eq!(sri, sha512);
+        assert_eq!(crate::read_hash_sync(&dir, &sha512).unwrap(), b"hello");
+        assert!(!crate::exists_sync(&dir, &actual_sha1));
+        assert!(!crate::exists_sync(&dir, &wrong_sha1));
+    }
+
+    #[test]
+    fn test_mismatched_content_is_not_stored() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let expected = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain(b"goodbye")
+            .result();
+        let actual = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain(b"hello")
+            .result();
+
+        assert!(write_expecting(&dir, &expected, b"hello").is_err());
+        assert!(!crate::exists_sync(&dir, &actual));
+    }
+}
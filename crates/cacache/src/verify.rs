@@ -0,0 +1,379 @@
+//! Functions for verifying a cache and garbage collecting unused content.
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use walkdir::WalkDir;
+
+use crate::content::{path, read, rm};
+use crate::errors::{Internal, Result};
+use crate::format::{self, ContentFormat};
+use crate::index;
+use crate::lock;
+
+/// Statistics about the work done by a [`verify`] run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyStats {
+    /// Number of content blobs that were re-hashed and found intact.
+    pub verified_content: usize,
+    /// Number of content blobs removed, whether unreferenced or corrupted.
+    pub reclaimed_count: usize,
+    /// Total size, in bytes, of removed content blobs that was actually
+    /// freed. Blobs still hard linked somewhere else, like under another
+    /// hash or into a `node_modules`, are left out.
+    pub reclaimed_size: u64,
+    /// Number of content blobs that failed their integrity check.
+    pub bad_content_count: usize,
+    /// Total size, in bytes, of content left in the cache.
+    pub kept_size: u64,
+    /// Number of live index entries found.
+    pub total_entries: usize,
+    /// Number of index entries removed because their content was missing or
+    /// corrupted.
+    pub rejected_entries: usize,
+}
+
+/// Verifies the cache and garbage collects anything that's no longer
+/// needed.
+///
+/// Content that isn't referenced by any live index entry is removed, all
+/// remaining content is re-hashed and removed if it turns out to be
+/// corrupted, and index entries whose content is gone are removed as well.
+/// The index is compacted along the way (see [`compact_index`]).
+///
+/// Content gets checked while writes carry on as usual. Only removing it
+/// waits for any writes in progress, in this or any other process, and holds
+/// off new ones until it's done (see [`CacheLock`]). Content that's been
+/// referenced or written again in the meantime is left alone.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     cacache::write("./my-cache", "my-key", b"hello").await?;
+///     cacache::remove("./my-cache", "my-key").await?;
+///
+///     let stats = cacache::verify("./my-cache").await?;
+///     println!("reclaimed {} bytes", stats.reclaimed_size);
+///
+///     Ok(())
+/// }
+/// ```
+pub async fn verify<P: AsRef<Path>>(cache: P) -> Result<VerifyStats> {
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(verify_sync(cache))
+}
+
+/// Verifies the cache and garbage collects anything that's no longer needed,
+/// synchronously. See [`verify`] for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     cacache::remove_sync("./my-cache", "my-key")?;
+///
+///     let stats = cacache::verify_sync("./my-cache")?;
+///     println!("reclaimed {} bytes", stats.reclaimed_size);
+///
+///     Ok(())
+/// }
+/// ```
+pub fn verify_sync<P: AsRef<Path>>(cache: P) -> Result<VerifyStats> {
+    let cache = cache.as_ref();
+    let started = SystemTime::now();
+    let mut stats = VerifyStats::default();
+
+    // Mark: re-hash all live content and find what can go. This is the slow
+    // part, so it only keeps out anything else that removes data, and lets
+    // writes through.
+    let shared = lock::lock_shared_sync(cache)?;
+    let format = format::content_format_sync(cache)?;
+    let (_, live) = live_content(cache)?;
+    let mut unreferenced = Vec::new();
+    let mut bad = Vec::new();
+    let content_dir = path::content_dir(cache);
+    if content_dir.exists() {
+        for file in WalkDir::new(&content_dir) {
+            let file = file.to_internal()?;
+            if !file.file_type().is_file() {
+                continue;
+            }
+            let meta = file
+                .metadata()
+                .with_context(|| format!("Failed to read metadata for {:?}", file.path()))?;
+            match live.get(file.path()) {
+                Some(sri) if check_content(file.path(), sri, format).is_ok() => {
+                    stats.verified_content += 1;
+                    stats.kept_size += meta.len();
+                }
+                Some(sri) => bad.push((file.path().to_owned(), sri.clone())),
+                None if meta.modified().map(|t| t <= started).unwrap_or(false) => {
+                    unreferenced.push(file.path().to_owned())
+                }
+                None => stats.kept_size += meta.len(),
+            }
+        }
+    }
+    drop(shared);
+
+    // Sweep: the index may have changed while we were marking, so only
+    // remove content that's still unreferenced, or still corrupted.
+    let _lock = lock::lock_exclusive(cache)?;
+    let format = format::content_format_sync(cache)?;
+    let (entries, live) = live_content(cache)?;
+    stats.total_entries = entries.len();
+    for cpath in unreferenced {
+        let meta = match current_metadata(&cpath)? {
+            Some(meta) => meta,
+            None => continue,
+        };
+        let written_since = meta.modified().map(|t| t > started).unwrap_or(true);
+        if written_since || live.contains_key(&cpath) {
+            stats.kept_size += meta.len();
+        } else {
+            remove_content(&cpath, &mut stats)?;
+        }
+    }
+    for (cpath, sri) in bad {
+        let meta = match current_metadata(&cpath)? {
+            Some(meta) => meta,
+            None => continue,
+        };
+        if check_content(&cpath, &sri, format).is_ok() {
+            // Written again since we looked at it.
+            stats.verified_content += 1;
+            stats.kept_size += meta.len();
+        } else {
+            stats.bad_content_count += 1;
+            remove_content(&cpath, &mut stats)?;
+        }
+    }
+
+    // Finally, drop index entries whose content is gone, compacting the
+    // index as we go.
+    stats.rejected_entries = entries
+        .iter()
+        .filter(|entry| !has_all_content(cache, entry))
+        .count();
+    index::compact(cache, |entry| has_all_content(cache, entry))?;
+
+    Ok(stats)
+}
+
+/// Lists the cache's live index entries, along with every content path they
+/// point to.
+fn live_content(cache: &Path) -> Result<(Vec<index::Metadata>, HashMap<PathBuf, ssri::Integrity>)> {
+    let mut entries = Vec::new();
+    if index::index_dir(cache).exists() {
+        for entry in index::ls(cache) {
+            entries.push(entry?);
+        }
+    }
+    let live = entries
+        .iter()
+        .flat_map(|entry| std::iter::once(&entry.integrity).chain(entry.references.iter()))
+        .flat_map(|sri| path::single_hashes(sri))
+        .map(|sri| (path::content_path(cache, &sri), sri))
+        .collect();
+    Ok((entries, live))
+}
+
+/// Looks content that was marked for removal up again, in case something
+/// else removed it in the meantime.
+fn current_metadata(cpath: &Path) -> Result<Option<fs::Metadata>> {
+    match fs::metadata(cpath) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        meta => Ok(Some(meta.with_context(|| {
+            format!("Failed to read metadata for {:?}", cpath)
+        })?)),
+    }
+}
+
+fn remove_content(cpath: &Path, stats: &mut VerifyStats) -> Result<()> {
+    stats.reclaimed_size += rm::remove_file(cpath)?;
+    stats.reclaimed_count += 1;
+    Ok(())
+}
+
+fn has_all_content(cache: &Path, entry: &index::Metadata) -> bool {
+    std::iter::once(&entry.integrity)
+        .chain(entry.references.iter())
+        .all(|sri| read::find_content_path(cache, sri).is_some())
+}
+
+fn check_content(cpath: &Path, sri: &ssri::Integrity, format: ContentFormat) -> Result<()> {
+    let mut reader = read::open_at(cpath, sri, format)?;
+    io::copy(&mut reader, &mut io::sink()).to_internal()?;
+    reader.check()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+
+    use async_std::task;
+    use pretty_assertions::assert_eq;
+
+    use crate::content::path;
+
+    #[test]
+    fn test_verify_keeps_live_content() {
+        task::block_on(async {
+            let tmp = tempfile::tempdir().unwrap();
+            let dir = tmp.path().to_owned();
+            let sri = crate::write(&dir, "key", b"my-data").await.unwrap();
+
+            let stats = crate::verify(&dir).await.unwrap();
+            assert_eq!(stats.total_entries, 1);
+            assert_eq!(stats.verified_content, 1);
+            assert_eq!(stats.reclaimed_count, 0);
+            assert_eq!(stats.rejected_entries, 0);
+
+            let data = crate::read(&dir, "key").await.unwrap();
+            assert_eq!(data, b"my-data");
+            assert!(crate::exists(&dir, &sri).await);
+        });
+    }
+
+    #[test]
+    fn test_verify_reclaims_unreferenced_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        let size = fs::metadata(path::content_path(&dir, &sri)).unwrap().len();
+        crate::remove_sync(&dir, "key").unwrap();
+
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.total_entries, 0);
+        assert_eq!(stats.reclaimed_count, 1);
+        assert_eq!(stats.reclaimed_size, size);
+        assert_eq!(stats.kept_size, 0);
+        assert!(!crate::exists_sync(&dir, &sri));
+    }
+
+    #[test]
+    fn test_verify_removes_corrupted_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        crate::write_sync(&dir, "other", b"other-data").unwrap();
+        fs::write(path::content_path(&dir, &sri), b"garbage").unwrap();
+
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.total_entries, 2);
+        assert_eq!(stats.verified_content, 1);
+        assert_eq!(stats.bad_content_count, 1);
+        assert_eq!(stats.reclaimed_count, 1);
+        assert_eq!(stats.rejected_entries, 1);
+
+        assert_eq!(crate::metadata_sync(&dir, "key").unwrap(), None);
+        assert_eq!(crate::read_sync(&dir, "other").unwrap(), b"other-data");
+    }
+
+    #[test]
+    fn test_verify_rejects_entries_with_missing_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        crate::remove_hash_sync(&dir, &sri).unwrap();
+
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.rejected_entries, 1);
+        assert_eq!(crate::metadata_sync(&dir, "key").unwrap(), None);
+    }
+
+    #[test]
+    fn test_verify_keeps_referenced_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let file = crate::write_hash_sync(&dir, b"file-data").unwrap();
+        let mut writer = crate::WriteOpts::new()
+            .references(vec![file.clone()])
+            .open_sync(&dir, "key")
+            .unwrap();
+        writer.write_all(b"my-data").unwrap();
+        writer.commit().unwrap();
+
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.verified_content, 2);
+        assert_eq!(stats.reclaimed_count, 0);
+        assert!(crate::exists_sync(&dir, &file));
+
+        crate::remove_sync(&dir, "key").unwrap();
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.reclaimed_count, 2);
+        assert!(!crate::exists_sync(&dir, &file));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_verify_counts_multi_hash_content_once() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = ssri::IntegrityOpts::new()
+            .algorithm(ssri::Algorithm::Sha256)
+            .algorithm(ssri::Algorithm::Sha512)
+            .chain(b"my-data")
+            .result();
+        let mut writer = crate::WriteOpts::new()
+            .integrity(sri.clone())
+            .open_sync(&dir, "key")
+            .unwrap();
+        writer.write_all(b"my-data").unwrap();
+        writer.commit().unwrap();
+        let size = fs::metadata(path::content_path(&dir, &sri)).unwrap().len();
+        crate::remove_sync(&dir, "key").unwrap();
+
+        // Both hashes are links to the same data, which only gets freed once.
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.reclaimed_count, 2);
+        assert_eq!(stats.reclaimed_size, size);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_verify_linked_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("cache");
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        fs::hard_link(path::content_path(&dir, &sri), tmp.path().join("linked")).unwrap();
+        crate::remove_sync(&dir, "key").unwrap();
+
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.reclaimed_count, 1);
+        // The data's still there, through the other link.
+        assert_eq!(stats.reclaimed_size, 0);
+        assert!(tmp.path().join("linked").exists());
+    }
+
+    #[test]
+    fn test_verify_keeps_unrecorded_raw_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::set_index_format_sync(&dir, crate::IndexFormat::Npm).unwrap();
+        crate::set_content_format_sync(&dir, crate::ContentFormat::Raw).unwrap();
+        crate::write_sync(&dir, "key", b"my-data").unwrap();
+        // npm writes raw content, but never records a format for it.
+        fs::remove_file(dir.join("content-format")).unwrap();
+
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.verified_content, 1);
+        assert_eq!(stats.bad_content_count, 0);
+        assert_eq!(stats.reclaimed_count, 0);
+        assert_eq!(crate::read_sync(&dir, "key").unwrap(), b"my-data");
+    }
+
+    #[test]
+    fn test_verify_empty_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let stats = crate::verify_sync(tmp.path()).unwrap();
+        assert_eq!(stats, crate::VerifyStats::default());
+    }
+}
@@ -1,4 +1,6 @@
-use oro_diagnostics::{Diagnostic, DiagnosticCategory, Explain};
+use std::path::PathBuf;
+
+use oro_diagnostics::{Diagnostic, DiagnosticCategory, Explain, Meta};
 use thiserror::Error;
 
 #[derive(Debug, Error, Diagnostic)]
@@ -17,6 +19,15 @@ pub enum NodeMaintainerError {
     #[label("node_maintainer::no_cwd")]
     NoCwd(#[from] std::io::Error),
 
+    #[error("Unknown layout mode `{0}`.")]
+    #[label("node_maintainer::invalid_layout")]
+    #[advice("Valid layout modes are `nested` and `isolated`.")]
+    InvalidLayout(String),
+
+    #[error("Failed to link package into `{}`. {0}", .1.display())]
+    #[label("node_maintainer::link")]
+    LinkError(#[source] std::io::Error, PathBuf),
+
     /// Error returned from Rogga
     #[error(transparent)]
     RoggaError(
@@ -26,4 +37,12 @@ pub enum NodeMaintainerError {
     ),
 }
 
-impl Explain for NodeMaintainerError {}
+impl Explain for NodeMaintainerError {
+    fn meta(&self) -> Option<Meta> {
+        use NodeMaintainerError::*;
+        match self {
+            LinkError(_, ref path) => Some(Meta::Fs { path: path.clone() }),
+            _ => None,
+        }
+    }
+}
@@ -1,13 +1,18 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use async_lock::Semaphore;
 use futures::{future, FutureExt};
 use oro_classic_resolver::ClassicResolver;
 use petgraph::dot::Dot;
 use petgraph::stable_graph::{NodeIndex, StableGraph};
-use rogga::{Package, PackageSpec, Rogga, RoggaOpts};
+use rogga::{
+    OroClientOpts, Package, PackageResolution, PackageSpec, RegistryAuth, Rogga, RoggaOpts,
+};
+use ssri::Integrity;
 use url::Url;
 
 pub use crate::error::NodeMaintainerError;
@@ -29,10 +34,55 @@ pub struct Dependency {
     pub dep_type: DepType,
 }
 
+/// Directory inside `node_modules` that holds the isolated package store.
+const STORE_DIR: &str = ".oro";
+
+/// How many packages get fetched at once, unless configured otherwise.
+const DEFAULT_CONCURRENCY: usize = 50;
+
+/// How packages get laid out on disk inside `node_modules`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutMode {
+    /// Every package gets its dependencies extracted into its own nested
+    /// `node_modules`, mirroring the resolved tree.
+    Nested,
+    /// Packages are extracted once into a virtual store at
+    /// `node_modules/.oro/<name>@<version>/node_modules/<name>`, and each
+    /// package only gets symlinks to its direct dependencies. Phantom
+    /// dependencies fail to resolve under this layout.
+    Isolated,
+}
+
+impl Default for LayoutMode {
+    fn default() -> Self {
+        LayoutMode::Nested
+    }
+}
+
+impl FromStr for LayoutMode {
+    type Err = NodeMaintainerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nested" => Ok(LayoutMode::Nested),
+            "isolated" => Ok(LayoutMode::Isolated),
+            _ => Err(NodeMaintainerError::InvalidLayout(s.into())),
+        }
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct NodeMaintainerOptions {
     registry: Option<Url>,
+    scope_registries: Vec<(String, Url)>,
     path: Option<PathBuf>,
+    cache: Option<PathBuf>,
+    remote_cache: Option<Url>,
+    layout: LayoutMode,
+    client_opts: OroClientOpts,
+    registry_auth: Vec<(String, RegistryAuth)>,
+    always_auth: Vec<String>,
+    concurrency: Option<usize>,
 }
 
 impl NodeMaintainerOptions {
@@ -45,23 +95,92 @@ impl NodeMaintainerOptions {
         self
     }
 
+    /// Registry to fetch packages in `scope` from instead of the main one.
+    pub fn scope_registry(mut self, scope: impl AsRef<str>, registry: Url) -> Self {
+        self.scope_registries
+            .push((scope.as_ref().into(), registry));
+        self
+    }
+
     pub fn path(mut self, path: impl AsRef<Path>) -> Self {
         self.path = Some(path.as_ref().into());
         self
     }
 
+    /// Package cache to extract files through. Extracted files are linked
+    /// from the cache into `node_modules` whenever possible.
+    pub fn cache(mut self, cache: impl AsRef<Path>) -> Self {
+        self.cache = Some(cache.as_ref().into());
+        self
+    }
+
+    /// Shared HTTP cache to fetch package tarballs from before going to the
+    /// registry, and to upload fetched tarballs to. Needs `cache` as well.
+    pub fn remote_cache(mut self, remote_cache: Url) -> Self {
+        self.remote_cache = Some(remote_cache);
+        self
+    }
+
+    pub fn layout(mut self, layout: LayoutMode) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Options for the HTTP client used to talk to registries. See
+    /// [`RoggaOpts::client_opts`].
+    pub fn client_opts(mut self, client_opts: OroClientOpts) -> Self {
+        self.client_opts = client_opts;
+        self
+    }
+
+    /// Credentials to send with requests to any URL starting with `prefix`.
+    /// See [`RoggaOpts::registry_auth`].
+    pub fn registry_auth(mut self, prefix: impl AsRef<str>, auth: RegistryAuth) -> Self {
+        self.registry_auth.push((prefix.as_ref().into(), auth));
+        self
+    }
+
+    /// See [`RoggaOpts::always_auth`].
+    pub fn always_auth(mut self, prefix: impl AsRef<str>) -> Self {
+        self.always_auth.push(prefix.as_ref().into());
+        self
+    }
+
+    /// How many packages to fetch at once, whether that's resolving them or
+    /// extracting them. Defaults to 50.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = Some(concurrency);
+        self
+    }
+
     pub async fn init(
         self,
         request: impl AsRef<str>,
     ) -> Result<NodeMaintainer, NodeMaintainerError> {
-        let rogga = RoggaOpts::new()
+        let mut rogga_opts = RoggaOpts::new()
             .use_corgi(true)
+            .client_opts(self.client_opts)
             .add_registry(
                 "",
                 self.registry
                     .unwrap_or_else(|| Url::parse("https://registry.npmjs.org").unwrap()),
-            )
-            .build();
+            );
+        for (scope, registry) in self.scope_registries {
+            rogga_opts = rogga_opts.add_registry(scope, registry);
+        }
+        if let Some(cache) = self.cache {
+            rogga_opts = rogga_opts.cache(cache);
+        }
+        if let Some(remote_cache) = self.remote_cache {
+            rogga_opts = rogga_opts.remote_cache(remote_cache);
+        }
+        for (prefix, auth) in self.registry_auth {
+            rogga_opts = rogga_opts.registry_auth(prefix, auth);
+        }
+        for prefix in self.always_auth {
+            rogga_opts = rogga_opts.always_auth(prefix);
+        }
+        let rogga = rogga_opts.build();
         let mut graph = StableGraph::new();
         let current_dir = env::current_dir().map_err(NodeMaintainerError::NoCwd)?;
         let cwd = self.path.unwrap_or(current_dir);
@@ -74,20 +193,25 @@ impl NodeMaintainerOptions {
         let root = graph.add_node(root_dep);
         Ok(NodeMaintainer {
             cwd,
+            layout: self.layout,
             rogga,
             resolver,
             root,
             graph,
+            fetches: Semaphore::new(self.concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1)),
         })
     }
 }
 
 pub struct NodeMaintainer {
     cwd: PathBuf,
+    layout: LayoutMode,
     rogga: Rogga,
     resolver: ClassicResolver,
     root: NodeIndex,
     graph: StableGraph<Package, Dependency>,
+    /// Bounds how many packages are being fetched at once.
+    fetches: Semaphore,
 }
 
 impl NodeMaintainer {
@@ -131,11 +255,12 @@ impl NodeMaintainer {
                 if !names.contains(&name[..]) {
                     names.insert(&name[..]);
                     let request = self.rogga.dep_request(&name[..], &spec[..], &self.cwd)?;
-                    packages.push(
-                        request
-                            .resolve_with(&self.resolver)
-                            .map(|pkg| (pkg, dep_type)),
-                    );
+                    let resolver = &self.resolver;
+                    let fetches = &self.fetches;
+                    packages.push(async move {
+                        let _permit = fetches.acquire().await;
+                        (request.resolve_with(resolver).await, dep_type)
+                    });
                 }
             }
             for (package, dep_type) in future::join_all(packages.drain(..)).await {
@@ -155,4 +280,307 @@ impl NodeMaintainer {
         }
         Ok(())
     }
+
+    /// Writes the resolved tree out to `node_modules`, using the configured
+    /// `LayoutMode`.
+    pub async fn extract(&self) -> Result<(), NodeMaintainerError> {
+        match self.layout {
+            LayoutMode::Nested => {
+                self.extract_nested(self.root, self.cwd.join("node_modules"))
+                    .await
+            }
+            LayoutMode::Isolated => self.extract_isolated().await,
+        }
+    }
+
+    fn extract_nested<'a>(
+        &'a self,
+        package_idx: NodeIndex,
+        node_modules: PathBuf,
+    ) -> future::BoxFuture<'a, Result<(), NodeMaintainerError>> {
+        async move {
+            let mut futs = Vec::new();
+            for child_idx in self.graph.neighbors(package_idx) {
+                let child = &self.graph[child_idx];
+                let dir = node_modules.join(child.name());
+                futs.push(
+                    async move {
+                        self.extract_package(child, &dir).await?;
+                        self.extract_nested(child_idx, dir.join("node_modules"))
+                            .await
+                    }
+                    .boxed(),
+                );
+            }
+            future::try_join_all(futs).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    async fn extract_isolated(&self) -> Result<(), NodeMaintainerError> {
+        let node_modules = self.cwd.join("node_modules");
+        let store = node_modules.join(STORE_DIR);
+
+        // Multiple nodes can resolve to the same name@version. Each of those
+        // only gets extracted into the store once.
+        let mut store_ids = HashMap::new();
+        let mut to_extract = HashMap::new();
+        for idx in self.graph.node_indices().filter(|idx| *idx != self.root) {
+            let package = &self.graph[idx];
+            let id = store_id(package).await?;
+            to_extract.entry(id.clone()).or_insert(idx);
+            store_ids.insert(idx, id);
+        }
+        future::try_join_all(to_extract.iter().map(|(id, idx)| {
+            let package = &self.graph[*idx];
+            let dir = store.join(id).join("node_modules").join(package.name());
+            async move { self.extract_package(package, &dir).await }
+        }))
+        .await?;
+
+        // Now hook everything up: each package only gets to see its direct
+        // dependencies, as symlinks into the store.
+        for idx in self.graph.node_indices() {
+            let (parent_nm, depth) = if idx == self.root {
+                (node_modules.clone(), 0)
+            } else {
+                (store.join(&store_ids[&idx]).join("node_modules"), 2)
+            };
+            for child_idx in self.graph.neighbors(idx) {
+                let child = &self.graph[child_idx];
+                let link = parent_nm.join(child.name());
+                let mut target = PathBuf::new();
+                // Scoped packages live one directory further down.
+                for _ in 0..(depth + child.name().matches('/').count()) {
+                    target.push("..");
+                }
+                if idx == self.root {
+                    target.push(STORE_DIR);
+                }
+                target.push(&store_ids[&child_idx]);
+                target.push("node_modules");
+                target.push(child.name());
+                link_dir(&target, &link)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn extract_package(
+        &self,
+        package: &Package,
+        dir: &Path,
+    ) -> Result<(), NodeMaintainerError> {
+        if let PackageResolution::Dir { path } = package.resolved() {
+            // Local directories are linked in as-is, instead of copied.
+            link_dir(path, dir)?;
+        } else {
+            let _permit = self.fetches.acquire().await;
+            package.extract_to_dir(dir).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Unique, filesystem-safe identifier for a package inside the isolated
+/// store, in the form of `<name>@<version>`, with `/` in scoped names
+/// replaced by `+`. Directory and git dependencies can share a name@version
+/// with something else entirely, so theirs also get a hash of the path or
+/// git spec they were resolved from tacked on.
+async fn store_id(package: &Package) -> Result<String, NodeMaintainerError> {
+    let name = package.name().replace('/', "+");
+    let source = match package.resolved() {
+        PackageResolution::Npm { version, .. } => return Ok(format!("{}@{}", name, version)),
+        PackageResolution::Dir { path } => path.display().to_string(),
+        PackageResolution::Git(info) => info.to_string(),
+    };
+    let version = package
+        .metadata()
+        .await?
+        .manifest
+        .version
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "0.0.0".into());
+    Ok(format!("{}@{}+{}", name, version, source_hash(&source)))
+}
+
+fn source_hash(source: &str) -> String {
+    let (_, hex) = Integrity::from(source).to_hex();
+    hex[..16].into()
+}
+
+fn link_dir(target: &Path, link: &Path) -> Result<(), NodeMaintainerError> {
+    if let Some(parent) = link.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| NodeMaintainerError::LinkError(e, link.to_owned()))?;
+    }
+    if fs::symlink_metadata(link).is_ok() {
+        // Windows treats directory symlinks and junctions as directories.
+        #[cfg(unix)]
+        let res = fs::remove_file(link);
+        #[cfg(windows)]
+        let res = fs::remove_dir(link);
+        res.map_err(|e| NodeMaintainerError::LinkError(e, link.to_owned()))?;
+    }
+    #[cfg(unix)]
+    let res = std::os::unix::fs::symlink(target, link);
+    #[cfg(windows)]
+    let res = std::os::windows::fs::symlink_dir(target, link).or_else(|e| {
+        // Symlinks need Developer Mode or admin rights on Windows, so fall
+        // back to a junction, which doesn't. Junctions can't be relative.
+        let absolute = match link.parent() {
+            Some(parent) => parent.join(target),
+            None => target.to_owned(),
+        };
+        junction::create(&absolute, link).map_err(|_| e)
+    });
+    res.map_err(|e| NodeMaintainerError::LinkError(e, link.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_from_str() {
+        assert_eq!("nested".parse::<LayoutMode>().unwrap(), LayoutMode::Nested);
+        assert_eq!(
+            "isolated".parse::<LayoutMode>().unwrap(),
+            LayoutMode::Isolated
+        );
+        assert!("hoisted".parse::<LayoutMode>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn link_dir_replaces_existing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("target");
+        let link = tmp.path().join("node_modules").join("@scope").join("pkg");
+        fs::create_dir_all(&target).unwrap();
+        link_dir(&target, &link).unwrap();
+        link_dir(&target, &link).unwrap();
+        assert_eq!(fs::read_link(&link).unwrap(), target);
+    }
+
+    #[cfg(unix)]
+    fn write_package(dir: &Path, manifest: &str) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("package.json"), manifest).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn isolated_layout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        write_package(
+            &root,
+            r#"{"name":"root","version":"1.0.0","dependencies":{"a":"file:./a","@scope/b":"file:./b"}}"#,
+        );
+        write_package(
+            &root.join("a"),
+            r#"{"name":"a","version":"1.0.0","dependencies":{"@scope/b":"file:./b"}}"#,
+        );
+        write_package(&root.join("b"), r#"{"name":"@scope/b","version":"2.0.0"}"#);
+
+        let mut nm = NodeMaintainerOptions::new()
+            .path(&root)
+            .layout(LayoutMode::Isolated)
+            .init(root.display().to_string())
+            .await
+            .unwrap();
+        nm.resolve().await.unwrap();
+        nm.extract().await.unwrap();
+
+        let node_modules = root.join("node_modules");
+        let store = node_modules.join(STORE_DIR);
+        let a_id = format!(
+            "a@1.0.0+{}",
+            source_hash(&root.join("a").display().to_string())
+        );
+        let b_id = format!(
+            "@scope+b@2.0.0+{}",
+            source_hash(&root.join("b").display().to_string())
+        );
+        // Packages land in the store once, under their escaped names.
+        assert_eq!(
+            fs::read_link(store.join(&a_id).join("node_modules/a")).unwrap(),
+            root.join("a")
+        );
+        assert_eq!(
+            fs::read_link(store.join(&b_id).join("node_modules/@scope/b")).unwrap(),
+            root.join("b")
+        );
+        // The root and every store package get relative links to their own
+        // direct dependencies only.
+        assert_eq!(
+            fs::read_link(node_modules.join("a")).unwrap(),
+            Path::new(".oro").join(&a_id).join("node_modules/a")
+        );
+        assert_eq!(
+            fs::read_link(node_modules.join("@scope/b")).unwrap(),
+            Path::new("../.oro")
+                .join(&b_id)
+                .join("node_modules/@scope/b")
+        );
+        assert_eq!(
+            fs::read_link(store.join(&a_id).join("node_modules/@scope/b")).unwrap(),
+            Path::new("../../..")
+                .join(&b_id)
+                .join("node_modules/@scope/b")
+        );
+        assert!(node_modules.join("a/package.json").exists());
+        assert!(node_modules.join("@scope/b/package.json").exists());
+        assert!(store
+            .join(&a_id)
+            .join("node_modules/@scope/b/package.json")
+            .exists());
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn isolated_layout_keeps_same_named_dirs_apart() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        write_package(
+            &root,
+            r#"{"name":"root","version":"1.0.0","dependencies":{"a":"file:./a","c":"file:./c2"}}"#,
+        );
+        write_package(
+            &root.join("a"),
+            r#"{"name":"a","version":"1.0.0","dependencies":{"c":"file:./c1"}}"#,
+        );
+        write_package(&root.join("c1"), r#"{"name":"c","version":"1.0.0"}"#);
+        write_package(&root.join("c2"), r#"{"name":"c","version":"1.0.0"}"#);
+
+        let mut nm = NodeMaintainerOptions::new()
+            .path(&root)
+            .layout(LayoutMode::Isolated)
+            .init(root.display().to_string())
+            .await
+            .unwrap();
+        nm.resolve().await.unwrap();
+        nm.extract().await.unwrap();
+
+        let node_modules = root.join("node_modules");
+        let store = node_modules.join(STORE_DIR);
+        let a_id = format!(
+            "a@1.0.0+{}",
+            source_hash(&root.join("a").display().to_string())
+        );
+        assert_eq!(
+            node_modules.join("c").canonicalize().unwrap(),
+            root.join("c2")
+        );
+        assert_eq!(
+            store
+                .join(&a_id)
+                .join("node_modules/c")
+                .canonicalize()
+                .unwrap(),
+            root.join("c1")
+        );
+    }
 }
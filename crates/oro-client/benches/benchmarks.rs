@@ -0,0 +1,106 @@
+use async_std::net::TcpListener;
+use async_std::prelude::*;
+use async_std::task;
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::future::join_all;
+use http_types::{Response, StatusCode};
+use oro_client::{Method, OroClient, OroClientOpts, Url};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+// Roughly a small packument, and a medium-sized tarball.
+const PACKUMENT_SIZE: usize = 16 * 1024;
+const TARBALL_SIZE: usize = 1024 * 1024;
+
+const NUM_PACKUMENTS: usize = 100;
+const NUM_TARBALLS: usize = 10;
+
+/// Starts a local HTTP/1.1 server that answers every request with `size`
+/// bytes.
+async fn serve_h1(size: usize) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    task::spawn(async move {
+        let mut incoming = listener.incoming();
+        while let Some(Ok(stream)) = incoming.next().await {
+            task::spawn(async_h1::accept(stream, move |_req| async move {
+                let mut res = Response::new(StatusCode::Ok);
+                res.set_body(vec![b'x'; size]);
+                Ok(res)
+            }));
+        }
+    });
+    url
+}
+
+/// Starts a local HTTP/2 server, without TLS, that answers every request
+/// with `size` bytes.
+async fn serve_h2(size: usize) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let body = Bytes::from(vec![b'x'; size]);
+    task::spawn(async move {
+        let mut incoming = listener.incoming();
+        while let Some(Ok(stream)) = incoming.next().await {
+            let body = body.clone();
+            task::spawn(async move {
+                let mut conn = h2::server::handshake(stream.compat()).await.unwrap();
+                while let Some(Ok((_req, mut respond))) = conn.accept().await {
+                    let res = http::Response::builder()
+                        .header("content-length", body.len())
+                        .body(())
+                        .unwrap();
+                    let mut stream = respond.send_response(res, false).unwrap();
+                    // h2 holds on to whatever the client's flow control
+                    // window doesn't have room for yet, and sends it later.
+                    stream.send_data(body.clone(), true).unwrap();
+                }
+            });
+        }
+    });
+    url
+}
+
+/// Fetches `count` URLs from `url` all at once, reading every body in full.
+async fn fetch_all(client: &OroClient, url: &Url, count: usize) {
+    let requests = (0..count).map(|i| {
+        let url = url.join(&format!("pkg-{}", i)).unwrap();
+        async move {
+            let mut res = client.send(client.opts(Method::Get, url)).await.unwrap();
+            res.body_bytes().await.unwrap()
+        }
+    });
+    join_all(requests).await;
+}
+
+fn bench_fetch(c: &mut Criterion, name: &str, size: usize, count: usize) {
+    let h1_url = task::block_on(serve_h1(size));
+    let h1_client = OroClientOpts::new().no_proxy("*").build();
+    c.bench_function(&format!("{}_h1", name), |b| {
+        b.iter(|| task::block_on(fetch_all(&h1_client, &h1_url, count)))
+    });
+
+    let h2_url = task::block_on(serve_h2(size));
+    let h2_client = OroClientOpts::new()
+        .no_proxy("*")
+        .http2_prior_knowledge(true)
+        .build();
+    c.bench_function(&format!("{}_h2", name), |b| {
+        b.iter(|| task::block_on(fetch_all(&h2_client, &h2_url, count)))
+    });
+}
+
+fn packuments(c: &mut Criterion) {
+    bench_fetch(c, "packuments", PACKUMENT_SIZE, NUM_PACKUMENTS);
+}
+
+fn tarballs(c: &mut Criterion) {
+    bench_fetch(c, "tarballs", TARBALL_SIZE, NUM_TARBALLS);
+}
+
+criterion_group!(benches, packuments, tarballs);
+criterion_main!(benches);
@@ -0,0 +1,120 @@
+use async_compression::futures::bufread::{BrotliDecoder, GzipDecoder};
+use futures::io::BufReader;
+use surf::http::{Body, Request};
+use surf::Response;
+
+/// The encodings we know how to decode, as sent in `Accept-Encoding`.
+pub(crate) const ACCEPT_ENCODING: &str = "gzip, br";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "br" => Some(Encoding::Brotli),
+            _ => None,
+        }
+    }
+}
+
+/// The encodings `req` asked for in its `Accept-Encoding` header that we're
+/// able to decode.
+pub(crate) fn accepted(req: &Request) -> Vec<Encoding> {
+    req.header("Accept-Encoding")
+        .into_iter()
+        .flat_map(|values| values.iter())
+        .flat_map(|value| value.as_str().split(','))
+        // Drop any quality value, like the `;q=0.8` in `gzip;q=0.8`.
+        .filter_map(|name| Encoding::parse(name.split(';').next().unwrap_or("")))
+        .collect()
+}
+
+/// Decodes `res`'s body on the fly if it was compressed with one of the
+/// `accepted` encodings. Anything else, including responses that were never
+/// asked to be compressed, is passed through as-is.
+pub(crate) fn decode(mut res: Response, accepted: &[Encoding]) -> Response {
+    let encoding = match res
+        .header("Content-Encoding")
+        .and_then(|values| Encoding::parse(values.last().as_str()))
+    {
+        Some(encoding) if accepted.contains(&encoding) => encoding,
+        _ => return res,
+    };
+    // The body no longer matches either of these once it's decoded.
+    res.remove_header("Content-Encoding");
+    res.remove_header("Content-Length");
+    let body = res.take_body();
+    res.set_body(match encoding {
+        Encoding::Gzip => Body::from_reader(BufReader::new(GzipDecoder::new(body)), None),
+        Encoding::Brotli => Body::from_reader(BufReader::new(BrotliDecoder::new(body)), None),
+    });
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_compression::futures::bufread::{BrotliEncoder, GzipEncoder};
+    use futures::io::AsyncReadExt;
+    use surf::http::{self, Method, StatusCode};
+
+    const PACKUMENT: &str = r#"{"name":"oro","versions":{}}"#;
+
+    async fn encode(encoding: Encoding) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        match encoding {
+            Encoding::Gzip => GzipEncoder::new(PACKUMENT.as_bytes())
+                .read_to_end(&mut encoded)
+                .await
+                .unwrap(),
+            Encoding::Brotli => BrotliEncoder::new(PACKUMENT.as_bytes())
+                .read_to_end(&mut encoded)
+                .await
+                .unwrap(),
+        };
+        encoded
+    }
+
+    fn response(content_encoding: &str, body: Vec<u8>) -> Response {
+        let mut res = http::Response::new(StatusCode::Ok);
+        res.insert_header("Content-Encoding", content_encoding);
+        res.set_body(body);
+        res.into()
+    }
+
+    #[test]
+    fn parses_accept_encoding() {
+        let mut req = Request::new(Method::Get, "https://registry.example.com/oro");
+        assert!(accepted(&req).is_empty());
+        req.insert_header("Accept-Encoding", "GZIP;q=0.8, deflate, br");
+        assert_eq!(accepted(&req), vec![Encoding::Gzip, Encoding::Brotli]);
+    }
+
+    #[async_std::test]
+    async fn decodes_accepted_encodings() {
+        for (name, encoding) in &[("gzip", Encoding::Gzip), ("br", Encoding::Brotli)] {
+            let res = response(name, encode(*encoding).await);
+            let mut res = decode(res, &[Encoding::Gzip, Encoding::Brotli]);
+            assert!(res.header("Content-Encoding").is_none());
+            assert_eq!(res.body_string().await.unwrap(), PACKUMENT);
+        }
+    }
+
+    #[async_std::test]
+    async fn leaves_other_responses_alone() {
+        let gzipped = encode(Encoding::Gzip).await;
+        // Like a tarball, which was never asked to be decoded.
+        let mut res = decode(response("gzip", gzipped.clone()), &[]);
+        assert!(res.header("Content-Encoding").is_some());
+        assert_eq!(res.body_bytes().await.unwrap(), gzipped);
+
+        let mut res = decode(response("deflate", b"deflated".to_vec()), &[Encoding::Gzip]);
+        assert_eq!(res.body_bytes().await.unwrap(), b"deflated");
+    }
+}
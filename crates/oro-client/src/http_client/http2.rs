@@ -0,0 +1,319 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::sync::Mutex;
+use async_std::task;
+use bytes::Bytes;
+use futures::io::{AsyncRead, AsyncWrite, BufReader};
+use futures::task::{Context, Poll, Waker};
+use h2::client::SendRequest;
+use h2::RecvStream;
+use http_types::{Body, StatusCode, Version};
+use native_tls::{HandshakeError, TlsStream};
+use surf::http::{Request, Response};
+use surf::Error;
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+use super::timeout::ReadTimer;
+use super::tls::TlsConfig;
+use super::Permits;
+
+/// Headers that only make sense for HTTP/1.1 connections, which HTTP/2
+/// servers reject outright.
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "host",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// What we know about how to talk to a host.
+#[derive(Clone)]
+pub(crate) enum Protocol {
+    /// The host didn't agree to HTTP/2, so requests go through the HTTP/1.1
+    /// pool instead.
+    Http1,
+    /// An open HTTP/2 connection, which every request to the host is
+    /// multiplexed over.
+    Http2(SendRequest<Bytes>),
+}
+
+/// A host's [`Protocol`], or `None` if we haven't connected to it yet or its
+/// HTTP/2 connection has closed.
+pub(crate) type Slot = Arc<Mutex<Option<Protocol>>>;
+
+/// Does a TLS handshake that offers HTTP/2 through ALPN. Returns `None` if
+/// the server picked HTTP/1.1 instead, in which case the connection is
+/// thrown away and the host should go through the HTTP/1.1 pool.
+pub(crate) async fn connect_alpn<S>(
+    tls: &TlsConfig,
+    host: &str,
+    stream: S,
+) -> io::Result<Option<AlpnStream<S>>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut builder = tls.builder();
+    builder.request_alpns(&["h2", "http/1.1"]);
+    let connector = builder.build().map_err(other)?;
+    let mut raw = Some(StdAdapter {
+        inner: stream,
+        waker: None,
+    });
+    let mut mid_handshake = None;
+    let stream = futures::future::poll_fn(|cx| {
+        let result = match mid_handshake.take() {
+            Some(mut mid) => {
+                mid.get_mut().set_waker(cx);
+                mid.handshake()
+            }
+            None => {
+                let mut raw = raw.take().expect("handshake polled after completion");
+                raw.set_waker(cx);
+                connector.connect(host, raw)
+            }
+        };
+        match result {
+            Ok(stream) => Poll::Ready(Ok(stream)),
+            Err(HandshakeError::WouldBlock(mid)) => {
+                mid_handshake = Some(mid);
+                Poll::Pending
+            }
+            Err(HandshakeError::Failure(e)) => Poll::Ready(Err(other(e))),
+        }
+    })
+    .await?;
+    let alpn = stream.negotiated_alpn().map_err(other)?;
+    if alpn.as_deref() == Some(b"h2") {
+        Ok(Some(AlpnStream(stream)))
+    } else {
+        log::trace!("{} doesn't support HTTP/2, falling back to HTTP/1.1", host);
+        Ok(None)
+    }
+}
+
+/// Starts an HTTP/2 connection over `io`. The connection is driven in the
+/// background until it closes, at which point `slot` is cleared so the next
+/// request reconnects.
+pub(crate) async fn start<S>(io: S, slot: Slot) -> Result<SendRequest<Bytes>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, connection) = h2::client::handshake(io.compat()).await.map_err(h2_error)?;
+    task::spawn(async move {
+        if let Err(e) = connection.await {
+            log::debug!("HTTP/2 connection closed: {}", e);
+        }
+        slot.lock().await.take();
+    });
+    Ok(sender)
+}
+
+/// Sends `req` as a new stream on an HTTP/2 connection.
+pub(crate) async fn send(
+    sender: SendRequest<Bytes>,
+    mut req: Request,
+    read_timeout: Option<Duration>,
+    permits: Permits,
+) -> Result<Response, Error> {
+    let body = req.take_body().into_bytes().await?;
+    let method = req.method().to_string();
+    let mut head = http::Request::builder()
+        .method(method.as_str())
+        .uri(req.url().as_str());
+    for (name, values) in req.iter() {
+        if CONNECTION_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        for value in values.iter() {
+            head = head.header(name.as_str(), value.as_str());
+        }
+    }
+    let head = head
+        .body(())
+        .map_err(|e| Error::new(StatusCode::BadRequest, e))?;
+
+    let mut sender = sender.ready().await.map_err(h2_error)?;
+    let (response, mut stream) = sender
+        .send_request(head, body.is_empty())
+        .map_err(h2_error)?;
+    if !body.is_empty() {
+        stream.send_data(body.into(), true).map_err(h2_error)?;
+    }
+    let (parts, stream) = response.await.map_err(h2_error)?.into_parts();
+
+    let status = StatusCode::try_from(parts.status.as_u16()).map_err(|_| {
+        Error::from_str(
+            StatusCode::BadGateway,
+            format!("unknown status code {}", parts.status),
+        )
+    })?;
+    let mut res = Response::new(status);
+    res.set_version(Some(Version::Http2_0));
+    for (name, value) in parts.headers.iter() {
+        // http-types only takes printable ASCII, so anything else is dropped.
+        if let Ok(value) = value.to_str() {
+            res.append_header(name.as_str(), value);
+        }
+    }
+    let len = parts
+        .headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse().ok());
+    let body = RecvBody {
+        stream,
+        chunk: Bytes::new(),
+        timer: ReadTimer::new(read_timeout),
+        _permits: permits,
+    };
+    res.set_body(Body::from_reader(BufReader::new(body), len));
+    Ok(res)
+}
+
+/// Errors from HTTP/2 connections and streams are all treated as gateway
+/// errors, so they get retried like any other connection failure.
+fn h2_error(err: h2::Error) -> Error {
+    Error::new(StatusCode::BadGateway, err)
+}
+
+fn other(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+/// A response body being streamed in over HTTP/2.
+struct RecvBody {
+    stream: RecvStream,
+    chunk: Bytes,
+    timer: ReadTimer,
+    // Counts against the client's connection limits until dropped.
+    _permits: Permits,
+}
+
+impl RecvBody {
+    fn poll_chunk(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        while self.chunk.is_empty() {
+            match futures::ready!(self.stream.poll_data(cx)) {
+                Some(Ok(chunk)) => self.chunk = chunk,
+                Some(Err(e)) => return Poll::Ready(Err(other(e))),
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        // Only let the server send more once we've actually used up what
+        // it's sent so far, so slow readers don't pile up data in memory.
+        let _ = self.stream.flow_control().release_capacity(len);
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl AsyncRead for RecvBody {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let read = this.poll_chunk(cx, buf);
+        this.timer.poll(cx, read)
+    }
+}
+
+/// A TLS stream that, unlike async-native-tls's, can tell us which protocol
+/// got negotiated through ALPN.
+pub(crate) struct AlpnStream<S>(TlsStream<StdAdapter<S>>);
+
+impl<S> AsyncRead for AlpnStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.0.get_mut().set_waker(cx);
+        would_block(self.0.read(buf))
+    }
+}
+
+impl<S> AsyncWrite for AlpnStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.0.get_mut().set_waker(cx);
+        would_block(self.0.write(buf))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.get_mut().set_waker(cx);
+        would_block(self.0.flush())
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.get_mut().set_waker(cx);
+        would_block(self.0.shutdown())
+    }
+}
+
+/// Lets native-tls, which only knows about blocking I/O, drive an async
+/// stream. Pending reads and writes show up as `WouldBlock` errors, and wake
+/// up whichever task last polled the stream.
+pub(crate) struct StdAdapter<S> {
+    inner: S,
+    waker: Option<Waker>,
+}
+
+impl<S: Unpin> StdAdapter<S> {
+    fn set_waker(&mut self, cx: &Context<'_>) {
+        match &self.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => self.waker = Some(cx.waker().clone()),
+        }
+    }
+
+    fn poll<T>(
+        &mut self,
+        f: impl FnOnce(Pin<&mut S>, &mut Context<'_>) -> Poll<io::Result<T>>,
+    ) -> io::Result<T> {
+        let waker = self.waker.as_ref().expect("stream used outside of a poll");
+        match f(Pin::new(&mut self.inner), &mut Context::from_waker(waker)) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> Read for StdAdapter<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.poll(|inner, cx| inner.poll_read(cx, buf))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Write for StdAdapter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.poll(|inner, cx| inner.poll_write(cx, buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.poll(|inner, cx| inner.poll_flush(cx))
+    }
+}
+
+fn would_block<T>(result: io::Result<T>) -> Poll<io::Result<T>> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+        result => Poll::Ready(result),
+    }
+}
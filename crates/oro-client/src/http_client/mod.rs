@@ -1,35 +1,73 @@
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::future::Future;
+use std::time::Duration;
 use std::{fmt::Debug, sync::Arc};
 
 use async_h1::client;
+use async_lock::{Semaphore, SemaphoreGuardArc};
 use async_native_tls::TlsStream;
 use async_std::net::TcpStream;
 use async_std::sync::Mutex;
 use async_trait::async_trait;
-use deadpool::managed::Pool;
+use bytes::Bytes;
+use deadpool::managed::{Pool, PoolError, TimeoutType};
+use futures::io::{AsyncRead, AsyncWrite};
+use h2::client::SendRequest;
 use http_types::StatusCode;
 use surf::http::{Request, Response};
 use surf::{Error, HttpClient};
 
+use http2::Protocol;
+use proxy::{AbsoluteForm, Proxies};
 use tcp::{TcpConnWrapper, TcpConnection};
 use tls::{TlsConnWrapper, TlsConnection};
 
+pub(crate) use tls::TlsConfig;
+
+pub(crate) use timeout::TimedOut;
+
+mod http2;
+pub(crate) mod proxy;
 mod tcp;
+pub(crate) mod timeout;
 mod tls;
 
-// TODO: Move this to a parameter. This current number is based on a few
-// random benchmarks and see whatever gave decent perf vs resource use.
-static MAX_CONCURRENT_CONNECTIONS: usize = 50;
+// This number is based on a few random benchmarks and seeing whatever gave
+// decent perf vs resource use.
+const DEFAULT_MAX_CONNECTIONS_PER_HOST: usize = 50;
 
-type HttpPool = HashMap<SocketAddr, Pool<TcpStream, std::io::Error>>;
-type HttpsPool = HashMap<SocketAddr, Pool<TlsStream<TcpStream>, Error>>;
+// Pools are keyed by the `host:port` they connect to.
+type HttpPool = HashMap<String, Pool<TcpStream, std::io::Error>>;
+type HttpsPool = HashMap<String, Pool<TlsStream<TcpStream>, Error>>;
+// HTTP/2 connections are keyed by `scheme://host:port`.
+type Http2Slots = HashMap<String, http2::Slot>;
+// Per-host request limits are keyed by the `host:port` requests are for,
+// whether or not they go through a proxy.
+type HostLimits = HashMap<String, Arc<Semaphore>>;
 
-/// Async-h1 based connection-pooling HTTP client.
+/// The slots a request holds under the client's connection limits. They're
+/// released once the response body is dropped.
+pub(crate) struct Permits {
+    _host: SemaphoreGuardArc,
+    _global: Option<SemaphoreGuardArc>,
+}
+
+/// Async-h1 based connection-pooling HTTP client. HTTPS hosts that support
+/// HTTP/2 get a single multiplexed HTTP/2 connection instead.
 #[derive(Clone)]
 pub struct PoolingClient {
     http_pool: Arc<Mutex<HttpPool>>,
     https_pool: Arc<Mutex<HttpsPool>>,
+    http2_slots: Arc<Mutex<Http2Slots>>,
+    host_limits: Arc<Mutex<HostLimits>>,
+    http2: bool,
+    http2_prior_knowledge: bool,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    max_connections_per_host: usize,
+    connections: Option<Arc<Semaphore>>,
+    proxies: Proxies,
+    tls: Arc<TlsConfig>,
 }
 
 impl Debug for PoolingClient {
@@ -50,7 +88,139 @@ impl PoolingClient {
         Self {
             http_pool: Arc::new(Mutex::new(HashMap::new())),
             https_pool: Arc::new(Mutex::new(HashMap::new())),
+            http2_slots: Arc::new(Mutex::new(HashMap::new())),
+            host_limits: Arc::new(Mutex::new(HashMap::new())),
+            http2: true,
+            http2_prior_knowledge: false,
+            connect_timeout: None,
+            read_timeout: None,
+            max_connections_per_host: DEFAULT_MAX_CONNECTIONS_PER_HOST,
+            connections: None,
+            proxies: Proxies::default(),
+            tls: Arc::new(TlsConfig::default()),
+        }
+    }
+
+    /// Limit on how long opening a new connection can take, including the
+    /// TLS handshake.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Limit on how long a connection can go without sending any data while
+    /// we're waiting on it.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Limit on how many connections can be in use at once, across all
+    /// hosts. Unlimited by default.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.connections = Some(Arc::new(Semaphore::new(max.max(1))));
+        self
+    }
+
+    /// Limit on how many requests can be in flight to any one host, and how
+    /// many HTTP/1.1 connections can be open to it. With HTTP/2, this caps
+    /// the streams multiplexed over the host's connection. Defaults to 50.
+    pub fn max_connections_per_host(mut self, max: usize) -> Self {
+        self.max_connections_per_host = max.max(1);
+        self
+    }
+
+    /// Whether to offer HTTP/2 to HTTPS hosts during the TLS handshake.
+    /// Hosts that don't take it up are sent HTTP/1.1 requests as usual.
+    /// Defaults to true.
+    pub fn http2(mut self, http2: bool) -> Self {
+        self.http2 = http2;
+        self
+    }
+
+    /// Whether to assume plain HTTP hosts speak HTTP/2, skipping HTTP/1.1
+    /// entirely. There's no way to negotiate this without TLS, so it's only
+    /// for servers known to support it. Not used with proxies. Defaults to
+    /// false.
+    pub fn http2_prior_knowledge(mut self, prior_knowledge: bool) -> Self {
+        self.http2_prior_knowledge = prior_knowledge;
+        self
+    }
+
+    /// Finds the HTTP/2 connection for `key`, opening one with `connect` if
+    /// there isn't one yet. `connect` returns `None` for hosts that turn out
+    /// not to speak HTTP/2, which get `None` from then on.
+    async fn http2_sender<S>(
+        &self,
+        key: String,
+        connect: impl Future<Output = std::io::Result<Option<S>>>,
+    ) -> Result<Option<SendRequest<Bytes>>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let slot = self
+            .http2_slots
+            .lock()
+            .await
+            .entry(key)
+            .or_insert_with(|| Arc::new(Mutex::new(None)))
+            .clone();
+        // Holding the lock while connecting means concurrent requests to a
+        // new host all wait for this one connection, instead of each
+        // opening their own.
+        let mut protocol = slot.lock().await;
+        match &*protocol {
+            Some(Protocol::Http1) => return Ok(None),
+            Some(Protocol::Http2(sender)) => return Ok(Some(sender.clone())),
+            None => {}
         }
+        match timeout::connect(self.connect_timeout, connect).await? {
+            Some(stream) => {
+                let sender = http2::start(stream, slot.clone()).await?;
+                *protocol = Some(Protocol::Http2(sender.clone()));
+                Ok(Some(sender))
+            }
+            None => {
+                *protocol = Some(Protocol::Http1);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Waits for a free slot under `host`'s request limit, and then under
+    /// the global one, if there is one. Slots are held until the returned
+    /// permits are dropped.
+    async fn acquire(&self, host: &str, port: u16) -> Permits {
+        let limit = self
+            .host_limits
+            .lock()
+            .await
+            .entry(format!("{}:{}", host, port))
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_connections_per_host)))
+            .clone();
+        // The per-host slot comes first, so requests stuck waiting on a busy
+        // host don't tie up global slots other hosts could be using.
+        let host = limit.acquire_arc().await;
+        let global = match &self.connections {
+            Some(connections) => Some(connections.acquire_arc().await),
+            None => None,
+        };
+        Permits {
+            _host: host,
+            _global: global,
+        }
+    }
+
+    /// Proxies to send requests through.
+    pub(crate) fn proxies(mut self, proxies: Proxies) -> Self {
+        self.proxies = proxies;
+        self
+    }
+
+    /// Certificates to use for HTTPS connections.
+    pub(crate) fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Arc::new(tls);
+        self
     }
 }
 
@@ -76,60 +246,122 @@ impl HttpClient for PoolingClient {
             ));
         }
 
-        let addr = req
+        let port = req
             .url()
-            .socket_addrs(|| match req.url().scheme() {
-                "http" => Some(80),
-                "https" => Some(443),
-                _ => None,
-            })?
-            .into_iter()
-            .next()
-            .ok_or_else(|| Error::from_str(StatusCode::BadRequest, "missing valid address"))?;
+            .port_or_known_default()
+            .ok_or_else(|| Error::from_str(StatusCode::BadRequest, "missing port"))?;
+
+        let proxy = self.proxies.for_url(req.url()).cloned();
+        if let Some(proxy) = &proxy {
+            log::trace!("> Proxy: {}", proxy.origin().ascii_serialization());
+        }
 
         log::trace!("> Scheme: {}", scheme);
 
         match scheme {
             "http" => {
+                if self.http2_prior_knowledge && proxy.is_none() {
+                    let key = format!("http://{}:{}", host, port);
+                    let connect =
+                        async { TcpStream::connect((host.as_str(), port)).await.map(Some) };
+                    if let Some(sender) = self.http2_sender(key, connect).await? {
+                        let permits = self.acquire(&host, port).await;
+                        return http2::send(sender, req, self.read_timeout, permits).await;
+                    }
+                }
+                let permits = self.acquire(&host, port).await;
+                // Plain HTTP requests are sent to the proxy itself, which
+                // makes them on our behalf.
+                let (conn_host, conn_port) = match &proxy {
+                    Some(proxy) => proxy::address(proxy)?,
+                    None => (host, port),
+                };
+                if let Some(auth) = proxy.as_ref().and_then(proxy::authorization) {
+                    req.insert_header("Proxy-Authorization", auth);
+                }
+                let key = format!("{}:{}", conn_host, conn_port);
                 let mut hash = http_pool.lock().await;
-                let pool = if let Some(pool) = hash.get(&addr) {
+                let pool = if let Some(pool) = hash.get(&key) {
                     pool
                 } else {
-                    let manager = TcpConnection::new(addr);
-                    let pool =
-                        Pool::<TcpStream, std::io::Error>::new(manager, MAX_CONCURRENT_CONNECTIONS);
-                    hash.insert(addr, pool);
-                    hash.get(&addr).expect("oh COME ON")
+                    let manager = TcpConnection::new(conn_host, conn_port, self.connect_timeout);
+                    let pool = Pool::<TcpStream, std::io::Error>::new(
+                        manager,
+                        self.max_connections_per_host,
+                    );
+                    hash.insert(key.clone(), pool);
+                    hash.get(&key).expect("oh COME ON")
                 };
                 let pool = pool.clone();
                 std::mem::drop(hash);
-                let stream = pool.get().await?;
+                let stream = pool.get().await.map_err(|e| match e {
+                    PoolError::Backend(e) => Error::from(e),
+                    PoolError::Timeout(kind) => pool_timeout(kind),
+                })?;
                 req.set_peer_addr(stream.peer_addr().ok());
                 req.set_local_addr(stream.local_addr().ok());
-                client::connect(TcpConnWrapper::new(stream), req).await
+                let conn = TcpConnWrapper::new(stream, self.read_timeout, permits);
+                if proxy.is_some() {
+                    let url = req.url().clone();
+                    client::connect(AbsoluteForm::new(conn, &url), req).await
+                } else {
+                    client::connect(conn, req).await
+                }
             }
             "https" => {
+                // HTTPS requests go through a CONNECT tunnel instead, so
+                // the proxy never sees what's in them.
+                if self.http2 {
+                    let key = format!("https://{}:{}", host, port);
+                    let connect = async {
+                        let stream = tls::connect_raw(&host, port, proxy.as_ref()).await?;
+                        http2::connect_alpn(&self.tls, &host, stream).await
+                    };
+                    if let Some(sender) = self.http2_sender(key, connect).await? {
+                        let permits = self.acquire(&host, port).await;
+                        return http2::send(sender, req, self.read_timeout, permits).await;
+                    }
+                }
+                let permits = self.acquire(&host, port).await;
+                let key = format!("{}:{}", host, port);
                 let mut hash = https_pool.lock().await;
-                let pool = if let Some(pool) = hash.get(&addr) {
+                let pool = if let Some(pool) = hash.get(&key) {
                     pool
                 } else {
-                    let manager = TlsConnection::new(host.clone(), addr);
+                    let manager = TlsConnection::new(
+                        host,
+                        port,
+                        proxy,
+                        self.tls.clone(),
+                        self.connect_timeout,
+                    );
                     let pool = Pool::<TlsStream<TcpStream>, Error>::new(
                         manager,
-                        MAX_CONCURRENT_CONNECTIONS,
+                        self.max_connections_per_host,
                     );
-                    hash.insert(addr, pool);
-                    hash.get(&addr).expect("oh COME ON")
+                    hash.insert(key.clone(), pool);
+                    hash.get(&key).expect("oh COME ON")
                 };
                 let pool = pool.clone();
                 std::mem::drop(hash);
-                let stream = pool.get().await.unwrap(); // TODO: remove unwrap
+                let stream = pool.get().await.map_err(|e| match e {
+                    PoolError::Backend(e) => e,
+                    PoolError::Timeout(kind) => pool_timeout(kind),
+                })?;
                 req.set_peer_addr(stream.get_ref().peer_addr().ok());
                 req.set_local_addr(stream.get_ref().local_addr().ok());
 
-                client::connect(TlsConnWrapper::new(stream), req).await
+                let conn = TlsConnWrapper::new(stream, self.read_timeout, permits);
+                client::connect(conn, req).await
             }
             _ => unreachable!(),
         }
     }
 }
+
+fn pool_timeout(kind: TimeoutType) -> Error {
+    Error::from_str(
+        StatusCode::GatewayTimeout,
+        format!("timed out waiting for a pooled connection ({:?})", kind),
+    )
+}
@@ -1,6 +1,6 @@
 use std::fmt::Debug;
-use std::net::SocketAddr;
 use std::pin::Pin;
+use std::time::Duration;
 
 use async_std::net::TcpStream;
 use async_trait::async_trait;
@@ -8,12 +8,26 @@ use deadpool::managed::{Manager, Object, RecycleResult};
 use futures::io::{AsyncRead, AsyncWrite};
 use futures::task::{Context, Poll};
 
+use super::timeout::{self, ReadTimer};
+use super::Permits;
+
 pub struct TcpConnWrapper {
     conn: Object<TcpStream, std::io::Error>,
+    timer: ReadTimer,
+    // Counts against the client's connection limits until dropped.
+    _permits: Permits,
 }
 impl TcpConnWrapper {
-    pub fn new(conn: Object<TcpStream, std::io::Error>) -> Self {
-        Self { conn }
+    pub fn new(
+        conn: Object<TcpStream, std::io::Error>,
+        read_timeout: Option<Duration>,
+        permits: Permits,
+    ) -> Self {
+        Self {
+            conn,
+            timer: ReadTimer::new(read_timeout),
+            _permits: permits,
+        }
     }
 }
 
@@ -23,7 +37,9 @@ impl AsyncRead for TcpConnWrapper {
         cx: &mut Context,
         buf: &mut [u8],
     ) -> Poll<Result<usize, std::io::Error>> {
-        Pin::new(&mut *self.conn).poll_read(cx, buf)
+        let this = &mut *self;
+        let read = Pin::new(&mut *this.conn).poll_read(cx, buf);
+        this.timer.poll(cx, read)
     }
 }
 
@@ -48,18 +64,28 @@ impl AsyncWrite for TcpConnWrapper {
 
 #[derive(Clone, Debug)]
 pub struct TcpConnection {
-    addr: SocketAddr,
+    host: String,
+    port: u16,
+    connect_timeout: Option<Duration>,
 }
 impl TcpConnection {
-    pub fn new(addr: SocketAddr) -> Self {
-        Self { addr }
+    pub fn new(host: String, port: u16, connect_timeout: Option<Duration>) -> Self {
+        Self {
+            host,
+            port,
+            connect_timeout,
+        }
     }
 }
 
 #[async_trait]
 impl Manager<TcpStream, std::io::Error> for TcpConnection {
     async fn create(&self) -> Result<TcpStream, std::io::Error> {
-        Ok(TcpStream::connect(self.addr).await?)
+        timeout::connect(
+            self.connect_timeout,
+            TcpStream::connect((self.host.as_str(), self.port)),
+        )
+        .await
     }
 
     async fn recycle(&self, _conn: &mut TcpStream) -> RecycleResult<std::io::Error> {
@@ -0,0 +1,127 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures::task::{Context, Poll};
+use futures_timer::Delay;
+use surf::Error;
+use thiserror::Error;
+
+/// Which of the client's timeouts ran out. These end up inside the errors
+/// returned by the client, so callers can tell them apart from other
+/// failures with [`find`].
+#[derive(Debug, Clone, Copy, Error)]
+pub(crate) enum TimedOut {
+    #[error("timed out after {0:?} while connecting")]
+    Connect(Duration),
+    #[error("timed out after {0:?} waiting for data")]
+    Read(Duration),
+    #[error("timed out after {0:?} waiting for a response")]
+    Request(Duration),
+}
+
+impl From<TimedOut> for io::Error {
+    fn from(timeout: TimedOut) -> Self {
+        io::Error::new(io::ErrorKind::TimedOut, timeout)
+    }
+}
+
+/// Looks for a [`TimedOut`] in `err`, whether it was returned directly or
+/// wrapped in an I/O error along the way.
+pub(crate) fn find(err: &Error) -> Option<TimedOut> {
+    err.downcast_ref::<TimedOut>().copied().or_else(|| {
+        err.downcast_ref::<io::Error>()
+            .and_then(|e| e.get_ref())
+            .and_then(|e| e.downcast_ref::<TimedOut>())
+            .copied()
+    })
+}
+
+/// Runs `fut`, failing with [`TimedOut::Connect`] if it takes longer than
+/// `timeout`.
+pub(crate) async fn connect<T>(
+    timeout: Option<Duration>,
+    fut: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match timeout {
+        Some(timeout) => async_std::io::timeout(timeout, fut)
+            .await
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::TimedOut => TimedOut::Connect(timeout).into(),
+                _ => e,
+            }),
+        None => fut.await,
+    }
+}
+
+/// Tracks how long a connection has gone without producing any data.
+pub(crate) struct ReadTimer {
+    timeout: Option<Duration>,
+    delay: Option<Delay>,
+}
+
+impl ReadTimer {
+    pub(crate) fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            delay: None,
+        }
+    }
+
+    /// Wraps the result of polling a read. Successful reads reset the timer,
+    /// and reads that have been pending for too long fail with
+    /// [`TimedOut::Read`].
+    pub(crate) fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        read: Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<usize>> {
+        let timeout = match (read, self.timeout) {
+            (Poll::Pending, Some(timeout)) => timeout,
+            (read, _) => {
+                self.delay = None;
+                return read;
+            }
+        };
+        let delay = self.delay.get_or_insert_with(|| Delay::new(timeout));
+        match Pin::new(delay).poll(cx) {
+            Poll::Ready(()) => {
+                self.delay = None;
+                Poll::Ready(Err(TimedOut::Read(timeout).into()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use surf::http::StatusCode;
+
+    #[test]
+    fn finds_wrapped_timeouts() {
+        let timeout = Duration::from_secs(1);
+        let direct = Error::new(StatusCode::GatewayTimeout, TimedOut::Request(timeout));
+        assert!(matches!(find(&direct), Some(TimedOut::Request(_))));
+        let wrapped = Error::from(io::Error::from(TimedOut::Read(timeout)));
+        assert!(matches!(find(&wrapped), Some(TimedOut::Read(_))));
+        let other = Error::from(io::Error::new(io::ErrorKind::Other, "nope"));
+        assert!(find(&other).is_none());
+    }
+
+    #[async_std::test]
+    async fn connect_timeout() {
+        let timeout = Some(Duration::from_millis(10));
+        let err = connect(timeout, futures::future::pending::<io::Result<()>>())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(matches!(
+            err.get_ref().and_then(|e| e.downcast_ref::<TimedOut>()),
+            Some(TimedOut::Connect(_))
+        ));
+    }
+}
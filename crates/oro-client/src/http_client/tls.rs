@@ -1,32 +1,170 @@
-use std::fmt::Debug;
-use std::net::SocketAddr;
+use std::fmt::{self, Debug};
+use std::path::Path;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 
-use async_native_tls::TlsStream;
+use async_native_tls::{Certificate, Identity, TlsConnector, TlsStream};
 use async_std::net::TcpStream;
 use async_trait::async_trait;
 use deadpool::managed::{Manager, Object, RecycleResult};
 use futures::io::{AsyncRead, AsyncWrite};
 use futures::task::{Context, Poll};
 use http_client::Error;
+use surf::http::Url;
+
+use super::proxy;
+use super::timeout::{self, ReadTimer};
+use super::Permits;
+
+/// Certificate settings shared by every TLS connection a client makes.
+#[derive(Clone)]
+pub(crate) struct TlsConfig {
+    pub(crate) roots: Vec<Certificate>,
+    pub(crate) identity: Option<Identity>,
+    pub(crate) strict: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            roots: Vec::new(),
+            identity: None,
+            strict: true,
+        }
+    }
+}
+
+impl Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("roots", &self.roots.len())
+            .field("identity", &self.identity.is_some())
+            .field("strict", &self.strict)
+            .finish()
+    }
+}
+
+impl TlsConfig {
+    /// Builds a config out of npm-style settings: PEM-encoded CA
+    /// certificates (`ca`, plus the contents of `cafile`), a client
+    /// certificate and key (`cert` and `key`), and whether to verify
+    /// servers at all (`strict-ssl`). Literal `\n`s in the PEM strings are
+    /// turned into newlines, like npm does.
+    pub(crate) fn load(
+        ca: &[String],
+        cafile: Option<&Path>,
+        cert: Option<&str>,
+        key: Option<&str>,
+        strict: bool,
+    ) -> Result<Self, String> {
+        let mut roots = Vec::new();
+        for pem in ca {
+            roots.extend(parse_certificates(&unescape(pem), "ca")?);
+        }
+        if let Some(cafile) = cafile {
+            let pem = std::fs::read_to_string(cafile)
+                .map_err(|e| format!("failed to read cafile at {}: {}", cafile.display(), e))?;
+            roots.extend(parse_certificates(&pem, &cafile.display().to_string())?);
+        }
+        let identity = match (cert, key) {
+            (Some(cert), Some(key)) => Some(
+                Identity::from_pkcs8(unescape(cert).as_bytes(), unescape(key).as_bytes())
+                    .map_err(|e| format!("invalid client certificate or key: {}", e))?,
+            ),
+            (None, None) => None,
+            _ => return Err("`cert` and `key` need to be set together".into()),
+        };
+        Ok(Self {
+            roots,
+            identity,
+            strict,
+        })
+    }
+
+    /// Connector settings for this config, so callers can add their own on
+    /// top, like ALPN protocols.
+    pub(crate) fn builder(&self) -> native_tls::TlsConnectorBuilder {
+        let mut builder = native_tls::TlsConnector::builder();
+        for cert in &self.roots {
+            builder.add_root_certificate(cert.clone());
+        }
+        if let Some(identity) = &self.identity {
+            builder.identity(identity.clone());
+        }
+        builder
+            .danger_accept_invalid_certs(!self.strict)
+            .danger_accept_invalid_hostnames(!self.strict);
+        builder
+    }
+
+    fn connector(&self) -> TlsConnector {
+        self.builder().into()
+    }
+}
+
+/// Opens a TCP connection to `host:port`, tunneling through `proxy` if
+/// there is one, ready for a TLS handshake.
+pub(crate) async fn connect_raw(
+    host: &str,
+    port: u16,
+    proxy: Option<&Url>,
+) -> std::io::Result<TcpStream> {
+    match proxy {
+        Some(proxy) => {
+            let (proxy_host, proxy_port) = proxy::address(proxy)?;
+            let mut stream = TcpStream::connect((proxy_host.as_str(), proxy_port)).await?;
+            let auth = proxy::authorization(proxy);
+            proxy::tunnel(&mut stream, host, port, auth.as_deref()).await?;
+            Ok(stream)
+        }
+        None => TcpStream::connect((host, port)).await,
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct TlsConnection {
     host: String,
-    addr: SocketAddr,
+    port: u16,
+    proxy: Option<Url>,
+    tls: Arc<TlsConfig>,
+    connect_timeout: Option<Duration>,
 }
 impl TlsConnection {
-    pub fn new(host: String, addr: SocketAddr) -> Self {
-        Self { host, addr }
+    pub fn new(
+        host: String,
+        port: u16,
+        proxy: Option<Url>,
+        tls: Arc<TlsConfig>,
+        connect_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            host,
+            port,
+            proxy,
+            tls,
+            connect_timeout,
+        }
     }
 }
 
 pub struct TlsConnWrapper {
     conn: Object<TlsStream<TcpStream>, Error>,
+    timer: ReadTimer,
+    // Counts against the client's connection limits until dropped.
+    _permits: Permits,
 }
 impl TlsConnWrapper {
-    pub fn new(conn: Object<TlsStream<TcpStream>, Error>) -> Self {
-        Self { conn }
+    pub fn new(
+        conn: Object<TlsStream<TcpStream>, Error>,
+        read_timeout: Option<Duration>,
+        permits: Permits,
+    ) -> Self {
+        Self {
+            conn,
+            timer: ReadTimer::new(read_timeout),
+            _permits: permits,
+        }
     }
 }
 
@@ -36,7 +174,9 @@ impl AsyncRead for TlsConnWrapper {
         cx: &mut Context,
         buf: &mut [u8],
     ) -> Poll<Result<usize, std::io::Error>> {
-        Pin::new(&mut *self.conn).poll_read(cx, buf)
+        let this = &mut *self;
+        let read = Pin::new(&mut *this.conn).poll_read(cx, buf);
+        this.timer.poll(cx, read)
     }
 }
 
@@ -61,9 +201,18 @@ impl AsyncWrite for TlsConnWrapper {
 #[async_trait]
 impl Manager<TlsStream<TcpStream>, Error> for TlsConnection {
     async fn create(&self) -> Result<TlsStream<TcpStream>, Error> {
-        log::trace!("Creating new socket to {:?}", self.addr);
-        let raw_stream = async_std::net::TcpStream::connect(self.addr).await?;
-        let stream = async_native_tls::connect(&self.host, raw_stream).await?;
+        log::trace!("Creating new socket to {}:{}", self.host, self.port);
+        // The timeout covers the TLS handshake too, since a stalled
+        // handshake hangs just as badly as a stalled connect.
+        let stream = timeout::connect(self.connect_timeout, async {
+            let raw_stream = connect_raw(&self.host, self.port, self.proxy.as_ref()).await?;
+            self.tls
+                .connector()
+                .connect(&self.host, raw_stream)
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        })
+        .await?;
         Ok(stream)
     }
 
@@ -71,3 +220,62 @@ impl Manager<TlsStream<TcpStream>, Error> for TlsConnection {
         Ok(())
     }
 }
+
+fn unescape(pem: &str) -> String {
+    pem.replace("\\n", "\n")
+}
+
+/// Parses every certificate in a PEM bundle.
+fn parse_certificates(pem: &str, source: &str) -> Result<Vec<Certificate>, String> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let mut certs = Vec::new();
+    let mut rest = pem;
+    while let Some(start) = rest.find("-----BEGIN CERTIFICATE-----") {
+        let end = rest[start..]
+            .find(END)
+            .map(|end| start + end + END.len())
+            .ok_or_else(|| format!("unterminated certificate in {}", source))?;
+        certs.push(
+            Certificate::from_pem(rest[start..end].as_bytes())
+                .map_err(|e| format!("invalid certificate in {}: {}", source, e))?,
+        );
+        rest = &rest[end..];
+    }
+    if certs.is_empty() {
+        return Err(format!("no PEM certificates found in {}", source));
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_to_strict() {
+        let config = TlsConfig::load(&[], None, None, None, true).unwrap();
+        assert!(config.strict);
+        assert!(config.roots.is_empty());
+        assert!(config.identity.is_none());
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        let err = TlsConfig::load(&["not a cert".into()], None, None, None, true).unwrap_err();
+        assert!(err.contains("no PEM certificates found in ca"));
+        let err = TlsConfig::load(
+            &["-----BEGIN CERTIFICATE-----\\nAAAA".into()],
+            None,
+            None,
+            None,
+            true,
+        )
+        .unwrap_err();
+        assert!(err.contains("unterminated certificate"));
+        let err = TlsConfig::load(&[], None, Some("cert"), None, true).unwrap_err();
+        assert!(err.contains("set together"));
+        let err =
+            TlsConfig::load(&[], Some(Path::new("/no/such/cafile")), None, None, true).unwrap_err();
+        assert!(err.contains("failed to read cafile"));
+    }
+}
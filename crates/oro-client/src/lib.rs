@@ -1,3 +1,7 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use async_std::{future, task};
 use oro_diagnostics::{Diagnostic, DiagnosticCategory, Explain, Meta};
 use serde::Deserialize;
 use surf::Client;
@@ -8,38 +12,98 @@ pub use surf::{
     Error as SurfError, RequestBuilder, Response,
 };
 
-use crate::http_client::PoolingClient;
+use crate::http_client::proxy::{self, Proxies};
+use crate::http_client::{timeout, PoolingClient, TimedOut, TlsConfig};
+
+pub use crate::secret::Secret;
 
+mod compression;
 mod http_client;
+mod retry;
+mod secret;
 
 #[derive(Debug, Error, Diagnostic)]
 pub enum OroClientError {
     // TODO: add registry URL here?
-    #[error("Registry request failed:\n\t{surf_err}")]
+    #[error("Registry request failed{}:\n\t{surf_err}", retried(.retries))]
     #[category(Net)]
     #[label("client::bad_request")]
-    RequestError { surf_err: SurfError, url: Url },
+    #[advice("Check your network connection and that the registry is reachable. Flaky connections can be given more retries with `--fetch-retries`.")]
+    RequestError {
+        surf_err: SurfError,
+        url: Url,
+        retries: u32,
+    },
 
-    #[error("Registry returned failed status code {status_code} for a request.")]
+    #[error("Registry returned failed status code {status_code} for a request{}.", retried(.retries))]
     #[category(Net)]
     #[label("client::response_failure")]
     ResponseError {
         url: Url,
         status_code: StatusCode,
         message: Option<String>,
+        retries: u32,
     },
+
+    #[error("Timed out after {timeout:?} connecting to {url}{}.", retried(.retries))]
+    #[category(Net)]
+    #[label("client::connect_timeout")]
+    #[advice("The registry didn't accept a connection in time. Check your network connection, and that the registry URL and any proxy settings are right.")]
+    ConnectTimeout {
+        url: Url,
+        timeout: Duration,
+        retries: u32,
+    },
+
+    #[error("Timed out after {timeout:?} waiting for data from {url}{}.", retried(.retries))]
+    #[category(Net)]
+    #[label("client::read_timeout")]
+    #[advice("The registry stopped sending data partway through a request. It may be overloaded, so try again in a bit.")]
+    ReadTimeout {
+        url: Url,
+        timeout: Duration,
+        retries: u32,
+    },
+
+    #[error("Request to {url} took longer than {timeout:?}{}.", retried(.retries))]
+    #[category(Net)]
+    #[label("client::request_timeout")]
+    #[advice(
+        "The registry took too long to respond. It may be overloaded, so try again in a bit."
+    )]
+    RequestTimeout {
+        url: Url,
+        timeout: Duration,
+        retries: u32,
+    },
+
+    #[error("Invalid TLS settings: {0}")]
+    #[category(Misc)]
+    #[label("client::tls_config")]
+    #[advice("Check that `cafile`, `ca`, `cert` and `key` hold valid PEM-encoded certificates and keys. Newlines in PEM values can be written as `\\n`.")]
+    TlsConfigError(String),
+}
+
+fn retried(retries: &u32) -> String {
+    match retries {
+        0 => String::new(),
+        1 => " after 1 retry".into(),
+        n => format!(" after {} retries", n),
+    }
 }
 
 impl Explain for OroClientError {
     fn meta(&self) -> Option<Meta> {
         use OroClientError::*;
         match self {
-            RequestError { ref url, .. } => Some(Meta::Net {
-                url: Some(url.clone()),
-            }),
-            ResponseError { ref url, .. } => Some(Meta::Net {
+            RequestError { ref url, .. }
+            | ResponseError { ref url, .. }
+            | ConnectTimeout { ref url, .. }
+            | ReadTimeout { ref url, .. }
+            | RequestTimeout { ref url, .. } => Some(Meta::Net {
                 url: Some(url.clone()),
             }),
+            TlsConfigError(_) => None,
         }
     }
 }
@@ -49,16 +113,240 @@ struct NpmError {
     message: String,
 }
 
+/// Build a new OroClient with specified options.
+#[derive(Clone, Debug)]
+pub struct OroClientOpts {
+    retries: u32,
+    min_retry_delay: Duration,
+    max_retry_delay: Duration,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    request_timeout: Duration,
+    max_connections: Option<usize>,
+    max_connections_per_host: Option<usize>,
+    http2: bool,
+    http2_prior_knowledge: bool,
+    proxies: Proxies,
+    ca: Vec<String>,
+    cafile: Option<PathBuf>,
+    cert: Option<String>,
+    key: Option<Secret>,
+    strict_ssl: bool,
+}
+
+impl Default for OroClientOpts {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            min_retry_delay: Duration::from_secs(1),
+            max_retry_delay: Duration::from_secs(60),
+            connect_timeout: Duration::from_secs(30),
+            read_timeout: Duration::from_secs(60),
+            request_timeout: Duration::from_secs(300),
+            max_connections: None,
+            max_connections_per_host: None,
+            http2: true,
+            http2_prior_knowledge: false,
+            proxies: Proxies::from_env(),
+            ca: Vec::new(),
+            cafile: None,
+            cert: None,
+            key: None,
+            strict_ssl: true,
+        }
+    }
+}
+
+impl OroClientOpts {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// How many times to retry requests that fail with connection errors,
+    /// timeouts, or 408, 429 and 5xx responses. Defaults to 2. Only GET,
+    /// HEAD, OPTIONS and body-less PUT requests are ever retried, since
+    /// anything else might have already gone through.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Delay before the first retry. Each retry after that waits twice as
+    /// long as the one before it, minus some random jitter. Defaults to 1s.
+    pub fn min_retry_delay(mut self, delay: Duration) -> Self {
+        self.min_retry_delay = delay;
+        self
+    }
+
+    /// The longest any retry will wait, including delays requested by the
+    /// server through `Retry-After`. Defaults to 60s.
+    pub fn max_retry_delay(mut self, delay: Duration) -> Self {
+        self.max_retry_delay = delay;
+        self
+    }
+
+    /// Limit on how long opening a connection can take, including the TLS
+    /// handshake. Defaults to 30s.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Limit on how long a connection can go quiet while we're waiting on
+    /// data from it, whether that's response headers or the body. Defaults
+    /// to 60s.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Limit on how long a single attempt at a request can take to get a
+    /// response back, connecting included. Reading the response body isn't
+    /// covered, since tarballs can take a while to download, but it's still
+    /// subject to `read_timeout`. Defaults to 5 minutes.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Limit on how many requests can be in flight at once, across all
+    /// hosts. Unlimited by default.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Limit on how many requests can be in flight to any one host at a
+    /// time, whether they each get their own HTTP/1.1 connection or share an
+    /// HTTP/2 one. Requests past that wait for one to finish. Defaults to 50.
+    pub fn max_connections_per_host(mut self, max: usize) -> Self {
+        self.max_connections_per_host = Some(max);
+        self
+    }
+
+    /// Whether to use HTTP/2 with HTTPS registries that support it, which
+    /// multiplexes every request to a registry over a single connection.
+    /// Registries that don't are talked to over HTTP/1.1 as usual. Defaults
+    /// to true.
+    pub fn http2(mut self, http2: bool) -> Self {
+        self.http2 = http2;
+        self
+    }
+
+    /// Whether to talk HTTP/2 to plain HTTP registries without checking
+    /// that they support it first. Only useful for local registries known
+    /// to speak HTTP/2, and never used through a proxy. Defaults to false.
+    pub fn http2_prior_knowledge(mut self, prior_knowledge: bool) -> Self {
+        self.http2_prior_knowledge = prior_knowledge;
+        self
+    }
+
+    /// Proxy to send plain HTTP requests through, and HTTPS requests too if
+    /// there's no `https_proxy`. Credentials in the URL are sent to the
+    /// proxy using basic auth. Defaults to `$HTTP_PROXY`.
+    pub fn proxy(mut self, proxy: Url) -> Self {
+        self.proxies.http = Some(proxy);
+        self
+    }
+
+    /// Proxy to tunnel HTTPS requests through. Defaults to `$HTTPS_PROXY`.
+    pub fn https_proxy(mut self, proxy: Url) -> Self {
+        self.proxies.https = Some(proxy);
+        self
+    }
+
+    /// Comma- or space-separated list of hosts to connect to directly
+    /// instead of through a proxy. Entries match subdomains too, can be
+    /// limited to a port with `host:port`, and `*` turns proxying off
+    /// entirely. Defaults to `$NO_PROXY`.
+    pub fn no_proxy(mut self, no_proxy: impl AsRef<str>) -> Self {
+        self.proxies.no_proxy = proxy::parse_no_proxy(no_proxy.as_ref());
+        self
+    }
+
+    /// PEM-encoded CA certificates to trust for HTTPS connections, on top
+    /// of the system's. Can be called more than once, and each call can
+    /// pass a whole bundle.
+    pub fn ca(mut self, pem: impl AsRef<str>) -> Self {
+        self.ca.push(pem.as_ref().into());
+        self
+    }
+
+    /// File of PEM-encoded CA certificates to trust for HTTPS connections,
+    /// on top of the system's and any added with [`ca`](Self::ca).
+    pub fn cafile(mut self, cafile: impl AsRef<Path>) -> Self {
+        self.cafile = Some(cafile.as_ref().into());
+        self
+    }
+
+    /// PEM-encoded client certificate to present to servers that ask for
+    /// one. Needs a [`key`](Self::key) as well.
+    pub fn cert(mut self, pem: impl AsRef<str>) -> Self {
+        self.cert = Some(pem.as_ref().into());
+        self
+    }
+
+    /// PEM-encoded PKCS #8 private key for the client certificate.
+    pub fn key(mut self, pem: impl Into<Secret>) -> Self {
+        self.key = Some(pem.into());
+        self
+    }
+
+    /// Whether to verify HTTPS servers' certificates. Turning this off makes
+    /// HTTPS no safer than plain HTTP, and is only meant for development
+    /// registries with self-signed certificates. Defaults to true.
+    pub fn strict_ssl(mut self, strict_ssl: bool) -> Self {
+        self.strict_ssl = strict_ssl;
+        self
+    }
+
+    pub fn build(self) -> OroClient {
+        // Bad TLS settings are reported by the first request instead of
+        // here, so building a client can't fail.
+        let (tls, tls_error) = match TlsConfig::load(
+            &self.ca,
+            self.cafile.as_deref(),
+            self.cert.as_deref(),
+            self.key.as_ref().map(Secret::expose),
+            self.strict_ssl,
+        ) {
+            Ok(tls) => (tls, None),
+            Err(e) => (TlsConfig::default(), Some(e)),
+        };
+        if !self.strict_ssl {
+            log::warn!("strict-ssl is off, so HTTPS certificates won't be verified");
+        }
+        let mut http_client = PoolingClient::new()
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout)
+            .http2(self.http2)
+            .http2_prior_knowledge(self.http2_prior_knowledge)
+            .proxies(self.proxies.clone())
+            .tls(tls);
+        if let Some(max) = self.max_connections {
+            http_client = http_client.max_connections(max);
+        }
+        if let Some(max) = self.max_connections_per_host {
+            http_client = http_client.max_connections_per_host(max);
+        }
+        OroClient {
+            client: Client::with_http_client(http_client),
+            opts: self,
+            tls_error,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct OroClient {
     client: Client,
+    opts: OroClientOpts,
+    tls_error: Option<String>,
 }
 
 impl Default for OroClient {
     fn default() -> Self {
-        Self {
-            client: Client::with_http_client(PoolingClient::new()),
-        }
+        OroClientOpts::new().build()
     }
 }
 
@@ -71,17 +359,116 @@ impl OroClient {
         RequestBuilder::new(method, uri)
     }
 
+    /// Like [`opts`](Self::opts), but asks the server to compress its
+    /// response, which [`send`](Self::send) then decodes transparently.
+    /// Meant for metadata like packuments, which can run to several MB
+    /// uncompressed. Tarballs are already gzipped, so they shouldn't use
+    /// this.
+    pub fn compressed_opts(&self, method: Method, uri: Url) -> RequestBuilder {
+        self.opts(method, uri)
+            .header("Accept-Encoding", compression::ACCEPT_ENCODING)
+    }
+
     pub async fn send(&self, request: RequestBuilder) -> Result<Response, OroClientError> {
-        let req = request.build();
+        if let Some(message) = &self.tls_error {
+            return Err(OroClientError::TlsConfigError(message.clone()));
+        }
+        let mut req: surf::http::Request = request.build().into();
         let url = req.url().clone();
-        let mut res = self
-            .client
-            .send(req)
-            .await
-            .map_err(|e| OroClientError::RequestError {
-                surf_err: e,
-                url: url.clone(),
-            })?;
+        let accepted = compression::accepted(&req);
+        // Bodies can only be read once, so hang on to it for retries.
+        let body =
+            req.take_body()
+                .into_bytes()
+                .await
+                .map_err(|e| OroClientError::RequestError {
+                    surf_err: e,
+                    url: url.clone(),
+                    retries: 0,
+                })?;
+        let max_retries = if retry::is_idempotent(req.method(), !body.is_empty()) {
+            self.opts.retries
+        } else {
+            0
+        };
+        let mut retries = 0;
+        loop {
+            let mut attempt = req.clone();
+            if !body.is_empty() {
+                attempt.set_body(body.clone());
+            }
+            let sent = future::timeout(self.opts.request_timeout, self.client.send(attempt))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(SurfError::new(
+                        StatusCode::GatewayTimeout,
+                        TimedOut::Request(self.opts.request_timeout),
+                    ))
+                });
+            let delay = match sent {
+                Ok(res) if retries < max_retries && retry::is_retryable_status(res.status()) => {
+                    log::debug!("{} responded with {}", url, res.status());
+                    retry::retry_after(&res)
+                        .map(|delay| delay.min(self.opts.max_retry_delay))
+                        .unwrap_or_else(|| self.backoff(retries))
+                }
+                Ok(res) => {
+                    let res = compression::decode(res, &accepted);
+                    return Self::check_response(res, url, retries).await;
+                }
+                Err(e) if retries < max_retries && retry::is_retryable_error(&e) => {
+                    log::debug!("Request to {} failed: {}", url, e);
+                    self.backoff(retries)
+                }
+                Err(e) => return Err(Self::request_error(e, url, retries)),
+            };
+            retries += 1;
+            log::debug!(
+                "Retrying {} in {:?} (retry {} of {})",
+                url,
+                delay,
+                retries,
+                max_retries
+            );
+            task::sleep(delay).await;
+        }
+    }
+
+    fn request_error(surf_err: SurfError, url: Url, retries: u32) -> OroClientError {
+        use OroClientError::*;
+        match timeout::find(&surf_err) {
+            Some(TimedOut::Connect(timeout)) => ConnectTimeout {
+                url,
+                timeout,
+                retries,
+            },
+            Some(TimedOut::Read(timeout)) => ReadTimeout {
+                url,
+                timeout,
+                retries,
+            },
+            Some(TimedOut::Request(timeout)) => RequestTimeout {
+                url,
+                timeout,
+                retries,
+            },
+            None => RequestError {
+                surf_err,
+                url,
+                retries,
+            },
+        }
+    }
+
+    fn backoff(&self, retry: u32) -> Duration {
+        retry::backoff(retry, self.opts.min_retry_delay, self.opts.max_retry_delay)
+    }
+
+    async fn check_response(
+        mut res: Response,
+        url: Url,
+        retries: u32,
+    ) -> Result<Response, OroClientError> {
         if res.status().is_client_error() || res.status().is_server_error() {
             let msg = match res.body_json::<NpmError>().await {
                 Ok(err) => err.message,
@@ -92,6 +479,7 @@ impl OroClient {
                             url,
                             status_code: res.status(),
                             message: None,
+                            retries,
                         });
                     }
                 },
@@ -100,9 +488,365 @@ impl OroClient {
                 status_code: res.status(),
                 url,
                 message: Some(msg),
+                retries,
             })
         } else {
             Ok(res)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::vec::IntoIter;
+
+    use async_std::io::BufReader;
+    use async_std::net::{TcpListener, TcpStream};
+    use async_std::prelude::*;
+    use async_std::sync::{Arc, Mutex};
+    use bytes::Bytes;
+    use futures::channel::mpsc::{self, UnboundedReceiver};
+    use futures::future::{join_all, select, Either};
+    use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+    /// Starts a server that answers each request with the next of
+    /// `responses`, given as a status line plus any extra headers.
+    async fn serve(responses: Vec<&'static str>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let responses = Arc::new(Mutex::new(responses.into_iter()));
+        task::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(Ok(stream)) = incoming.next().await {
+                task::spawn(handle(stream, responses.clone()));
+            }
+        });
+        url
+    }
+
+    async fn handle(stream: TcpStream, responses: Arc<Mutex<IntoIter<&'static str>>>) {
+        let mut reader = BufReader::new(stream.clone());
+        let mut writer = stream;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            if !line.trim().is_empty() {
+                continue;
+            }
+            let response = match responses.lock().await.next() {
+                Some(response) => response,
+                None => return,
+            };
+            let head = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", response);
+            writer.write_all(head.as_bytes()).await.unwrap();
+        }
+    }
+
+    fn client(retries: u32) -> OroClient {
+        OroClientOpts::new()
+            .retries(retries)
+            .min_retry_delay(Duration::from_millis(1))
+            .max_retry_delay(Duration::from_millis(10))
+            .build()
+    }
+
+    #[async_std::test]
+    async fn retries_server_errors() {
+        let url = serve(vec![
+            "503 Service Unavailable",
+            "429 Too Many Requests\r\nretry-after: 0",
+            "200 OK",
+        ])
+        .await;
+        let client = client(2);
+        let res = client.send(client.opts(Method::Get, url)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+    }
+
+    #[async_std::test]
+    async fn reports_retries() {
+        let url = serve(vec!["502 Bad Gateway", "502 Bad Gateway"]).await;
+        let client = client(1);
+        match client.send(client.opts(Method::Get, url)).await {
+            Err(err @ OroClientError::ResponseError { retries: 1, .. }) => {
+                assert!(err.to_string().contains("after 1 retry"))
+            }
+            res => panic!("unexpected result: {:?}", res.map(|res| res.status())),
+        }
+    }
+
+    #[async_std::test]
+    async fn does_not_retry_client_errors() {
+        let url = serve(vec!["404 Not Found", "200 OK"]).await;
+        let client = client(2);
+        match client.send(client.opts(Method::Get, url)).await {
+            Err(OroClientError::ResponseError {
+                status_code: StatusCode::NotFound,
+                retries: 0,
+                ..
+            }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|res| res.status())),
+        }
+    }
+
+    #[async_std::test]
+    async fn does_not_retry_posts() {
+        let url = serve(vec!["503 Service Unavailable", "200 OK"]).await;
+        let client = client(2);
+        let req = client.opts(Method::Post, url).body("{}");
+        match client.send(req).await {
+            Err(OroClientError::ResponseError {
+                status_code: StatusCode::ServiceUnavailable,
+                retries: 0,
+                ..
+            }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|res| res.status())),
+        }
+    }
+
+    /// Starts a server that accepts connections but never answers.
+    async fn stall() -> Url {
+        stall_counting().await.0
+    }
+
+    /// Like `stall`, but also reports each connection it accepts. The
+    /// returned task is the server itself.
+    async fn stall_counting() -> (Url, UnboundedReceiver<()>, task::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let (accepted, connections) = mpsc::unbounded();
+        let server = task::spawn(async move {
+            let mut streams = Vec::new();
+            let mut incoming = listener.incoming();
+            while let Some(Ok(stream)) = incoming.next().await {
+                let _ = accepted.unbounded_send(());
+                streams.push(stream);
+            }
+        });
+        (url, connections, server)
+    }
+
+    #[async_std::test]
+    async fn read_timeout() {
+        let url = stall().await;
+        let client = OroClientOpts::new()
+            .retries(0)
+            .read_timeout(Duration::from_millis(50))
+            .build();
+        match client.send(client.opts(Method::Get, url)).await {
+            Err(OroClientError::ReadTimeout { .. }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|res| res.status())),
+        }
+    }
+
+    #[async_std::test]
+    async fn request_timeout() {
+        let url = stall().await;
+        let client = OroClientOpts::new()
+            .retries(1)
+            .min_retry_delay(Duration::from_millis(1))
+            .request_timeout(Duration::from_millis(50))
+            .build();
+        match client.send(client.opts(Method::Get, url)).await {
+            Err(OroClientError::RequestTimeout { retries: 1, .. }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|res| res.status())),
+        }
+    }
+
+    /// Waits for a stalled server to report `expected` connections or
+    /// requests, and then makes sure no more come in for a little while.
+    async fn assert_received(events: &mut UnboundedReceiver<()>, expected: usize) {
+        for received in 0..expected {
+            match future::timeout(Duration::from_secs(5), events.next()).await {
+                Ok(Some(())) => {}
+                _ => panic!("expected {}, but only got {}", expected, received),
+            }
+        }
+        if let Ok(Some(())) = future::timeout(Duration::from_millis(100), events.next()).await {
+            panic!("got more than {}", expected);
+        }
+    }
+
+    /// Fires off a handful of requests at once at a server that never
+    /// answers, while `received` checks on what the server saw. The requests
+    /// are dropped once it's done.
+    async fn while_stalled<R: Future<Output = ()>>(client: &OroClient, url: &Url, received: R) {
+        let requests = (0..5).map(|_| client.send(client.opts(Method::Get, url.clone())));
+        let requests = join_all(requests);
+        futures::pin_mut!(requests, received);
+        if let Either::Left(_) = select(requests, received).await {
+            panic!("requests to a stalled server finished");
+        }
+    }
+
+    /// Checks that a handful of requests at once open exactly `expected`
+    /// connections between them.
+    async fn assert_connections_opened(opts: OroClientOpts, expected: usize) {
+        let (url, mut connections, server) = stall_counting().await;
+        let client = opts.retries(0).build();
+        while_stalled(&client, &url, assert_received(&mut connections, expected)).await;
+        server.cancel().await;
+    }
+
+    #[async_std::test]
+    async fn limits_connections() {
+        assert_connections_opened(OroClientOpts::new(), 5).await;
+        let opts = OroClientOpts::new().max_connections_per_host(2);
+        assert_connections_opened(opts, 2).await;
+        let opts = OroClientOpts::new().max_connections(1);
+        assert_connections_opened(opts, 1).await;
+    }
+
+    /// Starts an HTTP/2 server, without TLS, that answers every request
+    /// with its path. Also keeps count of the connections it accepted.
+    async fn serve_h2() -> (Url, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        task::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(Ok(stream)) = incoming.next().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                task::spawn(async move {
+                    let mut conn = h2::server::handshake(stream.compat()).await.unwrap();
+                    while let Some(Ok((req, mut respond))) = conn.accept().await {
+                        let body = Bytes::from(req.uri().path().to_owned());
+                        let res = http::Response::builder()
+                            .header("content-length", body.len())
+                            .body(())
+                            .unwrap();
+                        let mut stream = respond.send_response(res, false).unwrap();
+                        stream.send_data(body, true).unwrap();
+                    }
+                });
+            }
+        });
+        (url, connections)
+    }
+
+    #[async_std::test]
+    async fn multiplexes_http2_requests() {
+        let (url, connections) = serve_h2().await;
+        let client = OroClientOpts::new()
+            .retries(0)
+            .http2_prior_knowledge(true)
+            .no_proxy("*")
+            .build();
+        let requests = (0..5).map(|i| {
+            let url = url.join(&format!("pkg-{}", i)).unwrap();
+            let client = client.clone();
+            async move {
+                let mut res = client.send(client.opts(Method::Get, url)).await.unwrap();
+                assert_eq!(res.version(), Some(surf::http::Version::Http2_0));
+                res.body_string().await.unwrap()
+            }
+        });
+        let bodies = futures::future::join_all(requests).await;
+        assert_eq!(
+            bodies,
+            vec!["/pkg-0", "/pkg-1", "/pkg-2", "/pkg-3", "/pkg-4"]
+        );
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    /// Starts an HTTP/2 server, without TLS, that accepts requests but never
+    /// answers them, and reports each one it gets. The returned task is the
+    /// server itself. Connections are served until the client closes them.
+    async fn stall_h2_counting() -> (Url, UnboundedReceiver<()>, task::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let (received, requests) = mpsc::unbounded();
+        let server = task::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(Ok(stream)) = incoming.next().await {
+                let received = received.clone();
+                task::spawn(async move {
+                    let mut conn = h2::server::handshake(stream.compat()).await.unwrap();
+                    let mut pending = Vec::new();
+                    while let Some(Ok((_req, respond))) = conn.accept().await {
+                        let _ = received.unbounded_send(());
+                        pending.push(respond);
+                    }
+                });
+            }
+        });
+        (url, requests, server)
+    }
+
+    #[async_std::test]
+    async fn limits_http2_streams_per_host() {
+        let (url, mut requests, server) = stall_h2_counting().await;
+        let client = OroClientOpts::new()
+            .retries(0)
+            .http2_prior_knowledge(true)
+            .max_connections_per_host(2)
+            .no_proxy("*")
+            .build();
+        while_stalled(&client, &url, assert_received(&mut requests, 2)).await;
+        server.cancel().await;
+    }
+
+    #[async_std::test]
+    async fn sends_http_requests_through_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy: Url = format!("http://user:secret@{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let proxied = task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream.clone());
+            let mut writer = stream;
+            let mut head = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            writer
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            head
+        });
+        let client = OroClientOpts::new()
+            .retries(0)
+            .proxy(proxy)
+            .no_proxy("")
+            .build();
+        let url = "http://registry.example.com/foo?bar".parse().unwrap();
+        let res = client.send(client.opts(Method::Get, url)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        let head = proxied.await.to_lowercase();
+        assert!(head.starts_with("get http://registry.example.com/foo?bar http/1.1\r\n"));
+        assert!(head.contains("proxy-authorization: basic dxnlcjpzzwnyzxq=\r\n"));
+    }
+
+    #[async_std::test]
+    async fn reports_bad_tls_settings() {
+        let client = OroClientOpts::new().ca("not a certificate").build();
+        let url = "https://registry.example.com".parse().unwrap();
+        match client.send(client.opts(Method::Get, url)).await {
+            Err(OroClientError::TlsConfigError(message)) => {
+                assert!(message.contains("no PEM certificates"))
+            }
+            res => panic!("unexpected result: {:?}", res.map(|res| res.status())),
+        }
+    }
+}
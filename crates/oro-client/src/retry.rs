@@ -0,0 +1,114 @@
+use std::time::{Duration, SystemTime};
+
+use rand::Rng;
+use surf::http::{Method, StatusCode};
+use surf::Response;
+
+/// Returns true if sending this request again can't do any harm, even if
+/// the server already acted on it. A PUT with a body, like a publish, or a
+/// POST might have gone through before the connection dropped.
+pub(crate) fn is_idempotent(method: Method, has_body: bool) -> bool {
+    match method {
+        Method::Get | Method::Head | Method::Options => true,
+        Method::Put => !has_body,
+        _ => false,
+    }
+}
+
+/// Returns true if a response with this status is worth trying again:
+/// request timeouts, rate limiting and server errors.
+pub(crate) fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::RequestTimeout
+        || status == StatusCode::TooManyRequests
+        || status.is_server_error()
+}
+
+/// Returns true if a failed request is worth trying again. Errors carrying a
+/// client error status (like a bad URL) will fail the same way every time.
+pub(crate) fn is_retryable_error(err: &surf::Error) -> bool {
+    !err.status().is_client_error()
+}
+
+/// How long to wait before retry number `retry` (counting from zero):
+/// exponential backoff starting at `min`, capped at `max`, with up to half
+/// of it shaved off at random so clients failing together don't all come
+/// back at once.
+pub(crate) fn backoff(retry: u32, min: Duration, max: Duration) -> Duration {
+    let delay = min
+        .checked_mul(2u32.saturating_pow(retry))
+        .unwrap_or(max)
+        .min(max);
+    delay.mul_f64(rand::thread_rng().gen_range(0.5, 1.0))
+}
+
+/// Reads the delay a server asked for in a `Retry-After` header, which is
+/// either a number of seconds or an HTTP date.
+pub(crate) fn retry_after(res: &Response) -> Option<Duration> {
+    let value = res.header("Retry-After")?.last().as_str().trim();
+    parse_retry_after(value, SystemTime::now())
+}
+
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idempotent_methods() {
+        assert!(is_idempotent(Method::Get, false));
+        assert!(is_idempotent(Method::Head, false));
+        assert!(is_idempotent(Method::Options, false));
+        assert!(is_idempotent(Method::Put, false));
+        assert!(!is_idempotent(Method::Put, true));
+        assert!(!is_idempotent(Method::Post, false));
+        assert!(!is_idempotent(Method::Delete, false));
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        assert!(is_retryable_status(StatusCode::RequestTimeout));
+        assert!(is_retryable_status(StatusCode::TooManyRequests));
+        assert!(is_retryable_status(StatusCode::BadGateway));
+        assert!(is_retryable_status(StatusCode::ServiceUnavailable));
+        assert!(!is_retryable_status(StatusCode::NotFound));
+        assert!(!is_retryable_status(StatusCode::Unauthorized));
+    }
+
+    #[test]
+    fn backoff_grows_and_caps() {
+        let min = Duration::from_millis(100);
+        let max = Duration::from_secs(1);
+        for retry in 0..3 {
+            let delay = backoff(retry, min, max);
+            let full = min * 2u32.pow(retry);
+            assert!(delay >= full / 2 && delay <= full);
+        }
+        assert!(backoff(10, min, max) <= max);
+        assert!(backoff(u32::MAX, min, max) <= max);
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let now = SystemTime::now();
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        let later = httpdate::fmt_http_date(now + Duration::from_secs(30));
+        let delay = parse_retry_after(&later, now).unwrap();
+        assert!(delay > Duration::from_secs(28) && delay <= Duration::from_secs(30));
+        let earlier = httpdate::fmt_http_date(now - Duration::from_secs(30));
+        assert_eq!(
+            parse_retry_after(&earlier, now),
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+}
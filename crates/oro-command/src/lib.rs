@@ -1,7 +1,23 @@
+use std::path::PathBuf;
+
 use async_trait::async_trait;
-use oro_diagnostics::DiagnosticResult as Result;
+use directories::ProjectDirs;
+use oro_diagnostics::{AsDiagnostic, DiagnosticResult as Result};
+
+pub use network::NetworkOpts;
+
+mod network;
 
 #[async_trait]
 pub trait OroCommand {
     async fn execute(self) -> Result<()>;
 }
+
+/// The package cache commands use when they aren't pointed at one, under
+/// the platform's per-user cache directory.
+pub fn default_cache() -> Result<PathBuf> {
+    ProjectDirs::from("", "", "orogene")
+        .map(|d| d.cache_dir().to_owned())
+        .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+        .as_diagnostic("command::no_cache_dir")
+}
@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+
+use clap::Clap;
+use oro_client::{OroClientOpts, Secret};
+use oro_config::OroConfigLayer;
+use url::Url;
+
+/// Options for how commands talk to registries. Flatten these into any
+/// command that makes requests, and [`NetworkOpts::apply`] them to the
+/// client it builds.
+#[derive(Debug, Clap, OroConfigLayer)]
+pub struct NetworkOpts {
+    #[clap(
+        global = true,
+        about = "How many times to retry failed registry requests.",
+        long,
+        default_value = "2"
+    )]
+    fetch_retries: u32,
+    #[clap(
+        global = true,
+        about = "Maximum number of registry requests in flight at once.",
+        long
+    )]
+    max_connections: Option<usize>,
+    #[clap(
+        global = true,
+        about = "Maximum number of requests in flight to any one registry host. Defaults to 50.",
+        long
+    )]
+    max_connections_per_host: Option<usize>,
+    #[clap(
+        global = true,
+        about = "Proxy to send registry requests through. Defaults to $HTTP_PROXY.",
+        long
+    )]
+    proxy: Option<Url>,
+    #[clap(
+        global = true,
+        about = "Proxy to send HTTPS registry requests through. Defaults to $HTTPS_PROXY.",
+        long
+    )]
+    https_proxy: Option<Url>,
+    #[clap(
+        global = true,
+        about = "Comma-separated hosts to connect to without a proxy. Defaults to $NO_PROXY.",
+        long
+    )]
+    noproxy: Option<String>,
+    #[clap(
+        global = true,
+        about = "PEM-encoded CA certificate to trust for registry connections.",
+        long
+    )]
+    ca: Option<String>,
+    #[clap(
+        global = true,
+        about = "File of PEM-encoded CA certificates to trust for registry connections.",
+        long
+    )]
+    cafile: Option<PathBuf>,
+    #[clap(
+        global = true,
+        about = "PEM-encoded client certificate for registries that require one.",
+        long
+    )]
+    cert: Option<String>,
+    // Private key for `cert`. It's a secret, so it's only read from config,
+    // never from the command line.
+    #[clap(skip)]
+    key: Option<Secret>,
+    #[clap(
+        global = true,
+        about = "Whether to verify registry certificates (true, false).",
+        long
+    )]
+    strict_ssl: Option<bool>,
+}
+
+impl NetworkOpts {
+    /// Sets whichever of these options were given on `opts`.
+    pub fn apply(&self, mut opts: OroClientOpts) -> OroClientOpts {
+        opts = opts.retries(self.fetch_retries);
+        if let Some(max) = self.max_connections {
+            opts = opts.max_connections(max);
+        }
+        if let Some(max) = self.max_connections_per_host {
+            opts = opts.max_connections_per_host(max);
+        }
+        if let Some(proxy) = &self.proxy {
+            opts = opts.proxy(proxy.clone());
+        }
+        if let Some(proxy) = &self.https_proxy {
+            opts = opts.https_proxy(proxy.clone());
+        }
+        if let Some(noproxy) = &self.noproxy {
+            opts = opts.no_proxy(noproxy);
+        }
+        if let Some(ca) = &self.ca {
+            opts = opts.ca(ca);
+        }
+        if let Some(cafile) = &self.cafile {
+            opts = opts.cafile(cafile);
+        }
+        if let Some(cert) = &self.cert {
+            opts = opts.cert(cert);
+        }
+        if let Some(key) = &self.key {
+            opts = opts.key(key.clone());
+        }
+        if let Some(strict_ssl) = self.strict_ssl {
+            opts = opts.strict_ssl(strict_ssl);
+        }
+        opts
+    }
+}
@@ -26,11 +26,10 @@ struct OroCommandField {
     attrs: Vec<syn::Attribute>,
 }
 
-fn inner_type_of_option(ty: &syn::Type) -> Option<&syn::Type> {
+fn inner_type_of<'a>(ty: &'a syn::Type, wrapper: &str) -> Option<&'a syn::Type> {
     if let syn::Type::Path(syn::TypePath { path, .. }) = ty {
         if let Some(p) = path.segments.iter().next() {
-            // TODO: could be extended to support `Vec` too?
-            if p.ident != "Option" {
+            if p.ident != wrapper {
                 return None;
             }
 
@@ -44,11 +43,11 @@ fn inner_type_of_option(ty: &syn::Type) -> Option<&syn::Type> {
     None
 }
 
-fn oro_ignored(attr: &syn::Attribute) -> bool {
+fn oro_attr(attr: &syn::Attribute, name: &str) -> bool {
     if let Ok(syn::Meta::List(meta_list)) = attr.parse_meta() {
         if meta_list.path.get_ident().unwrap() == "oro_config" {
             if let Some(syn::NestedMeta::Meta(syn::Meta::Path(p))) = meta_list.nested.first() {
-                return p.get_ident().unwrap() == "ignore";
+                return p.get_ident().unwrap() == name;
             }
         }
     }
@@ -56,7 +55,12 @@ fn oro_ignored(attr: &syn::Attribute) -> bool {
 }
 
 fn should_be_ignored(field: &OroCommandField) -> bool {
-    field.attrs.iter().any(|attr| oro_ignored(attr))
+    field.attrs.iter().any(|attr| oro_attr(attr, "ignore"))
+}
+
+/// Flattened fields are layered by their own `OroConfigLayer` impl.
+fn should_be_flattened(field: &OroCommandField) -> bool {
+    field.attrs.iter().any(|attr| oro_attr(attr, "flatten"))
 }
 
 impl ToTokens for OroConfigLayer {
@@ -82,7 +86,11 @@ impl ToTokens for OroConfigLayer {
                 let ident = ident.clone().unwrap();
                 let lit_str = Lit::Str(LitStr::new(&ident.to_string(), ident.span()));
 
-                if let Some(inner) = inner_type_of_option(ty) {
+                if should_be_flattened(field) {
+                    quote! {
+                        self.#ident.layer_config(args, config)?;
+                    }
+                } else if let Some(inner) = inner_type_of(ty, "Option") {
                     quote! {
                         if args.occurrences_of(#lit_str) == 0 {
                             if let Ok(val) = config.get_str(#lit_str) {
@@ -90,6 +98,19 @@ impl ToTokens for OroConfigLayer {
                             }
                         }
                     }
+                } else if let Some(inner) = inner_type_of(ty, "Vec") {
+                    quote! {
+                        if args.occurrences_of(#lit_str) == 0 {
+                            let vals = list_values(config, #lit_str);
+                            if !vals.is_empty() {
+                                self.#ident = vals
+                                    .iter()
+                                    .map(|val| #inner::from_str(val))
+                                    .collect::<std::result::Result<_, _>>()
+                                    .map_err(|e| OroConfigError::ConfigParseError(Box::new(e)))?;
+                            }
+                        }
+                    }
                 } else {
                     quote! {
                         if args.occurrences_of(#lit_str) == 0 {
@@ -109,7 +130,7 @@ impl ToTokens for OroConfigLayer {
 
                 use oro_diagnostics::{DiagnosticError, DiagnosticResult as Result};
                 use clap::ArgMatches;
-                use oro_config::{OroConfig, OroConfigError, OroConfigLayer};
+                use oro_config::{list_values, OroConfig, OroConfigError, OroConfigLayer};
 
                 impl OroConfigLayer for #ident {
                     fn layer_config(&mut self, args: &ArgMatches, config: &OroConfig) -> Result<()> {
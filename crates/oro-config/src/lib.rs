@@ -8,12 +8,50 @@ use thiserror::Error;
 
 pub use oro_config_derive::*;
 
+pub use npmrc::Npmrc;
+
+mod npmrc;
+
 pub trait OroConfigLayer {
     fn layer_config(&mut self, _matches: &ArgMatches, _config: &OroConfig) -> Result<()> {
         Ok(())
     }
 }
 
+/// Reads the list `key` from `config` as strings. Lists can be configured as
+/// arrays, or as tables whose entries get turned into `key=value` strings.
+/// Tables of tables, like `registry_auth`, are flattened into npm-style
+/// `key:field=value` strings.
+pub fn list_values(config: &OroConfig, key: &str) -> Vec<String> {
+    if let Ok(vals) = config.get_array(key) {
+        vals.into_iter()
+            .filter_map(|val| val.into_str().ok())
+            .collect()
+    } else if let Ok(table) = config.get_table(key) {
+        table
+            .into_iter()
+            .flat_map(|(key, val)| match val.clone().into_table() {
+                Ok(fields) => fields
+                    .into_iter()
+                    .filter_map(|(field, val)| {
+                        val.into_str()
+                            .ok()
+                            .map(|val| format!("{}:{}={}", key, field, val))
+                    })
+                    .collect::<Vec<_>>(),
+                Err(_) => val
+                    .into_str()
+                    .ok()
+                    .map(|val| format!("{}={}", key, val))
+                    .into_iter()
+                    .collect(),
+            })
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
 #[derive(Debug, Error, Diagnostic)]
 pub enum OroConfigError {
     #[error(transparent)]
@@ -32,6 +70,9 @@ pub struct OroConfigOptions {
     env: bool,
     pkg_root: Option<PathBuf>,
     global_config_file: Option<PathBuf>,
+    npmrc: bool,
+    user_npmrc: Option<PathBuf>,
+    global_npmrc: Option<PathBuf>,
 }
 
 impl Default for OroConfigOptions {
@@ -41,6 +82,9 @@ impl Default for OroConfigOptions {
             env: true,
             pkg_root: None,
             global_config_file: None,
+            npmrc: true,
+            user_npmrc: None,
+            global_npmrc: None,
         }
     }
 }
@@ -70,8 +114,40 @@ impl OroConfigOptions {
         self
     }
 
+    /// Whether to read `.npmrc` files at all.
+    pub fn npmrc(mut self, npmrc: bool) -> Self {
+        self.npmrc = npmrc;
+        self
+    }
+
+    /// The user's `.npmrc`, usually `~/.npmrc`.
+    pub fn user_npmrc(mut self, file: Option<PathBuf>) -> Self {
+        self.user_npmrc = file;
+        self
+    }
+
+    /// npm's global `npmrc`, usually `$PREFIX/etc/npmrc`.
+    pub fn global_npmrc(mut self, file: Option<PathBuf>) -> Self {
+        self.global_npmrc = file;
+        self
+    }
+
+    /// Loads and merges all config sources. From lowest to highest
+    /// precedence: the global and user `.npmrc`s, the global ororc, the
+    /// project's `.npmrc`, `ORO_CONFIG_*` environment variables, and the
+    /// project's ororc. The `.npmrc`s are layered the same way npm does it.
     pub fn load(self) -> Result<OroConfig> {
         let mut c = OroConfig::new();
+        if self.npmrc && self.global {
+            if let Some(npmrc) = self.global_npmrc {
+                c.merge(Npmrc::new(npmrc))
+                    .map_err(OroConfigError::ConfigError)?;
+            }
+            if let Some(npmrc) = self.user_npmrc {
+                c.merge(Npmrc::new(npmrc))
+                    .map_err(OroConfigError::ConfigError)?;
+            }
+        }
         if self.global {
             if let Some(config_file) = self.global_config_file {
                 let path = config_file.display().to_string();
@@ -79,6 +155,12 @@ impl OroConfigOptions {
                     .map_err(OroConfigError::ConfigError)?;
             }
         }
+        if self.npmrc {
+            if let Some(root) = &self.pkg_root {
+                c.merge(Npmrc::new(root.join(".npmrc")))
+                    .map_err(OroConfigError::ConfigError)?;
+            }
+        }
         if self.env {
             c.merge(Environment::with_prefix("oro_config"))
                 .map_err(OroConfigError::ConfigError)?;
@@ -135,6 +217,72 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn lists() -> Result<()> {
+        let dir = tempdir()?;
+        let file = dir.path().join("ororc.toml");
+        fs::write(
+            &file,
+            r#"
+            tags = ["a", "b"]
+
+            [registry_auth."//npm.example.com/"]
+            _auth = "dXNlcjpwYXNz"
+            "#,
+        )?;
+        let config = OroConfigOptions::new()
+            .env(false)
+            .global_config_file(Some(file))
+            .load()?;
+        assert_eq!(
+            list_values(&config, "tags"),
+            vec![String::from("a"), String::from("b")]
+        );
+        assert_eq!(
+            list_values(&config, "registry_auth"),
+            vec![String::from("//npm.example.com/:_auth=dXNlcjpwYXNz")]
+        );
+        assert!(list_values(&config, "missing").is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn npmrc_precedence() -> Result<()> {
+        let dir = tempdir()?;
+        let global = dir.path().join("npmrc");
+        let user = dir.path().join("user.npmrc");
+        let root = dir.path().join("project");
+        fs::create_dir(&root)?;
+        fs::write(
+            &global,
+            "registry=https://global.example.com/\ncafile=/global.pem\nstrict-ssl=false",
+        )?;
+        fs::write(
+            &user,
+            "registry=https://user.example.com/\ncafile=/user.pem\n//user.example.com/:_authToken=abc",
+        )?;
+        fs::write(
+            root.join(".npmrc"),
+            "registry=https://project.example.com/\n//project.example.com/:_authToken=def",
+        )?;
+        let config = OroConfigOptions::new()
+            .env(false)
+            .global_npmrc(Some(global))
+            .user_npmrc(Some(user))
+            .pkg_root(Some(root))
+            .load()?;
+        assert_eq!(
+            config.get_str("registry")?,
+            String::from("https://project.example.com/")
+        );
+        assert_eq!(config.get_str("cafile")?, String::from("/user.pem"));
+        assert!(!config.get_bool("strict_ssl")?);
+        let auth = config.get_table("registry_auth")?;
+        assert!(auth.contains_key("//user.example.com/"));
+        assert!(auth.contains_key("//project.example.com/"));
+        Ok(())
+    }
+
     #[test]
     fn missing_config() -> Result<()> {
         let config = OroConfigOptions::new().global(false).env(false).load()?;
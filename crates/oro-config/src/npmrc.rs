@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use config::{ConfigError, Source, Value};
+
+/// An npm-style `.npmrc` file, as a config source. Missing files are treated
+/// as empty, just like npm does.
+///
+/// Keys are mapped onto orogene's config like so:
+///
+/// * Regular keys are lowercased and have their dashes turned into
+///   underscores, so `strict-ssl` is read as `strict_ssl`.
+/// * `@scope:registry` keys end up in the `scope_registry` table, keyed by
+///   scope (`@scope`).
+/// * Keys for a specific registry, like `//registry.example.com/:_authToken`,
+///   end up in the `registry_auth` table, keyed by URL prefix
+///   (`//registry.example.com/`), with their original names.
+///
+/// `${VAR}` in keys and values is replaced with the value of that environment
+/// variable, and just like in npm, it's an error for that variable to be
+/// unset. Sections are ignored, since npm doesn't use them for anything.
+#[derive(Clone, Debug)]
+pub struct Npmrc {
+    path: PathBuf,
+}
+
+impl Npmrc {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl Source for Npmrc {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => parse(&contents, &self.path.display().to_string(), |var| {
+                env::var(var).ok()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(ConfigError::Foreign(Box::new(e))),
+        }
+    }
+}
+
+/// Parses `contents`, looking up `${VAR}`s with `env`.
+fn parse(
+    contents: &str,
+    uri: &str,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<HashMap<String, Value>, ConfigError> {
+    let uri = uri.to_owned();
+    let mut props = HashMap::new();
+    let mut scope_registries = HashMap::new();
+    let mut registry_auth: HashMap<String, HashMap<String, Value>> = HashMap::new();
+    let mut in_section = false;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            in_section = true;
+            continue;
+        }
+        if in_section {
+            continue;
+        }
+        let (key, value) = match line.find('=') {
+            Some(idx) => (line[..idx].trim(), unquote(line[idx + 1..].trim())),
+            // npm treats a bare key as a flag that's turned on.
+            None => (line, "true".into()),
+        };
+        let key = expand_env(key, &env, &uri)?;
+        let value = Value::new(Some(&uri), expand_env(&value, &env, &uri)?);
+        if key.starts_with("//") {
+            if let Some(idx) = key.rfind(':') {
+                registry_auth
+                    .entry(key[..idx].into())
+                    .or_default()
+                    .insert(key[idx + 1..].into(), value);
+            }
+        } else if key.starts_with('@') && key.ends_with(":registry") {
+            let scope = &key[..key.len() - ":registry".len()];
+            scope_registries.insert(scope.to_lowercase(), value);
+        } else {
+            props.insert(key.to_lowercase().replace('-', "_"), value);
+        }
+    }
+    if !scope_registries.is_empty() {
+        props.insert(
+            "scope_registry".into(),
+            Value::new(Some(&uri), scope_registries),
+        );
+    }
+    if !registry_auth.is_empty() {
+        let registry_auth = registry_auth
+            .into_iter()
+            .map(|(prefix, fields)| (prefix, Value::new(Some(&uri), fields)))
+            .collect::<HashMap<_, _>>();
+        props.insert(
+            "registry_auth".into(),
+            Value::new(Some(&uri), registry_auth),
+        );
+    }
+    Ok(props)
+}
+
+/// Strips quotes from a value, or the trailing comment if it isn't quoted.
+fn unquote(value: &str) -> String {
+    for quote in &['"', '\''] {
+        if value.len() >= 2 && value.starts_with(*quote) && value.ends_with(*quote) {
+            return value[1..value.len() - 1].replace(&format!("\\{}", quote), &quote.to_string());
+        }
+    }
+    let mut unquoted = String::new();
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            ';' | '#' if escaped => {
+                unquoted.pop();
+                unquoted.push(c);
+            }
+            ';' | '#' => break,
+            _ => unquoted.push(c),
+        }
+        escaped = c == '\\';
+    }
+    unquoted.trim().into()
+}
+
+/// Replaces `${VAR}` with the value `env` has for `VAR`, failing if it has
+/// none. `\${VAR}` can be used to get a literal `${VAR}`.
+fn expand_env(
+    s: &str,
+    env: impl Fn(&str) -> Option<String>,
+    uri: &str,
+) -> Result<String, ConfigError> {
+    let mut expanded = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        if rest[..start].ends_with('\\') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str(&rest[start..=end]);
+        } else {
+            expanded.push_str(&rest[..start]);
+            match env(&rest[start + 2..end]) {
+                Some(value) => expanded.push_str(&value),
+                None => {
+                    return Err(ConfigError::Message(format!(
+                        "Failed to replace env in config {}: {}",
+                        uri,
+                        &rest[start..=end]
+                    )))
+                }
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    fn get(props: &HashMap<String, Value>, key: &str) -> String {
+        props[key].clone().into_str().unwrap()
+    }
+
+    fn test_env(var: &str) -> Option<String> {
+        match var {
+            "TOKEN" => Some("s3cret".into()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_npmrc() {
+        let props = parse(
+            r#"
+; a comment
+# another comment
+registry = https://registry.example.com/
+@myscope:registry=https://npm.example.com/
+//npm.example.com/:_authToken=${TOKEN}
+//npm.example.com/:always-auth=true
+strict-ssl = false ; turn it off
+cafile = "/etc/ssl/my ca.pem"
+escaped = \${TOKEN}
+
+[section]
+ignored = true
+"#,
+            ".npmrc",
+            test_env,
+        )
+        .unwrap();
+        assert_eq!(get(&props, "registry"), "https://registry.example.com/");
+        assert_eq!(get(&props, "strict_ssl"), "false");
+        assert_eq!(get(&props, "cafile"), "/etc/ssl/my ca.pem");
+        assert_eq!(get(&props, "escaped"), "${TOKEN}");
+        assert!(!props.contains_key("ignored"));
+
+        let scopes = props["scope_registry"].clone().into_table().unwrap();
+        assert_eq!(
+            scopes["@myscope"].clone().into_str().unwrap(),
+            "https://npm.example.com/"
+        );
+
+        let auth = props["registry_auth"].clone().into_table().unwrap();
+        let host = auth["//npm.example.com/"].clone().into_table().unwrap();
+        assert_eq!(host["_authToken"].clone().into_str().unwrap(), "s3cret");
+        assert_eq!(host["always-auth"].clone().into_str().unwrap(), "true");
+    }
+
+    #[test]
+    fn unset_env_is_an_error() {
+        assert!(parse("//npm.example.com/:_authToken=${UNSET}", ".npmrc", test_env).is_err());
+        assert!(parse("${UNSET}=true", ".npmrc", test_env).is_err());
+    }
+}
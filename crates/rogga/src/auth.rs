@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+
+use url::Url;
+
+use oro_client::Secret;
+
+use crate::error::{Result, RoggaError};
+
+/// Credentials to send to a registry, mirroring the kinds npm supports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegistryAuth {
+    /// A bearer token, like npm's `_authToken`.
+    Token(Secret),
+    /// A plain username and password, sent using HTTP basic auth.
+    Basic { username: String, password: Secret },
+    /// Pre-encoded basic auth credentials (base64 of `username:password`),
+    /// like npm's `_auth`.
+    Auth(Secret),
+}
+
+impl RegistryAuth {
+    /// The value of the `Authorization` header for these credentials.
+    pub(crate) fn header(&self) -> String {
+        match self {
+            RegistryAuth::Token(token) => format!("Bearer {}", token.expose()),
+            RegistryAuth::Basic { username, password } => format!(
+                "Basic {}",
+                base64::encode(format!("{}:{}", username, password.expose()))
+            ),
+            RegistryAuth::Auth(auth) => format!("Basic {}", auth.expose()),
+        }
+    }
+}
+
+/// A single npm-style per-registry setting, in `<prefix>:<key>=<value>`
+/// form, like `//registry.example.com/:_authToken=abc`. This is how entries
+/// of the `registry_auth` config table are passed around.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegistrySetting {
+    pub prefix: String,
+    pub key: String,
+    pub value: Secret,
+}
+
+impl FromStr for RegistrySetting {
+    type Err = RoggaError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || RoggaError::InvalidRegistrySetting(s.into());
+        let eq = s.find('=').ok_or_else(invalid)?;
+        let name = s[..eq].trim();
+        let colon = name.rfind(':').ok_or_else(invalid)?;
+        let prefix = name[..colon].trim();
+        let key = name[colon + 1..].trim();
+        if prefix.is_empty() || key.is_empty() {
+            return Err(invalid());
+        }
+        Ok(RegistrySetting {
+            prefix: prefix.into(),
+            key: key.into(),
+            value: Secret::new(s[eq + 1..].trim()),
+        })
+    }
+}
+
+/// The credentials for one URL prefix, put together from its
+/// [`RegistrySetting`]s.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegistryCredential {
+    pub prefix: String,
+    pub auth: RegistryAuth,
+    /// Whether to send these credentials along with tarballs from this
+    /// registry even when they're hosted somewhere else, like npm's
+    /// `always-auth`.
+    pub always_auth: bool,
+}
+
+impl RegistryCredential {
+    /// Groups `settings` by prefix and picks credentials for each the same
+    /// way npm does: `_authToken` wins over `username` and `_password`,
+    /// which win over `_auth`. Like npm, `_password` is expected to be
+    /// base64-encoded. Prefixes without usable credentials are skipped.
+    pub fn from_settings(settings: &[RegistrySetting]) -> Vec<Self> {
+        let mut prefixes: BTreeMap<&str, BTreeMap<String, &str>> = BTreeMap::new();
+        for setting in settings {
+            // Some config formats lowercase their keys, so match on them
+            // case-insensitively.
+            prefixes
+                .entry(&setting.prefix)
+                .or_default()
+                .insert(setting.key.to_lowercase(), setting.value.expose());
+        }
+        prefixes
+            .into_iter()
+            .filter_map(|(prefix, fields)| {
+                let auth = credential_auth(prefix, &fields)?;
+                Some(RegistryCredential {
+                    prefix: prefix.into(),
+                    auth,
+                    always_auth: fields.get("always-auth").map(|v| v.trim()) == Some("true"),
+                })
+            })
+            .collect()
+    }
+}
+
+fn credential_auth(prefix: &str, fields: &BTreeMap<String, &str>) -> Option<RegistryAuth> {
+    if let Some(token) = fields.get("_authtoken") {
+        return Some(RegistryAuth::Token(Secret::new(*token)));
+    }
+    if let (Some(username), Some(password)) = (fields.get("username"), fields.get("_password")) {
+        match base64::decode(password)
+            .ok()
+            .and_then(|password| String::from_utf8(password).ok())
+        {
+            Some(password) => {
+                return Some(RegistryAuth::Basic {
+                    username: (*username).into(),
+                    password: Secret::new(password),
+                })
+            }
+            None => log::warn!(
+                "Ignoring `_password` for {}: it isn't valid base64.",
+                prefix
+            ),
+        }
+    }
+    fields
+        .get("_auth")
+        .map(|auth| RegistryAuth::Auth(Secret::new(*auth)))
+}
+
+/// Registry credentials, keyed by the URL prefix they apply to. Like npm,
+/// prefixes ignore the scheme, so `https://registry.example.com/` and
+/// `//registry.example.com/` are the same key.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RegistryCredentials {
+    prefixes: Vec<(String, RegistryAuth)>,
+    always_auth: HashSet<String>,
+}
+
+impl RegistryCredentials {
+    pub(crate) fn insert(&mut self, prefix: &str, auth: RegistryAuth) {
+        let key = prefix_key(prefix);
+        self.prefixes.retain(|(existing, _)| existing != &key);
+        self.prefixes.push((key, auth));
+    }
+
+    /// Sends the credentials for `prefix` along with tarballs from that
+    /// registry no matter where they're hosted.
+    pub(crate) fn always_auth(&mut self, prefix: &str) {
+        self.always_auth.insert(prefix_key(prefix));
+    }
+
+    /// Finds the credentials for the longest prefix that matches `url`.
+    pub(crate) fn for_url(&self, url: &Url) -> Option<&RegistryAuth> {
+        self.lookup(url).map(|(_, auth)| auth)
+    }
+
+    /// Finds the credentials for a tarball that was published to `registry`.
+    /// Tarballs that live on the registry's host get the registry's
+    /// credentials even if they're outside its path, but credentials are
+    /// only sent to other hosts if the registry's prefix has `always-auth`.
+    pub(crate) fn for_tarball(&self, tarball: &Url, registry: &Url) -> Option<&RegistryAuth> {
+        self.for_url(tarball).or_else(|| {
+            let (prefix, auth) = self.lookup(registry)?;
+            if (tarball.host_str() == registry.host_str()
+                && tarball.port_or_known_default() == registry.port_or_known_default())
+                || self.always_auth.contains(prefix)
+            {
+                Some(auth)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn lookup(&self, url: &Url) -> Option<&(String, RegistryAuth)> {
+        let key = key(url);
+        self.prefixes
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+    }
+}
+
+/// Normalizes a configured prefix into the same form as [`key`].
+fn prefix_key(prefix: &str) -> String {
+    let url = if prefix.starts_with("//") {
+        format!("https:{}", prefix).parse::<Url>()
+    } else {
+        prefix.parse::<Url>()
+    };
+    let mut key = match url {
+        Ok(url) => key(&url),
+        // Not much of a URL, but someone might still want to match on it.
+        Err(_) => prefix.trim_start_matches("https:").to_owned(),
+    };
+    if !key.ends_with('/') {
+        key.push('/');
+    }
+    key
+}
+
+/// npm-style "nerfed" URL: `//host[:port]/path`, without the scheme,
+/// userinfo, query or fragment.
+fn key(url: &Url) -> String {
+    let mut key = format!("//{}", url.host_str().unwrap_or(""));
+    if let Some(port) = url.port() {
+        key.push_str(&format!(":{}", port));
+    }
+    key.push_str(url.path());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(token: &str) -> RegistryAuth {
+        RegistryAuth::Token(Secret::new(token))
+    }
+
+    fn settings(settings: &[&str]) -> Vec<RegistryCredential> {
+        let settings = settings
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect::<Vec<RegistrySetting>>();
+        RegistryCredential::from_settings(&settings)
+    }
+
+    #[test]
+    fn parses_settings() {
+        let setting: RegistrySetting = "//registry.example.com/:_auth=dXNlcjpwYXNz"
+            .parse()
+            .unwrap();
+        assert_eq!(setting.prefix, "//registry.example.com/");
+        assert_eq!(setting.key, "_auth");
+        assert_eq!(setting.value.expose(), "dXNlcjpwYXNz");
+        let setting: RegistrySetting = "//registry.example.com:8443/:username=user"
+            .parse()
+            .unwrap();
+        assert_eq!(setting.prefix, "//registry.example.com:8443/");
+        assert!("//registry.example.com/"
+            .parse::<RegistrySetting>()
+            .is_err());
+        assert!("_authToken=abc".parse::<RegistrySetting>().is_err());
+    }
+
+    #[test]
+    fn credentials_from_settings() {
+        assert_eq!(
+            settings(&[
+                "//a.example.com/:_authToken=abc",
+                "//a.example.com/:_auth=dXNlcjpwYXNz",
+                "//b.example.com/:username=user",
+                "//b.example.com/:_password=cGFzcw==",
+                "//b.example.com/:always-auth=true",
+                "//c.example.com/:_auth=dXNlcjpwYXNz",
+                "//d.example.com/:username=user",
+                "//e.example.com/:always-auth=true",
+            ]),
+            vec![
+                RegistryCredential {
+                    prefix: "//a.example.com/".into(),
+                    auth: token("abc"),
+                    always_auth: false,
+                },
+                RegistryCredential {
+                    prefix: "//b.example.com/".into(),
+                    auth: RegistryAuth::Basic {
+                        username: "user".into(),
+                        password: Secret::new("pass"),
+                    },
+                    always_auth: true,
+                },
+                RegistryCredential {
+                    prefix: "//c.example.com/".into(),
+                    auth: RegistryAuth::Auth(Secret::new("dXNlcjpwYXNz")),
+                    always_auth: false,
+                },
+            ]
+        );
+        // Keys that got lowercased along the way still count.
+        assert_eq!(
+            settings(&["//a.example.com/:_authtoken=abc"])[0].auth,
+            token("abc")
+        );
+    }
+
+    #[test]
+    fn config_to_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let ororc = dir.path().join("ororc.toml");
+        std::fs::write(
+            &ororc,
+            r#"
+            [registry_auth."//registry.example.com/"]
+            username = "user"
+            _password = "cGFzcw=="
+            "#,
+        )
+        .unwrap();
+        let config = oro_config::OroConfigOptions::new()
+            .env(false)
+            .global_config_file(Some(ororc))
+            .load()
+            .unwrap();
+        let settings = oro_config::list_values(&config, "registry_auth")
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect::<Vec<RegistrySetting>>();
+        let mut creds = RegistryCredentials::default();
+        for cred in RegistryCredential::from_settings(&settings) {
+            creds.insert(&cred.prefix, cred.auth);
+        }
+        let url = "https://registry.example.com/oro".parse().unwrap();
+        assert_eq!(
+            creds.for_url(&url).map(|auth| auth.header()),
+            Some("Basic dXNlcjpwYXNz".into())
+        );
+    }
+
+    #[test]
+    fn headers() {
+        assert_eq!(token("abc").header(), "Bearer abc");
+        let basic = RegistryAuth::Basic {
+            username: "user".into(),
+            password: Secret::new("pass"),
+        };
+        assert_eq!(basic.header(), "Basic dXNlcjpwYXNz");
+        let auth = RegistryAuth::Auth(Secret::new("dXNlcjpwYXNz"));
+        assert_eq!(auth.header(), "Basic dXNlcjpwYXNz");
+    }
+
+    #[test]
+    fn matches_longest_prefix() {
+        let mut creds = RegistryCredentials::default();
+        creds.insert("//registry.example.com/", token("host"));
+        creds.insert("https://registry.example.com/private", token("path"));
+        let url = |s: &str| s.parse::<Url>().unwrap();
+        assert_eq!(
+            creds.for_url(&url("https://registry.example.com/foo")),
+            Some(&token("host"))
+        );
+        assert_eq!(
+            creds.for_url(&url("http://registry.example.com/private/foo")),
+            Some(&token("path"))
+        );
+        assert_eq!(
+            creds.for_url(&url("https://registry.example.com/privateer")),
+            Some(&token("host"))
+        );
+        assert_eq!(creds.for_url(&url("https://registry.npmjs.org/foo")), None);
+        assert_eq!(
+            creds.for_url(&url("https://registry.example.com.evil.com/foo")),
+            None
+        );
+        assert_eq!(
+            creds.for_url(&url("https://registry.example.com:8443/foo")),
+            None
+        );
+    }
+
+    #[test]
+    fn tarballs_only_get_credentials_on_the_same_host() {
+        let mut creds = RegistryCredentials::default();
+        creds.insert("//registry.example.com/npm/", token("npm"));
+        let registry = "https://registry.example.com/npm/".parse().unwrap();
+        let tarball = |s: &str| s.parse::<Url>().unwrap();
+        assert_eq!(
+            creds.for_tarball(
+                &tarball("https://registry.example.com/npm/foo/-/foo-1.0.0.tgz"),
+                &registry
+            ),
+            Some(&token("npm"))
+        );
+        assert_eq!(
+            creds.for_tarball(
+                &tarball("https://registry.example.com/tarballs/foo-1.0.0.tgz"),
+                &registry
+            ),
+            Some(&token("npm"))
+        );
+        assert_eq!(
+            creds.for_tarball(&tarball("https://cdn.example.com/foo-1.0.0.tgz"), &registry),
+            None
+        );
+        creds.always_auth("//registry.example.com/npm/");
+        assert_eq!(
+            creds.for_tarball(&tarball("https://cdn.example.com/foo-1.0.0.tgz"), &registry),
+            Some(&token("npm"))
+        );
+    }
+}
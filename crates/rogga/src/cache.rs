@@ -1,114 +1,677 @@
-/*
-use std::collections::HashMap;
+//! Per-file package storage in cacache.
+//!
+//! Instead of storing whole tarballs, each file in a package is written into
+//! cacache as its own content entry, and a package index listing those
+//! entries gets stored under a key derived from the tarball's sha512.
+//! Packages can then be materialized straight from the cache, with identical
+//! files shared across every package that contains them.
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use async_compression::futures::bufread::GzipDecoder;
+use async_std::io::{self, BufReader};
 use async_std::prelude::*;
-use async_tar::Archive;
-use cacache::WriteOpts;
-use futures::{self, io::AsyncRead};
-use ssri::Integrity;
+use async_tar::{Archive, EntryType};
+use cacache::{ContentFormat, WriteOpts};
+use futures::io::AsyncRead;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use ssri::{Algorithm, Integrity, IntegrityOpts};
+use url::Url;
 
-use crate::error::{Internal, Result, RoggaError};
+use crate::error::{Result, RoggaError};
+use crate::extract;
 use crate::integrity::AsyncIntegrity;
 
-pub async fn from_tarball<P, R>(cache: P, tarball: R) -> Result<Integrity>
+/// A single entry in a cached package's index.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Path of this entry, relative to the package directory.
+    pub path: PathBuf,
+    pub kind: IndexEntryKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IndexEntryKind {
+    /// A regular file, stored in the cache under `integrity`.
+    File {
+        integrity: String,
+        size: u64,
+        mode: u32,
+    },
+    /// A symlink pointing at `target`, relative to its own location.
+    Symlink { target: PathBuf },
+}
+
+/// Writes every file in `tarball` into `cache` as its own content entry, and
+/// records an index of the package's contents keyed by the tarball's
+/// sha512. If `expected` is given, the tarball is also hashed with its
+/// strongest algorithm and checked against it. Returns the tarball's
+/// integrity, which always includes the sha512.
+pub async fn from_tarball<P, R>(
+    cache: P,
+    tarball: R,
+    expected: Option<&Integrity>,
+) -> Result<Integrity>
 where
-    P: AsRef<std::path::Path>,
+    P: AsRef<Path>,
     R: AsyncRead + Unpin + Send + Sync,
 {
-    use async_std::io::{self, BufReader};
-    let path = std::path::PathBuf::from(cache.as_ref());
-
-    let sri_builder = AsyncIntegrity::new(BufReader::new(tarball));
+    let cache = cache.as_ref();
+    use_raw_content(cache).await?;
+    // File content is only referenced once the package index is written at
+    // the very end, so keep other processes' garbage collection off it until
+    // then.
+    let _lock = cacache::lock_shared(cache).await?;
+    // The package gets keyed on what was actually written, not on whatever
+    // the registry claimed, which might only be a sha1.
+    let mut opts = IntegrityOpts::new().algorithm(Algorithm::Sha512);
+    if let Some(algorithm) = expected.map(|sri| sri.pick_algorithm()) {
+        if algorithm != Algorithm::Sha512 {
+            opts = opts.algorithm(algorithm);
+        }
+    }
+    let sri_builder = AsyncIntegrity::new(BufReader::new(tarball), opts);
     let decoder = GzipDecoder::new(BufReader::new(sri_builder));
     let ar = Archive::new(decoder);
-    let mut entries = ar.clone().entries().to_internal()?;
-    let mut entry_hash = HashMap::new();
+    let mut entries = ar
+        .clone()
+        .entries()
+        .map_err(|e| RoggaError::ExtractIoError(e, None))?;
+    let mut index = Vec::new();
 
     while let Some(file) = entries.next().await {
-        let f = file.to_internal()?;
+        let f = file.map_err(|e| RoggaError::ExtractIoError(e, None))?;
         let header = f.header();
-        let mode = header.mode().to_internal()?;
-        let size = header.size().to_internal()?;
-        let path = path.clone();
-        let key = f.path().to_internal()?.display().to_string();
-
-        let mut writer = WriteOpts::new()
-            .size(size as usize)
-            .open_hash(&path)
-            .await
-            .to_internal()?;
-
-        io::copy(f, async_std::io::BufWriter::new(&mut writer))
-            .await
-            .to_internal()?;
-
-        let sri = writer.commit().await.to_internal()?;
-        entry_hash.insert(key, (sri, size, mode));
+        let entry_path = header
+            .path()
+            .map_err(|e| RoggaError::ExtractIoError(e, None))?
+            .into_owned();
+        let path = match extract::strip_entry_path(&entry_path)? {
+            Some(path) => path,
+            None => continue,
+        };
+        match header.entry_type() {
+            EntryType::Regular => {
+                let mode = header
+                    .mode()
+                    .map_err(|e| RoggaError::ExtractIoError(e, None))?;
+                let size = header
+                    .size()
+                    .map_err(|e| RoggaError::ExtractIoError(e, None))?;
+                let mut writer = WriteOpts::new()
+                    .size(size as usize)
+                    .open_hash(cache)
+                    .await?;
+                io::copy(f, &mut writer)
+                    .await
+                    .map_err(|e| RoggaError::ExtractIoError(e, None))?;
+                let integrity = writer.commit().await?;
+                index.push(IndexEntry {
+                    path,
+                    kind: IndexEntryKind::File {
+                        integrity: integrity.to_string(),
+                        size,
+                        mode,
+                    },
+                });
+            }
+            EntryType::Link => {
+                // Hard links just turn into another reference to the same
+                // content.
+                let target = header
+                    .link_name()
+                    .map_err(|e| RoggaError::ExtractIoError(e, None))?
+                    .and_then(|target| extract::strip_entry_path(&target).transpose())
+                    .transpose()?;
+                let kind = index
+                    .iter()
+                    .find(|entry| Some(&entry.path) == target.as_ref())
+                    .map(|entry| entry.kind.clone())
+                    .ok_or_else(|| {
+                        RoggaError::MaliciousArchive(
+                            entry_path.clone(),
+                            "hard link target is not a file in the package",
+                        )
+                    })?;
+                index.push(IndexEntry { path, kind });
+            }
+            EntryType::Symlink => {
+                let target = header
+                    .link_name()
+                    .map_err(|e| RoggaError::ExtractIoError(e, None))?
+                    .ok_or_else(|| {
+                        RoggaError::MaliciousArchive(entry_path.clone(), "symlink has no target")
+                    })?
+                    .into_owned();
+                if !extract::link_stays_inside(&path, &target) {
+                    return Err(RoggaError::MaliciousArchive(
+                        entry_path,
+                        "symlink points outside of the package",
+                    ));
+                }
+                index.push(IndexEntry {
+                    path,
+                    kind: IndexEntryKind::Symlink { target },
+                });
+            }
+            other => {
+                log::trace!("Skipping unsupported {:?} entry: {:?}", other, entry_path);
+            }
+        }
     }
 
     std::mem::drop(entries);
-    let (sri, mut reader) = ar
+    // Whatever's left in the stream (like tar padding) still needs to go
+    // through the hasher for the integrity to be right.
+    let mut reader = ar
         .into_inner()
-        .map_err(|_| RoggaError::MiscError("Failed to get inner Read".into()))
-        .to_internal()?
+        .map_err(|_| RoggaError::MiscError("Failed to get inner Read".into()))?
         .into_inner()
-        .into_inner()
-        .inner_result();
+        .into_inner();
     let mut buf = Vec::new();
-    reader.read_to_end(&mut buf).await.to_internal()?;
+    reader
+        .read_to_end(&mut buf)
+        .await
+        .map_err(|e| RoggaError::ExtractIoError(e, None))?;
+    let sri = reader.into_result();
 
+    if let Some(expected) = expected {
+        if expected.matches(&sri).is_none() {
+            return Err(RoggaError::IntegrityMismatch(expected.clone(), sri));
+        }
+    }
+
+    // The package's index entry keeps its files alive for cacache's garbage
+    // collection and eviction.
+    let files = index
+        .iter()
+        .filter_map(|entry| match &entry.kind {
+            IndexEntryKind::File { integrity, .. } => integrity.parse().ok(),
+            _ => None,
+        })
+        .collect::<Vec<Integrity>>();
+    let mut writer = WriteOpts::new()
+        .references(files)
+        .open(
+            cache,
+            package_key(&sri).expect("from_tarball always computes a sha512"),
+        )
+        .await?;
+    writer
+        .write_all(&bincode::serialize(&index)?)
+        .await
+        .map_err(|e| RoggaError::ExtractIoError(e, None))?;
+    writer.commit().await?;
     log::trace!("Finished caching tarball contents from stream");
-    Ok(cacache::write(
-        &path,
-        format!("orogene::pkg::{}", sri.to_string()),
-        bincode::serialize(&entry_hash).to_internal()?,
-    )
-    .await
-    .to_internal()?)
+    Ok(sri)
 }
 
-pub async fn tarball_itself<P, R>(cache: P, tarball: R) -> Result<Integrity>
+/// Response headers make-fetch-happen keeps in the cache entries it writes.
+const NPM_RESPONSE_HEADERS: &[&str] = &[
+    "cache-control",
+    "content-encoding",
+    "content-language",
+    "content-type",
+    "date",
+    "etag",
+    "expires",
+    "last-modified",
+    "link",
+    "location",
+    "pragma",
+    "vary",
+];
+
+/// Returns the key npm (through make-fetch-happen) caches a tarball
+/// downloaded from `url` under.
+pub fn npm_tarball_key(url: &Url) -> String {
+    format!("make-fetch-happen:request-cache:{}", url)
+}
+
+/// Stores a whole tarball in the cache the way npm would, under
+/// `npm_tarball_key`, along with the response `headers` it was served with.
+/// This is only useful for caches shared with npm, which should use
+/// `cacache::IndexFormat::Npm` and raw content.
+pub async fn tarball_to_npm_cache<P, R>(
+    cache: P,
+    url: &Url,
+    headers: &[(String, String)],
+    tarball: R,
+    expected: Option<&Integrity>,
+) -> Result<Integrity>
 where
-    P: AsRef<std::path::Path>,
+    P: AsRef<Path>,
     R: AsyncRead + Unpin + Send + Sync,
 {
-    use async_std::io::{self, BufReader};
-    let path = std::path::PathBuf::from(cache.as_ref());
-
-    let reader = BufReader::new(tarball);
-    let mut writer = WriteOpts::new().open_hash(&path).await.to_internal()?;
-
-    io::copy(reader, async_std::io::BufWriter::new(&mut writer))
+    use_raw_content(cache.as_ref()).await?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or(0);
+    // make-fetch-happen only keeps request headers that affect what the
+    // registry sends back, and we don't send any of those for tarballs.
+    let res_headers = headers
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), value))
+        .filter(|(name, _)| NPM_RESPONSE_HEADERS.contains(&name.as_str()))
+        .map(|(name, value)| (name, json!(value)))
+        .collect::<serde_json::Map<_, _>>();
+    let mut opts = WriteOpts::new().metadata(json!({
+        "time": now,
+        "url": url.to_string(),
+        "reqHeaders": {},
+        "resHeaders": res_headers,
+        "options": { "compress": true },
+    }));
+    opts = match expected {
+        Some(sri) => opts.algorithm(sri.to_hex().0).integrity(sri.clone()),
+        None => opts.algorithm(Algorithm::Sha512),
+    };
+    let mut writer = opts.open(cache.as_ref(), npm_tarball_key(url)).await?;
+    io::copy(tarball, &mut writer)
         .await
-        .to_internal()?;
+        .map_err(|e| RoggaError::ExtractIoError(e, None))?;
+    let sri = writer.commit().await?;
+    log::trace!("Cached tarball from {} for npm", url);
+    Ok(sri)
+}
 
-    let sri = writer.commit().await.to_internal()?;
+/// Returns the index for a package previously stored with `from_tarball`, if
+/// there is one.
+pub async fn read_index<P: AsRef<Path>>(
+    cache: P,
+    sri: &Integrity,
+) -> Result<Option<Vec<IndexEntry>>> {
+    let key = match package_key(sri) {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    if cacache::metadata(cache.as_ref(), &key).await?.is_none() {
+        return Ok(None);
+    }
+    let data = cacache::read(cache.as_ref(), &key).await?;
+    Ok(Some(bincode::deserialize(&data)?))
+}
 
-    Ok(
-        cacache::write(&path, format!("orogene::pkg::{}", sri.to_string()), b"")
-            .await
-            .to_internal()?,
-    )
+/// Returns true if a package with this tarball integrity has been stored in
+/// the cache with `from_tarball`, and its index and every file it lists are
+/// still there. Packages with missing pieces need to be stored again, and
+/// packages can only be found by their sha512, so this is always false for
+/// integrities without one.
+pub async fn has_package<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<bool> {
+    let cache = cache.as_ref();
+    let key = match package_key(sri) {
+        Some(key) => key,
+        None => return Ok(false),
+    };
+    let entry = match cacache::metadata(cache, key).await? {
+        Some(entry) => entry,
+        None => return Ok(false),
+    };
+    let index: Vec<IndexEntry> = match cacache::read_hash(cache, &entry.integrity).await {
+        Ok(data) => match bincode::deserialize(&data) {
+            Ok(index) => index,
+            Err(_) => return Ok(false),
+        },
+        Err(_) => {
+            log::debug!("Index for cached package {} is missing", sri);
+            return Ok(false);
+        }
+    };
+    for entry in index {
+        if let IndexEntryKind::File { integrity, .. } = entry.kind {
+            let exists = match integrity.parse() {
+                Ok(integrity) => cacache::exists(cache, &integrity).await,
+                Err(_) => false,
+            };
+            if !exists {
+                log::debug!("{:?} in cached package {} is missing", entry.path, sri);
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
 }
 
-pub async fn tarball_to_mem<P, R>(cache: P, tarball: R) -> Result<Integrity>
+/// Materializes a package previously stored with `from_tarball` into `dir`.
+/// Files are linked from the cache whenever possible.
+pub async fn to_dir<P, Q>(cache: P, sri: &Integrity, dir: Q) -> Result<()>
 where
-    P: AsRef<std::path::Path>,
-    R: AsyncRead + Unpin + Send + Sync,
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
 {
-    use async_std::io::BufReader;
-    let path = std::path::PathBuf::from(cache.as_ref());
+    let cache = cache.as_ref();
+    let dir = dir.as_ref();
+    let index = read_index(cache, sri).await?.ok_or_else(|| {
+        RoggaError::CacheError(cacache::Error::EntryNotFound(
+            cache.to_path_buf(),
+            sri.to_string(),
+        ))
+    })?;
+    extract::mkdirp(dir).await?;
+    let mut symlinks = Vec::new();
+    for entry in index {
+        extract::check_no_symlinks(dir, &entry.path).await?;
+        let path = dir.join(&entry.path);
+        extract::prepare_target(&path).await?;
+        match entry.kind {
+            IndexEntryKind::File {
+                integrity, mode, ..
+            } => {
+                let integrity = integrity
+                    .parse()
+                    .map_err(|e: ssri::Error| RoggaError::MiscError(e.to_string()))?;
+                extract::place_from_cache(cache, &integrity, &path, mode).await?;
+            }
+            IndexEntryKind::Symlink { target } => {
+                extract::symlink(&target, &path).await?;
+                symlinks.push(path);
+            }
+        }
+    }
+    extract::check_symlinks(dir, symlinks).await
+}
 
-    let mut reader = BufReader::new(tarball);
-    let mut buf = Vec::new();
-    reader.read_to_end(&mut buf).await.to_internal()?;
-    let sri = Integrity::from(&buf);
+/// Package files get linked out of the cache, which only works for raw
+/// content. New caches get set up for it, but caches that already store
+/// something else are left alone: converting them is up to `oro cache
+/// migrate`.
+async fn use_raw_content(cache: &Path) -> Result<()> {
+    match cacache::init_content_format(cache, ContentFormat::Raw).await? {
+        ContentFormat::Raw => Ok(()),
+        format => Err(RoggaError::ContentFormatMismatch(cache.into(), format)),
+    }
+}
+
+/// Cache key for a package index, which is always the tarball's sha512
+/// hash, so lookups work regardless of how many other hashes the requested
+/// integrity happens to include. Integrities without a sha512 have no key.
+fn package_key(sri: &Integrity) -> Option<String> {
+    sri.hashes
+        .iter()
+        .find(|hash| hash.algorithm == Algorithm::Sha512)
+        .map(|hash| format!("orogene::pkg::{}", hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(
-        cacache::write(&path, format!("orogene::pkg::{}", sri.to_string()), b"")
+    use async_compression::futures::bufread::GzipEncoder;
+    use async_tar::{Builder, Header};
+
+    /// Builds a gzipped package tarball out of `files`.
+    async fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (path, data) in files {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, format!("package/{}", path), *data)
+                .await
+                .unwrap();
+        }
+        let tar = builder.into_inner().await.unwrap();
+        let mut gzipped = Vec::new();
+        GzipEncoder::new(&tar[..])
+            .read_to_end(&mut gzipped)
             .await
-            .to_internal()?,
-    )
+            .unwrap();
+        gzipped
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn links_files_from_a_default_cache() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = tmp.path().join("cache");
+        let dir = tmp.path().join("pkg");
+        let data = tarball(&[("index.js", &b"module.exports = 1"[..])]).await;
+        let sri = from_tarball(&cache, &data[..], None).await.unwrap();
+        to_dir(&cache, &sri, &dir).await.unwrap();
+
+        let file = dir.join("index.js");
+        assert_eq!(
+            async_std::fs::read(&file).await.unwrap(),
+            b"module.exports = 1"
+        );
+        // Filesystems that support reflinks give the file its own inode
+        // instead, which is just as good.
+        let probe = tmp.path().join("probe");
+        std::fs::write(tmp.path().join("original"), b"").unwrap();
+        if reflink::reflink(tmp.path().join("original"), &probe).is_err() {
+            assert!(std::fs::metadata(&file).unwrap().nlink() > 1);
+        }
+    }
+
+    #[async_std::test]
+    async fn keys_packages_on_the_computed_sha512() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = tmp.path().join("cache");
+        let data = tarball(&[("index.js", &b"module.exports = 1"[..])]).await;
+        let sha1 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha1)
+            .chain(&data)
+            .result();
+        let sha512 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain(&data)
+            .result();
+        let sri = from_tarball(&cache, &data[..], Some(&sha1)).await.unwrap();
+        assert!(sri.matches(&sha1).is_some());
+        assert!(sri.matches(&sha512).is_some());
+        assert!(has_package(&cache, &sha512).await.unwrap());
+        // A sha1 alone isn't enough to find the package again.
+        assert!(!has_package(&cache, &sha1).await.unwrap());
+        assert!(read_index(&cache, &sha1).await.unwrap().is_none());
+    }
+
+    #[async_std::test]
+    async fn missing_files_need_caching_again() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = tmp.path().join("cache");
+        let data = tarball(&[
+            ("index.js", &b"module.exports = 1"[..]),
+            ("package.json", &b"{}"[..]),
+        ])
+        .await;
+        let sri = from_tarball(&cache, &data[..], None).await.unwrap();
+        assert!(has_package(&cache, &sri).await.unwrap());
+
+        let gone = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain(b"{}")
+            .result();
+        cacache::remove_hash(&cache, &gone).await.unwrap();
+        assert!(!has_package(&cache, &sri).await.unwrap());
+
+        from_tarball(&cache, &data[..], Some(&sri)).await.unwrap();
+        assert!(has_package(&cache, &sri).await.unwrap());
+        let dir = tmp.path().join("pkg");
+        to_dir(&cache, &sri, &dir).await.unwrap();
+        assert_eq!(
+            async_std::fs::read(dir.join("package.json")).await.unwrap(),
+            b"{}"
+        );
+    }
+
+    #[async_std::test]
+    async fn leaves_existing_caches_alone() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = tmp.path().join("cache");
+        cacache::write(&cache, "key", b"hello").await.unwrap();
+        let data = tarball(&[("index.js", &b"module.exports = 1"[..])]).await;
+        assert!(matches!(
+            from_tarball(&cache, &data[..], None).await,
+            Err(RoggaError::ContentFormatMismatch(..))
+        ));
+        assert_eq!(
+            cacache::content_format(&cache).await.unwrap(),
+            ContentFormat::Snappy
+        );
+        assert_eq!(cacache::read(&cache, "key").await.unwrap(), b"hello");
+    }
+
+    #[async_std::test]
+    async fn packages_survive_verify() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = tmp.path().join("cache");
+        let data = tarball(&[
+            ("index.js", &b"module.exports = 1"[..]),
+            ("lib/util.js", &b"exports.util = true"[..]),
+        ])
+        .await;
+        let sri = from_tarball(&cache, &data[..], None).await.unwrap();
+
+        // Package files aren't stored under keys of their own, so only the
+        // package index's references keep them from being collected.
+        let stats = cacache::verify(&cache).await.unwrap();
+        assert_eq!(stats.reclaimed_count, 0);
+        assert_eq!(stats.bad_content_count, 0);
+        assert!(has_package(&cache, &sri).await.unwrap());
+
+        let dir = tmp.path().join("pkg");
+        to_dir(&cache, &sri, &dir).await.unwrap();
+        assert_eq!(
+            async_std::fs::read(dir.join("lib/util.js")).await.unwrap(),
+            b"exports.util = true"
+        );
+    }
+
+    /// Written by npm 10, with `npm cache add` of a tarball served from
+    /// this URL.
+    const NPM_FIXTURE_URL: &str = "http://127.0.0.1:8765/oro-fixture-1.0.0.tgz";
+
+    fn copy_dir(from: &Path, to: &Path) {
+        std::fs::create_dir_all(to).unwrap();
+        for entry in std::fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_type().unwrap().is_dir() {
+                copy_dir(&entry.path(), &to.join(entry.file_name()));
+            } else {
+                std::fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+            }
+        }
+    }
+
+    async fn npm_cache(dir: &Path) {
+        cacache::set_index_format(dir, cacache::IndexFormat::Npm)
+            .await
+            .unwrap();
+        cacache::set_content_format(dir, ContentFormat::Raw)
+            .await
+            .unwrap();
+    }
+
+    /// The single entry in an index bucket, as JSON.
+    fn bucket_entry(bucket: &Path) -> serde_json::Value {
+        let bucket = std::fs::read_to_string(bucket).unwrap();
+        let line = bucket.trim().splitn(2, '\t').nth(1).unwrap();
+        serde_json::from_str(line).unwrap()
+    }
+
+    /// Where npm keeps content with this integrity, relative to the cache.
+    fn path_for(sri: &Integrity) -> PathBuf {
+        let (algorithm, hex) = sri.to_hex();
+        PathBuf::from("content-v2")
+            .join(algorithm.to_string())
+            .join(&hex[0..2])
+            .join(&hex[2..4])
+            .join(&hex[4..])
+    }
+
+    #[async_std::test]
+    async fn reads_tarballs_cached_by_npm() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = tmp.path().join("cache");
+        copy_dir(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/npm-cache"),
+            &cache,
+        );
+        npm_cache(&cache).await;
+
+        let url: Url = NPM_FIXTURE_URL.parse().unwrap();
+        let entry = cacache::metadata(&cache, npm_tarball_key(&url))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.metadata["url"], NPM_FIXTURE_URL);
+        let data = cacache::read_hash(&cache, &entry.integrity).await.unwrap();
+        let sri = from_tarball(&cache, &data[..], Some(&entry.integrity))
+            .await
+            .unwrap();
+        let dir = tmp.path().join("pkg");
+        to_dir(&cache, &sri, &dir).await.unwrap();
+        assert_eq!(
+            async_std::fs::read_to_string(dir.join("index.js"))
+                .await
+                .unwrap(),
+            "module.exports = \"hello from npm\"\n"
+        );
+    }
+
+    #[async_std::test]
+    async fn writes_tarballs_like_npm() {
+        let tmp = tempfile::tempdir().unwrap();
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/npm-cache");
+        let cache = tmp.path().join("cache");
+        npm_cache(&cache).await;
+
+        let url: Url = NPM_FIXTURE_URL.parse().unwrap();
+        let bucket = "index-v5/53/e9/6a37d68fd1d6716f3177d6638b9a94f62facaacbb48e2c8cde29f3b86e1e";
+        let expected = bucket_entry(&fixture.join(bucket));
+        let integrity: Integrity = expected["integrity"].as_str().unwrap().parse().unwrap();
+        let content = path_for(&integrity);
+        let data = std::fs::read(fixture.join(&content)).unwrap();
+        let headers = [
+            ("Content-Type", "application/x-tar"),
+            ("Date", "Sun, 18 Oct 2026 16:25:40 GMT"),
+            ("Last-Modified", "Sun, 18 Oct 2026 16:25:39 GMT"),
+            ("Connection", "keep-alive"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<Vec<_>>();
+        let sri = tarball_to_npm_cache(&cache, &url, &headers, &data[..], Some(&integrity))
+            .await
+            .unwrap();
+        assert_eq!(sri, integrity);
+
+        // npm would find both the entry and its content right where it put
+        // them itself.
+        assert_eq!(std::fs::read(cache.join(&content)).unwrap(), data);
+        let mut written = bucket_entry(&cache.join(bucket));
+        let mut expected = expected;
+        for entry in [&mut written, &mut expected].iter_mut() {
+            let entry = entry.as_object_mut().unwrap();
+            assert!(entry.remove("time").unwrap().is_u64());
+            let metadata = entry["metadata"].as_object_mut().unwrap();
+            assert!(metadata.remove("time").unwrap().is_u64());
+        }
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn package_key_uses_sha512() {
+        let sha512 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain(b"hello")
+            .result();
+        let sha1 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha1)
+            .chain(b"hello")
+            .result();
+        let both: Integrity = format!("{} {}", sha1, sha512).parse().unwrap();
+        assert_eq!(package_key(&both), package_key(&sha512));
+        assert_eq!(
+            package_key(&sha512),
+            Some(format!("orogene::pkg::{}", sha512.to_string()))
+        );
+        assert_eq!(package_key(&sha1), None);
+    }
 }
-*/
@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use oro_diagnostics::{Diagnostic, DiagnosticCategory, Explain, Meta};
 use oro_node_semver::Version;
 use oro_package_spec::PackageSpec;
+use ssri::Integrity;
 use thiserror::Error;
 
 use crate::resolver::ResolverError;
@@ -52,6 +53,12 @@ pub enum RoggaError {
     #[label("rogga::io::extract")]
     ExtractIoError(#[source] std::io::Error, Option<PathBuf>),
 
+    #[error("Refusing to extract `{}` from tarball: {1}.", .0.display())]
+    #[category(Fs)]
+    #[label("rogga::extract::malicious_archive")]
+    #[advice("This package's tarball tried to write outside of its own directory, which is a sign of a malicious or broken package. Please report it to the package maintainers or your registry.")]
+    MaliciousArchive(PathBuf, &'static str),
+
     #[error(transparent)]
     OroClientError(
         #[from]
@@ -59,6 +66,19 @@ pub enum RoggaError {
         oro_client::OroClientError,
     ),
 
+    #[error(transparent)]
+    #[label("rogga::cache")]
+    CacheError(#[from] cacache::Error),
+
+    #[error("Failed to (de)serialize package index. {0}")]
+    #[label("rogga::cache::index")]
+    CacheIndexError(#[from] bincode::Error),
+
+    #[error("Integrity check failed for package tarball.\n\tWanted: {0}\n\tActual: {1}")]
+    #[label("rogga::integrity_mismatch")]
+    #[advice("The tarball may have been corrupted or tampered with in transit. If this keeps happening, check your registry or proxy.")]
+    IntegrityMismatch(Integrity, Integrity),
+
     #[error(transparent)]
     #[label("rogga::serde")]
     SerdeError(#[from] serde_json::Error),
@@ -67,6 +87,23 @@ pub enum RoggaError {
     #[label("rogga::bad_url")]
     UrlError(#[from] url::ParseError),
 
+    #[error("Invalid scoped registry `{0}`.")]
+    #[category(Parse)]
+    #[label("rogga::scope_registry")]
+    #[advice("Scoped registries should look like `@scope=https://registry.example.com/`.")]
+    InvalidScopeRegistry(String),
+
+    #[error("Invalid registry setting `{0}`.")]
+    #[category(Parse)]
+    #[label("rogga::registry_setting")]
+    #[advice("Registry settings should look like `//registry.example.com/:_authToken=<token>`.")]
+    InvalidRegistrySetting(String),
+
+    #[error("Cache at {0:?} stores {1} content, but packages need raw content.")]
+    #[label("rogga::cache::content_format")]
+    #[advice("Convert the cache with `oro cache migrate raw`, or use a different cache.")]
+    ContentFormatMismatch(PathBuf, cacache::ContentFormat),
+
     #[error(transparent)]
     #[label("rogga::which_git_failure")]
     #[advice("Are you sure git is installed and available in your $PATH?")]
@@ -87,6 +124,8 @@ impl Explain for RoggaError {
         match self {
             DirReadError(_, ref path) => Some(Meta::Fs { path: path.clone() }),
             ExtractIoError(_, Some(path)) => Some(Meta::Fs { path: path.clone() }),
+            MaliciousArchive(path, _) => Some(Meta::Fs { path: path.clone() }),
+            ContentFormatMismatch(path, _) => Some(Meta::Fs { path: path.clone() }),
             _ => None,
         }
     }
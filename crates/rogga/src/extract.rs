@@ -1,11 +1,12 @@
 use std::mem;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use async_compression::futures::bufread::GzipDecoder;
 use async_std::io::{self, BufReader};
 use async_std::prelude::*;
-use async_tar::Archive;
+use async_tar::{Archive, EntryType};
 use futures::AsyncRead;
+use ssri::Integrity;
 
 use crate::error::{Result, RoggaError};
 
@@ -15,11 +16,7 @@ where
     R: AsyncRead + Unpin + Send + Sync,
 {
     let dir = PathBuf::from(dir.as_ref());
-    let takeme = dir.clone();
-    async_std::task::spawn_blocking(move || {
-        mkdirp::mkdirp(&takeme).map_err(|e| RoggaError::ExtractIoError(e, Some(takeme.clone())))
-    })
-    .await?;
+    mkdirp(&dir).await?;
 
     let decoder = GzipDecoder::new(BufReader::new(tarball));
     let ar = Archive::new(decoder);
@@ -27,38 +24,90 @@ where
         .clone()
         .entries()
         .map_err(|e| RoggaError::ExtractIoError(e, None))?;
+    let mut symlinks = Vec::new();
 
     while let Some(file) = entries.next().await {
         let f = file.map_err(|e| RoggaError::ExtractIoError(e, None))?;
         let header = f.header();
-        let path = dir.join(
-            header
-                .path()
-                .map_err(|e| RoggaError::ExtractIoError(e, None))?
-                .as_ref(),
-        );
-        if let async_tar::EntryType::Regular = header.entry_type() {
-            let takeme = path.clone();
-
-            async_std::task::spawn_blocking(move || {
-                mkdirp::mkdirp(&takeme.parent().unwrap()).map_err(|e| {
-                    RoggaError::ExtractIoError(e, Some(takeme.parent().unwrap().into()))
-                })
-            })
-            .await?;
-            let mut writer = async_std::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(&path)
-                .await
-                .map_err(|e| RoggaError::ExtractIoError(e, Some(path.clone())))?;
-
-            io::copy(f, async_std::io::BufWriter::new(&mut writer))
-                .await
-                .map_err(|e| RoggaError::ExtractIoError(e, Some(path.clone())))?;
+        let entry_path = header
+            .path()
+            .map_err(|e| RoggaError::ExtractIoError(e, None))?
+            .into_owned();
+        let relative = match strip_entry_path(&entry_path)? {
+            Some(relative) => relative,
+            // The toplevel directory itself.
+            None => continue,
+        };
+        check_no_symlinks(&dir, &relative).await?;
+        let path = dir.join(&relative);
+
+        match header.entry_type() {
+            EntryType::Directory => {
+                mkdirp(&path).await?;
+            }
+            EntryType::Regular => {
+                let mode = header
+                    .mode()
+                    .map_err(|e| RoggaError::ExtractIoError(e, Some(path.clone())))?;
+                prepare_target(&path).await?;
+                let mut writer = create_file(&path, mode).await?;
+                io::copy(f, async_std::io::BufWriter::new(&mut writer))
+                    .await
+                    .map_err(|e| RoggaError::ExtractIoError(e, Some(path.clone())))?;
+            }
+            EntryType::Symlink => {
+                let target = header
+                    .link_name()
+                    .map_err(|e| RoggaError::ExtractIoError(e, Some(path.clone())))?
+                    .ok_or_else(|| {
+                        RoggaError::MaliciousArchive(entry_path.clone(), "symlink has no target")
+                    })?
+                    .into_owned();
+                if !link_stays_inside(&relative, &target) {
+                    return Err(RoggaError::MaliciousArchive(
+                        entry_path,
+                        "symlink points outside of the package",
+                    ));
+                }
+                prepare_target(&path).await?;
+                symlink(&target, &path).await?;
+                symlinks.push(path);
+            }
+            EntryType::Link => {
+                let target = header
+                    .link_name()
+                    .map_err(|e| RoggaError::ExtractIoError(e, Some(path.clone())))?
+                    .ok_or_else(|| {
+                        RoggaError::MaliciousArchive(entry_path.clone(), "hard link has no target")
+                    })?
+                    .into_owned();
+                let target = strip_entry_path(&target)?.ok_or_else(|| {
+                    RoggaError::MaliciousArchive(entry_path.clone(), "hard link to a directory")
+                })?;
+                check_no_symlinks(&dir, &target).await?;
+                let target = dir.join(target);
+                match async_std::fs::symlink_metadata(&target).await {
+                    Ok(meta) if meta.is_file() => {}
+                    _ => {
+                        return Err(RoggaError::MaliciousArchive(
+                            entry_path,
+                            "hard link target is not a file in the package",
+                        ))
+                    }
+                }
+                prepare_target(&path).await?;
+                async_std::fs::hard_link(&target, &path)
+                    .await
+                    .map_err(|e| RoggaError::ExtractIoError(e, Some(path.clone())))?;
+            }
+            other => {
+                log::trace!("Skipping unsupported {:?} entry: {:?}", other, entry_path);
+            }
         }
     }
 
+    check_symlinks(&dir, symlinks).await?;
+
     mem::drop(entries);
     let mut reader = ar
         .into_inner()
@@ -74,3 +123,340 @@ where
     log::trace!("Finished caching tarball contents from stream");
     Ok(())
 }
+
+/// Turns a path from a tarball entry into a path relative to the extraction
+/// directory. Like npm, this strips the first path component (usually
+/// `package/`), whatever it happens to be called. Returns `None` if nothing
+/// is left after stripping.
+pub(crate) fn strip_entry_path(path: &Path) -> Result<Option<PathBuf>> {
+    let mut stripped = PathBuf::new();
+    let mut first = true;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) if first => first = false,
+            Component::Normal(part) => stripped.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(RoggaError::MaliciousArchive(
+                    path.into(),
+                    "path escapes the package directory",
+                ));
+            }
+        }
+    }
+    if stripped.as_os_str().is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(stripped))
+    }
+}
+
+/// Checks that a symlink at `link` (relative to the extraction directory)
+/// pointing at `target` doesn't end up pointing outside of it.
+pub(crate) fn link_stays_inside(link: &Path, target: &Path) -> bool {
+    let mut depth = link.components().count().saturating_sub(1);
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Makes sure nothing gets written through a symlink created by an earlier
+/// entry, which could otherwise be used to write anywhere on the filesystem.
+pub(crate) async fn check_no_symlinks(dir: &Path, relative: &Path) -> Result<()> {
+    let mut current = dir.to_path_buf();
+    if let Some(parent) = relative.parent() {
+        for component in parent.components() {
+            current.push(component);
+            if let Ok(meta) = async_std::fs::symlink_metadata(&current).await {
+                if meta.file_type().is_symlink() {
+                    return Err(RoggaError::MaliciousArchive(
+                        relative.into(),
+                        "entry would be written through a symlink",
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Symlinks can be chained together into something that escapes the package
+/// even if each one of them looks harmless on its own, so do one last check
+/// on where they actually point once everything is on disk.
+pub(crate) async fn check_symlinks(dir: &Path, symlinks: Vec<PathBuf>) -> Result<()> {
+    if symlinks.is_empty() {
+        return Ok(());
+    }
+    let dir = dir.to_path_buf();
+    async_std::task::spawn_blocking(move || {
+        let root = dir
+            .canonicalize()
+            .map_err(|e| RoggaError::ExtractIoError(e, Some(dir.clone())))?;
+        for link in symlinks {
+            if let Ok(real) = link.canonicalize() {
+                if !real.starts_with(&root) {
+                    let _ = std::fs::remove_file(&link);
+                    return Err(RoggaError::MaliciousArchive(
+                        link,
+                        "symlink points outside of the package",
+                    ));
+                }
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+pub(crate) async fn mkdirp(dir: &Path) -> Result<()> {
+    let takeme = dir.to_path_buf();
+    async_std::task::spawn_blocking(move || {
+        mkdirp::mkdirp(&takeme).map_err(|e| RoggaError::ExtractIoError(e, Some(takeme.clone())))
+    })
+    .await?;
+    Ok(())
+}
+
+/// Creates the parent directory for `path` and gets rid of anything that
+/// might already be there, so links and fresh modes can be applied.
+pub(crate) async fn prepare_target(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        mkdirp(parent).await?;
+    }
+    if let Ok(meta) = async_std::fs::symlink_metadata(path).await {
+        if meta.is_dir() {
+            async_std::fs::remove_dir_all(path).await
+        } else {
+            async_std::fs::remove_file(path).await
+        }
+        .map_err(|e| RoggaError::ExtractIoError(e, Some(path.into())))?;
+    }
+    Ok(())
+}
+
+/// Creates a new file for extraction. Modes are normalized the same way npm
+/// does it: files are always readable, and executable if the tarball said
+/// they should be executable by anyone. The process umask then gets applied
+/// on top of that by the OS.
+async fn create_file(path: &Path, mode: u32) -> Result<async_std::fs::File> {
+    let mut opts = async_std::fs::OpenOptions::new();
+    opts.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use async_std::os::unix::fs::OpenOptionsExt;
+        opts.mode(if mode & 0o111 == 0 { 0o666 } else { 0o777 });
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    opts.open(path)
+        .await
+        .map_err(|e| RoggaError::ExtractIoError(e, Some(path.into())))
+}
+
+#[cfg(unix)]
+pub(crate) async fn symlink(target: &Path, path: &Path) -> Result<()> {
+    async_std::os::unix::fs::symlink(target, path)
+        .await
+        .map_err(|e| RoggaError::ExtractIoError(e, Some(path.into())))
+}
+
+#[cfg(windows)]
+pub(crate) async fn symlink(target: &Path, path: &Path) -> Result<()> {
+    // Windows needs to know up front whether a link points at a directory.
+    let resolved = match path.parent() {
+        Some(parent) => parent.join(target),
+        None => target.to_owned(),
+    };
+    let res = match async_std::fs::metadata(&resolved).await {
+        Ok(meta) if meta.is_dir() => async_std::os::windows::fs::symlink_dir(target, path).await,
+        _ => async_std::os::windows::fs::symlink_file(target, path).await,
+    };
+    res.map_err(|e| RoggaError::ExtractIoError(e, Some(path.into())))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) async fn symlink(_target: &Path, path: &Path) -> Result<()> {
+    Err(RoggaError::ExtractIoError(
+        std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "symlinks are not supported on this platform",
+        ),
+        Some(path.into()),
+    ))
+}
+
+/// Puts a file from the cache into place at `path`. Regular files are linked
+/// from the cache, but executables get their own copy, since changing the
+/// mode of a hard link would change it for the shared cache content too.
+pub(crate) async fn place_from_cache(
+    cache: &Path,
+    sri: &Integrity,
+    path: &Path,
+    mode: u32,
+) -> Result<()> {
+    if mode & 0o111 == 0 {
+        cacache::link_hash(cache, sri, path).await?;
+    } else {
+        mem::drop(create_file(path, mode).await?);
+        cacache::copy_hash(cache, sri, path).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_compression::futures::bufread::GzipEncoder;
+    use async_tar::{Builder, Header};
+
+    /// Builds a gzipped tarball out of `(type, path, link name, data)`
+    /// entries. Paths are written into the headers as-is, skipping the
+    /// checks `Header::set_path` would do, so they can be as nasty as needed.
+    async fn raw_tarball(entries: &[(EntryType, &str, &str, &[u8])]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (kind, path, link, data) in entries {
+            let mut header = Header::new_old();
+            {
+                let old = header.as_old_mut();
+                old.name[..path.len()].copy_from_slice(path.as_bytes());
+                old.linkname[..link.len()].copy_from_slice(link.as_bytes());
+            }
+            header.set_entry_type(*kind);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *data).await.unwrap();
+        }
+        let tar = builder.into_inner().await.unwrap();
+        let mut gzipped = Vec::new();
+        GzipEncoder::new(&tar[..])
+            .read_to_end(&mut gzipped)
+            .await
+            .unwrap();
+        gzipped
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn extracts_links_inside_the_package() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("pkg");
+        let data = raw_tarball(&[
+            (
+                EntryType::Regular,
+                "package/lib/cli.js",
+                "",
+                b"#!/usr/bin/env node",
+            ),
+            (EntryType::Symlink, "package/bin/cli", "../lib/cli.js", b""),
+            (EntryType::Link, "package/cli.js", "package/lib/cli.js", b""),
+        ])
+        .await;
+        extract_to_dir(&data[..], &dir).await.unwrap();
+        assert_eq!(
+            std::fs::read_link(dir.join("bin/cli")).unwrap(),
+            Path::new("../lib/cli.js")
+        );
+        assert_eq!(
+            std::fs::read(dir.join("bin/cli")).unwrap(),
+            b"#!/usr/bin/env node"
+        );
+        assert_eq!(
+            std::fs::read(dir.join("cli.js")).unwrap(),
+            b"#!/usr/bin/env node"
+        );
+    }
+
+    #[async_std::test]
+    async fn rejects_writing_through_symlinks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("pkg");
+        std::fs::create_dir(tmp.path().join("outside")).unwrap();
+
+        let data = raw_tarball(&[
+            (EntryType::Symlink, "package/evil", "../outside", b""),
+            (EntryType::Regular, "package/evil/pwned", "", b"pwned"),
+        ])
+        .await;
+        assert!(extract_to_dir(&data[..], &dir).await.is_err());
+        assert!(!tmp.path().join("outside/pwned").exists());
+
+        // Even links that stay inside the package can't be written through.
+        let data = raw_tarball(&[
+            (EntryType::Symlink, "package/lib", ".", b""),
+            (EntryType::Regular, "package/lib/pwned", "", b"pwned"),
+        ])
+        .await;
+        assert!(extract_to_dir(&data[..], &dir).await.is_err());
+        assert!(!dir.join("pwned").exists());
+    }
+
+    #[async_std::test]
+    async fn rejects_absolute_hard_links() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("pkg");
+        let secret = tmp.path().join("secret");
+        std::fs::write(&secret, b"secret").unwrap();
+        let secret = secret.display().to_string();
+
+        let data = raw_tarball(&[
+            (EntryType::Regular, "package/index.js", "", b""),
+            (EntryType::Link, "package/secret", &secret, b""),
+        ])
+        .await;
+        assert!(extract_to_dir(&data[..], &dir).await.is_err());
+        assert!(!dir.join("secret").exists());
+    }
+
+    #[async_std::test]
+    async fn rejects_parent_dir_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("pkg");
+
+        let data =
+            raw_tarball(&[(EntryType::Regular, "package/../escape.txt", "", b"pwned")]).await;
+        assert!(extract_to_dir(&data[..], &dir).await.is_err());
+        assert!(!tmp.path().join("escape.txt").exists());
+    }
+
+    #[test]
+    fn strips_leading_component() {
+        assert_eq!(
+            strip_entry_path(Path::new("package/lib/index.js")).unwrap(),
+            Some(PathBuf::from("lib/index.js"))
+        );
+        assert_eq!(
+            strip_entry_path(Path::new("node/./index.js")).unwrap(),
+            Some(PathBuf::from("index.js"))
+        );
+        assert_eq!(strip_entry_path(Path::new("package/")).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_escaping_paths() {
+        assert!(strip_entry_path(Path::new("package/../../etc/passwd")).is_err());
+        assert!(strip_entry_path(Path::new("/etc/passwd")).is_err());
+    }
+
+    #[test]
+    fn symlink_targets() {
+        assert!(link_stays_inside(
+            Path::new("bin/cli"),
+            Path::new("../lib/cli.js")
+        ));
+        assert!(link_stays_inside(Path::new("a/b/c"), Path::new("../../d")));
+        assert!(!link_stays_inside(Path::new("cli"), Path::new("../other")));
+        assert!(!link_stays_inside(Path::new("a/b"), Path::new("../../x")));
+        assert!(!link_stays_inside(
+            Path::new("cli"),
+            Path::new("/usr/bin/node")
+        ));
+    }
+}
@@ -23,4 +23,16 @@ pub trait PackageFetcher: std::fmt::Debug + Send + Sync {
     async fn metadata(&self, pkg: &Package) -> Result<VersionMetadata>;
     async fn packument(&self, pkg: &PackageSpec, base_dir: &Path) -> Result<Arc<Packument>>;
     async fn tarball(&self, pkg: &Package) -> Result<Box<dyn AsyncRead + Unpin + Send + Sync>>;
+
+    /// Like `tarball`, but also returns the response headers the tarball was
+    /// served with, for fetchers that get it over HTTP.
+    async fn tarball_with_headers(
+        &self,
+        pkg: &Package,
+    ) -> Result<(
+        Box<dyn AsyncRead + Unpin + Send + Sync>,
+        Vec<(String, String)>,
+    )> {
+        Ok((self.tarball(pkg).await?, Vec::new()))
+    }
 }
@@ -10,6 +10,7 @@ use oro_client::{self, OroClient};
 use oro_package_spec::PackageSpec;
 use url::Url;
 
+use crate::auth::RegistryCredentials;
 use crate::error::{Result, RoggaError};
 use crate::fetch::PackageFetcher;
 use crate::package::Package;
@@ -25,6 +26,7 @@ pub struct NpmFetcher {
     /// through a special Accept header on request.
     use_corgi: bool,
     registries: HashMap<String, Url>,
+    credentials: RegistryCredentials,
     packuments: DashMap<Url, Arc<Packument>>,
 }
 
@@ -33,11 +35,13 @@ impl NpmFetcher {
         client: Arc<Mutex<OroClient>>,
         use_corgi: bool,
         registries: HashMap<String, Url>,
+        credentials: RegistryCredentials,
     ) -> Self {
         Self {
             client,
             use_corgi,
             registries,
+            credentials,
             packuments: DashMap::new(),
         }
     }
@@ -74,7 +78,10 @@ impl NpmFetcher {
         if let Some(packument) = self.packuments.get(&packument_url) {
             return Ok(packument.value().clone());
         }
-        let opts = client.opts(Method::Get, packument_url.clone());
+        let mut opts = client.compressed_opts(Method::Get, packument_url.clone());
+        if let Some(auth) = self.credentials.for_url(&packument_url) {
+            opts = opts.header("Authorization", auth.header());
+        }
         let packument_data = client
             .send(opts.header(
                 "Accept",
@@ -141,6 +148,16 @@ impl PackageFetcher for NpmFetcher {
     }
 
     async fn tarball(&self, pkg: &Package) -> Result<Box<dyn AsyncRead + Unpin + Send + Sync>> {
+        Ok(self.tarball_with_headers(pkg).await?.0)
+    }
+
+    async fn tarball_with_headers(
+        &self,
+        pkg: &Package,
+    ) -> Result<(
+        Box<dyn AsyncRead + Unpin + Send + Sync>,
+        Vec<(String, String)>,
+    )> {
         // NOTE: This .clone() is so we can free up the client lock, which
         // would otherwise, you know, make it so we can only make one request
         // at a time :(
@@ -149,11 +166,24 @@ impl PackageFetcher for NpmFetcher {
             PackageResolution::Npm { ref tarball, .. } => tarball,
             _ => panic!("How did a non-Npm resolution get here?"),
         };
-        Ok(Box::new(
-            client
-                .send(client.opts(Method::Get, url.clone()))
-                .await
-                .map_err(RoggaError::OroClientError)?,
-        ))
+        let registry = match pkg.from().target() {
+            PackageSpec::Npm { ref scope, .. } => self.pick_registry(scope),
+            _ => self.pick_registry(&None),
+        };
+        let mut opts = client.opts(Method::Get, url.clone());
+        // Tarballs can live anywhere, so only send credentials along if
+        // they're actually meant for wherever this one is.
+        if let Some(auth) = self.credentials.for_tarball(url, &registry) {
+            opts = opts.header("Authorization", auth.header());
+        }
+        let res = client
+            .send(opts)
+            .await
+            .map_err(RoggaError::OroClientError)?;
+        let headers = res
+            .iter()
+            .map(|(name, values)| (name.as_str().to_owned(), values.last().as_str().to_owned()))
+            .collect();
+        Ok((Box::new(res), headers))
     }
 }
@@ -2,31 +2,24 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 
 use futures::prelude::*;
-use ssri::IntegrityOpts;
+use ssri::{Integrity, IntegrityOpts};
 
 pub struct AsyncIntegrity<R: AsyncBufRead> {
     pub opts: IntegrityOpts,
     pub reader: R,
 }
 
-/*
 impl<R: AsyncBufRead + Unpin> AsyncIntegrity<R> {
-    pub fn new(reader: R) -> Self {
-        Self {
-            reader,
-            opts: IntegrityOpts::new().algorithm(Algorithm::Sha256),
-        }
+    pub fn new(reader: R, opts: IntegrityOpts) -> Self {
+        Self { reader, opts }
     }
 
-    // pub fn into_inner(self) -> R {
-    //     self.reader
-    // }
-
-    pub fn inner_result(self) -> (ssri::Integrity, R) {
-        (self.opts.result(), self.reader)
+    /// Consumes this reader, returning the integrity of everything that was
+    /// read through it.
+    pub fn into_result(self) -> Integrity {
+        self.opts.result()
     }
 }
-*/
 
 impl<R: AsyncBufRead + Unpin> AsyncRead for AsyncIntegrity<R> {
     fn poll_read(
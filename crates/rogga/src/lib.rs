@@ -1,20 +1,25 @@
+pub use oro_client::{OroClientOpts, Secret};
 pub use oro_package_spec::{GitHost, GitInfo, PackageSpec, VersionSpec};
 
-mod cache;
+mod auth;
+pub mod cache;
 mod error;
 mod extract;
 mod fetch;
 mod integrity;
 mod package;
 mod packument;
+mod remote;
 mod request;
 mod resolver;
 mod rogga;
 
 pub use crate::rogga::*;
+pub use auth::{RegistryAuth, RegistryCredential, RegistrySetting};
 pub use error::RoggaError;
 pub use package::*;
 pub use packument::*;
+pub use remote::*;
 pub use request::*;
 pub use resolver::*;
 
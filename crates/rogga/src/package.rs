@@ -1,12 +1,17 @@
 use std::fmt;
+use std::path::{Path, PathBuf};
 
 use async_std::sync::Arc;
-use futures::io::AsyncRead;
+use futures::io::{AsyncRead, AsyncReadExt, Cursor};
 use oro_package_spec::PackageSpec;
+use ssri::Integrity;
 
-use crate::error::Result;
+use crate::cache;
+use crate::error::{Result, RoggaError};
+use crate::extract;
 use crate::fetch::PackageFetcher;
 use crate::packument::VersionMetadata;
+use crate::remote::RemoteCache;
 use crate::resolver::PackageResolution;
 
 /// A resolved package. A concrete version has been determined from its
@@ -16,6 +21,8 @@ pub struct Package {
     pub(crate) name: String,
     pub(crate) resolved: PackageResolution,
     pub(crate) fetcher: Arc<dyn PackageFetcher>,
+    pub(crate) cache: Option<PathBuf>,
+    pub(crate) remote: Option<Arc<RemoteCache>>,
 }
 
 impl Package {
@@ -38,6 +45,138 @@ impl Package {
     pub async fn tarball(&self) -> Result<Box<dyn AsyncRead + Unpin + Send + Sync>> {
         self.fetcher.tarball(&self).await
     }
+
+    /// Returns the expected integrity of this package's tarball, if known.
+    pub async fn integrity(&self) -> Result<Option<Integrity>> {
+        if let PackageResolution::Npm { .. } = self.resolved() {
+            if let Some(sri) = self.metadata().await?.dist.integrity {
+                return Ok(sri.parse().ok());
+            }
+        }
+        Ok(None)
+    }
+
+    /// Makes sure this package's contents are stored in `cache`, fetching
+    /// its tarball only if they aren't there already. Returns the tarball's
+    /// integrity, which can be used to look the package back up.
+    pub async fn store_in_cache(&self, cache: impl AsRef<Path>) -> Result<Integrity> {
+        let cache = cache.as_ref();
+        let expected = self.integrity().await?;
+        if let Some(sri) = &expected {
+            if cache::has_package(cache, sri).await? {
+                return Ok(sri.clone());
+            }
+        }
+        if let PackageResolution::Npm { tarball, .. } = self.resolved() {
+            if cacache::index_format(cache).await? == cacache::IndexFormat::Npm {
+                // Go through the tarball npm itself would have cached, so
+                // both sides get to reuse each other's downloads.
+                let sri = match &expected {
+                    Some(sri) if cacache::exists(cache, sri).await => sri.clone(),
+                    _ => {
+                        let fetched = self.fetch_tarball(expected.as_ref()).await?;
+                        let sri = cache::tarball_to_npm_cache(
+                            cache,
+                            tarball,
+                            &fetched.headers,
+                            fetched.data,
+                            expected.as_ref(),
+                        )
+                        .await?;
+                        self.share_tarball(&sri, fetched.fresh).await;
+                        sri
+                    }
+                };
+                let data = cacache::read_hash(cache, &sri).await?;
+                return cache::from_tarball(cache, Cursor::new(data), Some(&sri)).await;
+            }
+        }
+        let fetched = self.fetch_tarball(expected.as_ref()).await?;
+        let sri = cache::from_tarball(cache, fetched.data, expected.as_ref()).await?;
+        self.share_tarball(&sri, fetched.fresh).await;
+        Ok(sri)
+    }
+
+    /// Fetches this package's tarball, going to the remote cache first if
+    /// there is one. Tarballs that had to come from the registry are also
+    /// returned in full, so they can be shared once they've been verified.
+    async fn fetch_tarball(&self, expected: Option<&Integrity>) -> Result<FetchedTarball> {
+        let remote = match (&self.remote, self.resolved()) {
+            (Some(remote), PackageResolution::Npm { .. }) => remote,
+            _ => {
+                let (data, headers) = self.fetcher.tarball_with_headers(&self).await?;
+                return Ok(FetchedTarball {
+                    data,
+                    fresh: None,
+                    headers,
+                });
+            }
+        };
+        if let Some(sri) = expected {
+            // The remote cache is an optimization, so it failing shouldn't
+            // fail the install.
+            match remote.fetch(sri).await {
+                Ok(Some(data)) => {
+                    return Ok(FetchedTarball {
+                        data: Box::new(Cursor::new(data)),
+                        fresh: None,
+                        headers: Vec::new(),
+                    })
+                }
+                Ok(None) => {}
+                Err(err) => log::warn!("Failed to check remote cache for {}: {}", self.name, err),
+            }
+        }
+        let (mut tarball, headers) = self.fetcher.tarball_with_headers(&self).await?;
+        let mut data = Vec::new();
+        tarball
+            .read_to_end(&mut data)
+            .await
+            .map_err(|e| RoggaError::ExtractIoError(e, None))?;
+        // Caching and sharing the tarball both read from the same buffer.
+        let data: Arc<[u8]> = data.into();
+        Ok(FetchedTarball {
+            data: Box::new(Cursor::new(data.clone())),
+            fresh: Some(data),
+            headers,
+        })
+    }
+
+    async fn share_tarball(&self, sri: &Integrity, data: Option<Arc<[u8]>>) {
+        if let (Some(remote), Some(data)) = (&self.remote, data) {
+            if let Err(err) = remote.upload(sri, data).await {
+                log::warn!(
+                    "Failed to upload {} to the remote cache: {}",
+                    self.name,
+                    err
+                );
+            }
+        }
+    }
+
+    /// Extracts this package's contents into `dir`. If Rogga was configured
+    /// with a cache, file contents are stored there and linked into `dir`
+    /// instead of being written out directly, and packages that are already
+    /// cached are extracted without fetching their tarball at all.
+    pub async fn extract_to_dir(&self, dir: impl AsRef<Path>) -> Result<()> {
+        if let Some(cache) = &self.cache {
+            let sri = self.store_in_cache(cache).await?;
+            cache::to_dir(cache, &sri, dir).await
+        } else {
+            extract::extract_to_dir(self.tarball().await?, dir).await
+        }
+    }
+}
+
+/// A package tarball on its way into the cache.
+struct FetchedTarball {
+    data: Box<dyn AsyncRead + Unpin + Send + Sync>,
+    /// The whole tarball, if it came from the registry and should be shared
+    /// with the remote cache once it's been verified.
+    fresh: Option<Arc<[u8]>>,
+    /// Response headers the tarball was served with. Tarballs from the
+    /// remote cache don't have any worth keeping.
+    headers: Vec<(String, String)>,
 }
 
 impl fmt::Debug for Package {
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use futures::io::Cursor;
+use http_types::{Body, Method};
+use oro_client::{OroClient, OroClientError, StatusCode};
+use ssri::Integrity;
+use url::Url;
+
+use crate::error::{Result, RoggaError};
+
+/// A content store shared over plain HTTP, such as a team-wide package
+/// cache. Blobs live at `<base>/<algorithm>/<hex digest>`, are fetched with
+/// `GET` and uploaded with `PUT`.
+///
+/// The remote cache is never trusted: everything downloaded from it is
+/// checked against the integrity it was requested by, and anything that
+/// doesn't match is treated as a miss.
+#[derive(Clone, Debug)]
+pub struct RemoteCache {
+    client: OroClient,
+    base: Url,
+}
+
+impl RemoteCache {
+    pub fn new(client: OroClient, mut base: Url) -> Self {
+        // Make sure relative joins append to the base path instead of
+        // replacing its last segment.
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        Self { client, base }
+    }
+
+    /// Returns the URL the content for `sri` lives at.
+    pub fn blob_url(&self, sri: &Integrity) -> Result<Url> {
+        let (algorithm, hex) = sri.to_hex();
+        Ok(self.base.join(&format!("{}/{}", algorithm, hex))?)
+    }
+
+    /// Fetches the content for `sri`, returning `None` if the remote cache
+    /// doesn't have it or sent back something that fails verification.
+    pub async fn fetch(&self, sri: &Integrity) -> Result<Option<Vec<u8>>> {
+        let url = self.blob_url(sri)?;
+        let mut res = match self
+            .client
+            .send(self.client.opts(Method::Get, url.clone()))
+            .await
+        {
+            Ok(res) => res,
+            Err(OroClientError::ResponseError {
+                status_code: StatusCode::NotFound,
+                ..
+            }) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let data = res
+            .body_bytes()
+            .await
+            .map_err(|e| RoggaError::MiscError(e.to_string()))?;
+        if sri.check(&data).is_err() {
+            log::warn!(
+                "Ignoring remote cache content at {} that failed its integrity check",
+                url
+            );
+            return Ok(None);
+        }
+        Ok(Some(data))
+    }
+
+    /// Uploads `data` as the content for `sri`.
+    pub async fn upload(&self, sri: &Integrity, data: Arc<[u8]>) -> Result<()> {
+        let url = self.blob_url(sri)?;
+        let len = data.len();
+        let body = Body::from_reader(Cursor::new(data), Some(len));
+        self.client
+            .send(self.client.opts(Method::Put, url).body(body))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use async_std::io::BufReader;
+    use async_std::net::{TcpListener, TcpStream};
+    use async_std::prelude::*;
+    use async_std::sync::Mutex;
+    use async_std::task;
+
+    type Blobs = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Starts a tiny in-process blob server, returning its base URL and the
+    /// blobs it holds.
+    async fn serve() -> (Url, Blobs) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/cache/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let blobs = Blobs::default();
+        let served = blobs.clone();
+        task::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(Ok(stream)) = incoming.next().await {
+                task::spawn(handle(stream, served.clone()));
+            }
+        });
+        (url, blobs)
+    }
+
+    async fn handle(stream: TcpStream, blobs: Blobs) {
+        let mut reader = BufReader::new(stream.clone());
+        let mut writer = stream;
+        loop {
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let mut parts = request_line.split_whitespace();
+            let method = parts.next().unwrap_or("").to_string();
+            let path = parts.next().unwrap_or("").to_string();
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).await.unwrap();
+                let header = header.trim();
+                if header.is_empty() {
+                    break;
+                }
+                let mut kv = header.splitn(2, ':');
+                if kv.next().unwrap().eq_ignore_ascii_case("content-length") {
+                    length = kv.next().unwrap().trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).await.unwrap();
+            let (status, body) = match method.as_str() {
+                "PUT" => {
+                    blobs.lock().await.insert(path, body);
+                    ("201 Created", Vec::new())
+                }
+                _ => match blobs.lock().await.get(&path) {
+                    Some(data) => ("200 OK", data.clone()),
+                    None => ("404 Not Found", Vec::new()),
+                },
+            };
+            let head = format!(
+                "HTTP/1.1 {}\r\ncontent-length: {}\r\n\r\n",
+                status,
+                body.len()
+            );
+            writer.write_all(head.as_bytes()).await.unwrap();
+            writer.write_all(&body).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn blob_urls() {
+        let sri = Integrity::from(b"hello");
+        let (algorithm, hex) = sri.to_hex();
+        let remote = RemoteCache::new(
+            OroClient::new(),
+            "https://cache.example.com/team".parse().unwrap(),
+        );
+        assert_eq!(
+            remote.blob_url(&sri).unwrap().as_str(),
+            format!("https://cache.example.com/team/{}/{}", algorithm, hex)
+        );
+    }
+
+    #[async_std::test]
+    async fn round_trip() {
+        let (url, _) = serve().await;
+        let remote = RemoteCache::new(OroClient::new(), url);
+        let sri = Integrity::from(b"hello");
+        assert_eq!(remote.fetch(&sri).await.unwrap(), None);
+        remote.upload(&sri, Arc::from(&b"hello"[..])).await.unwrap();
+        assert_eq!(remote.fetch(&sri).await.unwrap(), Some(b"hello".to_vec()));
+    }
+
+    #[async_std::test]
+    async fn rejects_tampered_content() {
+        let (url, blobs) = serve().await;
+        let remote = RemoteCache::new(OroClient::new(), url);
+        let sri = Integrity::from(b"hello");
+        let path = remote.blob_url(&sri).unwrap().path().to_string();
+        blobs.lock().await.insert(path, b"not hello".to_vec());
+        assert_eq!(remote.fetch(&sri).await.unwrap(), None);
+    }
+}
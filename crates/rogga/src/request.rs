@@ -8,6 +8,7 @@ use crate::error::Result;
 use crate::fetch::PackageFetcher;
 use crate::package::Package;
 use crate::packument::Packument;
+use crate::remote::RemoteCache;
 use crate::resolver::{PackageResolution, PackageResolver};
 
 /// A package request from which more information can be derived. PackageRequest objects can be resolved into a `Package` by using a `PackageResolver`
@@ -16,6 +17,8 @@ pub struct PackageRequest {
     pub(crate) spec: PackageSpec,
     pub(crate) base_dir: PathBuf,
     pub(crate) fetcher: Arc<dyn PackageFetcher>,
+    pub(crate) cache: Option<PathBuf>,
+    pub(crate) remote: Option<Arc<RemoteCache>>,
 }
 
 impl PackageRequest {
@@ -48,6 +51,8 @@ impl PackageRequest {
             name: self.name,
             resolved,
             fetcher: self.fetcher,
+            cache: self.cache,
+            remote: self.remote,
         })
     }
 }
@@ -1,22 +1,28 @@
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use async_std::sync::{Arc, Mutex};
-use oro_client::OroClient;
+use oro_client::OroClientOpts;
 use url::Url;
 
 pub use oro_package_spec::{PackageSpec, VersionSpec};
 
-use crate::error::Result;
+use crate::auth::{RegistryAuth, RegistryCredentials};
+use crate::error::{Result, RoggaError};
 use crate::fetch::{DirFetcher, GitFetcher, NpmFetcher, PackageFetcher};
+use crate::remote::RemoteCache;
 use crate::request::PackageRequest;
 
 /// Build a new Rogga instance with specified options.
 #[derive(Default)]
 pub struct RoggaOpts {
     cache: Option<PathBuf>,
+    remote_cache: Option<Url>,
     registries: HashMap<String, Url>,
+    credentials: RegistryCredentials,
     use_corgi: Option<bool>,
+    client_opts: OroClientOpts,
 }
 
 impl RoggaOpts {
@@ -29,8 +35,37 @@ impl RoggaOpts {
         self
     }
 
+    /// Shared HTTP content store to check for package tarballs before going
+    /// to the registry, and to upload newly fetched tarballs to. Only used
+    /// when a local cache is configured as well.
+    pub fn remote_cache(mut self, remote_cache: Url) -> Self {
+        self.remote_cache = Some(remote_cache);
+        self
+    }
+
+    /// Registry to fetch packages in `scope` from. The scope can be given
+    /// with or without its leading `@`, and an empty scope sets the registry
+    /// used for everything else.
     pub fn add_registry(mut self, scope: impl AsRef<str>, registry: Url) -> Self {
-        self.registries.insert(scope.as_ref().into(), registry);
+        let scope = scope.as_ref().trim_start_matches('@');
+        self.registries.insert(scope.into(), registry);
+        self
+    }
+
+    /// Credentials to send with requests to any URL starting with `prefix`,
+    /// which can be given with or without a scheme (`//registry.example.com/`).
+    /// Tarballs also get the credentials of the registry they came from, but
+    /// only if they're on the same host.
+    pub fn registry_auth(mut self, prefix: impl AsRef<str>, auth: RegistryAuth) -> Self {
+        self.credentials.insert(prefix.as_ref(), auth);
+        self
+    }
+
+    /// Sends the credentials for `prefix` along with tarballs from that
+    /// registry even when they're hosted somewhere else, like npm's
+    /// `always-auth`.
+    pub fn always_auth(mut self, prefix: impl AsRef<str>) -> Self {
+        self.credentials.always_auth(prefix.as_ref());
         self
     }
 
@@ -39,21 +74,65 @@ impl RoggaOpts {
         self
     }
 
+    /// Options for the HTTP client used to talk to registries: retries,
+    /// connection limits, proxies and TLS.
+    pub fn client_opts(mut self, client_opts: OroClientOpts) -> Self {
+        self.client_opts = client_opts;
+        self
+    }
+
     pub fn build(self) -> Rogga {
-        let client = Arc::new(Mutex::new(OroClient::new()));
+        let oro_client = self.client_opts.build();
+        let remote = self
+            .remote_cache
+            .map(|url| Arc::new(RemoteCache::new(oro_client.clone(), url)));
+        let client = Arc::new(Mutex::new(oro_client));
         let use_corgi = self.use_corgi.unwrap_or(false);
         Rogga {
-            // cache: self.cache,
-            npm_fetcher: Arc::new(NpmFetcher::new(client.clone(), use_corgi, self.registries)),
+            cache: self.cache,
+            remote,
+            npm_fetcher: Arc::new(NpmFetcher::new(
+                client.clone(),
+                use_corgi,
+                self.registries,
+                self.credentials,
+            )),
             dir_fetcher: Arc::new(DirFetcher::new()),
             git_fetcher: Arc::new(GitFetcher::new(client)),
         }
     }
 }
 
+/// A registry to use for a specific scope, in `@scope=url` form, as accepted
+/// by `--scope-registry`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScopeRegistry {
+    pub scope: String,
+    pub registry: Url,
+}
+
+impl FromStr for ScopeRegistry {
+    type Err = RoggaError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || RoggaError::InvalidScopeRegistry(s.into());
+        let idx = s.find('=').ok_or_else(invalid)?;
+        let scope = s[..idx].trim().trim_start_matches('@');
+        if scope.is_empty() || scope.contains('/') {
+            return Err(invalid());
+        }
+        let registry = s[idx + 1..].trim().parse().map_err(|_| invalid())?;
+        Ok(ScopeRegistry {
+            scope: scope.into(),
+            registry,
+        })
+    }
+}
+
 /// Toplevel client for making package requests.
 pub struct Rogga {
-    // cache: Option<PathBuf>,
+    cache: Option<PathBuf>,
+    remote: Option<Arc<RemoteCache>>,
     npm_fetcher: Arc<dyn PackageFetcher>,
     dir_fetcher: Arc<dyn PackageFetcher>,
     git_fetcher: Arc<dyn PackageFetcher>,
@@ -85,6 +164,8 @@ impl Rogga {
             spec,
             fetcher,
             base_dir: base_dir.as_ref().into(),
+            cache: self.cache.clone(),
+            remote: self.remote.clone(),
         })
     }
 
@@ -103,6 +184,8 @@ impl Rogga {
             spec,
             fetcher,
             base_dir: base_dir.as_ref().into(),
+            cache: self.cache.clone(),
+            remote: self.remote.clone(),
         })
     }
 
@@ -116,3 +199,22 @@ impl Rogga {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_scope_registries() {
+        let registry: ScopeRegistry = "@myscope=https://npm.example.com/".parse().unwrap();
+        assert_eq!(registry.scope, "myscope");
+        assert_eq!(registry.registry.as_str(), "https://npm.example.com/");
+        let registry: ScopeRegistry = "myscope = https://npm.example.com/".parse().unwrap();
+        assert_eq!(registry.scope, "myscope");
+        assert!("@myscope".parse::<ScopeRegistry>().is_err());
+        assert!("=https://npm.example.com/"
+            .parse::<ScopeRegistry>()
+            .is_err());
+        assert!("@myscope=not a url".parse::<ScopeRegistry>().is_err());
+    }
+}
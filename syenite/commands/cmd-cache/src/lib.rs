@@ -0,0 +1,351 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use cacache::{ContentFormat, IndexFormat, ListOpts};
+use clap::Clap;
+use colored::*;
+use futures::stream::StreamExt;
+use humansize::{file_size_opts, FileSize};
+use oro_classic_resolver::ClassicResolver;
+use oro_command::{NetworkOpts, OroCommand};
+use oro_config::OroConfigLayer;
+use oro_diagnostics::{AsDiagnostic, DiagnosticResult as Result};
+use rogga::{
+    OroClientOpts, PackageResolution, RegistryCredential, RegistrySetting, RoggaOpts, ScopeRegistry,
+};
+use serde_json::json;
+use url::Url;
+
+#[derive(Debug, Clap, OroConfigLayer)]
+pub struct CacheCmd {
+    #[clap(about = "Location of the package cache.", long, short = 'C')]
+    cache: Option<PathBuf>,
+    #[clap(
+        about = "Maximum size of the package cache, in bytes, enforced after `add` and `verify`.",
+        long
+    )]
+    cache_max_size: Option<u64>,
+    #[clap(
+        about = "Registry to fetch packages from.",
+        default_value = "https://registry.npmjs.org",
+        long
+    )]
+    registry: Url,
+    #[clap(from_global)]
+    scope_registry: Vec<ScopeRegistry>,
+    // Per-registry credentials. These can hold secrets, so they're only
+    // read from config, never from the command line.
+    #[clap(skip)]
+    registry_auth: Vec<RegistrySetting>,
+    #[clap(flatten)]
+    #[oro_config(flatten)]
+    network: NetworkOpts,
+    #[clap(from_global)]
+    json: bool,
+    #[clap(from_global)]
+    quiet: bool,
+    #[clap(subcommand)]
+    #[oro_config(ignore)]
+    subcommand: CacheSubCmd,
+}
+
+#[derive(Debug, Clap)]
+pub enum CacheSubCmd {
+    #[clap(about = "List cache entries, with a size and age summary")]
+    Ls {
+        #[clap(about = "Only list entries whose keys contain this pattern")]
+        pattern: Option<String>,
+        #[clap(about = "Only list entries whose keys start with this prefix", long)]
+        prefix: Option<String>,
+        #[clap(about = "Only list entries written more than this many days ago", long)]
+        older_than: Option<u64>,
+    },
+    #[clap(about = "Verify cached data and garbage collect unused content")]
+    Verify,
+    #[clap(about = "Remove an entry from the cache, or clear the cache entirely")]
+    Clean {
+        #[clap(about = "Key to remove. The whole cache is cleared if omitted")]
+        key: Option<String>,
+    },
+    #[clap(about = "Convert cached content to another storage format")]
+    Migrate {
+        #[clap(about = "Format to store content in (raw, snappy)")]
+        format: ContentFormat,
+        #[clap(
+            about = "Index layout for new entries (cacache, npm). Use npm, with raw content, to share the cache with npm.",
+            long
+        )]
+        index: Option<IndexFormat>,
+    },
+    #[clap(about = "Fetch a package into the cache")]
+    Add {
+        #[clap(about = "Package spec to add")]
+        spec: String,
+    },
+}
+
+#[async_trait]
+impl OroCommand for CacheCmd {
+    async fn execute(self) -> Result<()> {
+        let cache = match self.cache.clone() {
+            Some(cache) => cache,
+            None => oro_command::default_cache()?,
+        };
+        match &self.subcommand {
+            CacheSubCmd::Ls {
+                pattern,
+                prefix,
+                older_than,
+            } => {
+                self.ls(&cache, pattern.as_deref(), prefix.as_deref(), *older_than)
+                    .await
+            }
+            CacheSubCmd::Verify => self.verify(&cache).await,
+            CacheSubCmd::Clean { key } => self.clean(&cache, key.as_deref()).await,
+            CacheSubCmd::Migrate { format, index } => self.migrate(&cache, *format, *index).await,
+            CacheSubCmd::Add { spec } => self.add(&cache, spec).await,
+        }
+    }
+}
+
+impl CacheCmd {
+    async fn ls(
+        &self,
+        cache: &Path,
+        pattern: Option<&str>,
+        prefix: Option<&str>,
+        older_than: Option<u64>,
+    ) -> Result<()> {
+        let mut entries = Vec::new();
+        if cache.exists() {
+            let mut opts = ListOpts::new();
+            if let Some(prefix) = prefix {
+                opts = opts.prefix(prefix);
+            }
+            if let Some(days) = older_than {
+                opts = opts.older_than(Duration::from_secs(days * 24 * 60 * 60));
+            }
+            let mut stream = opts.list(cache);
+            while let Some(entry) = stream.next().await {
+                let entry = entry.as_diagnostic("cache::ls")?;
+                if pattern.map(|p| entry.key.contains(p)).unwrap_or(true) {
+                    entries.push(entry);
+                }
+            }
+        }
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        let total: usize = entries.iter().map(|e| e.size).sum();
+        let oldest = entries.iter().map(|e| e.time).min();
+        let newest = entries.iter().map(|e| e.time).max();
+
+        if self.json {
+            let output = serde_json::to_string_pretty(&json!({
+                "cache": cache.display().to_string(),
+                "count": entries.len(),
+                "size": total,
+                "oldest": oldest.map(|t| t as u64),
+                "newest": newest.map(|t| t as u64),
+                "entries": entries.iter().map(|e| json!({
+                    "key": e.key,
+                    "integrity": e.integrity.to_string(),
+                    "size": e.size,
+                    "time": e.time as u64,
+                    "metadata": e.metadata,
+                })).collect::<Vec<_>>(),
+            }))
+            .as_diagnostic("cache::ls::serialize")?;
+            if !self.quiet {
+                println!("{}", output);
+            }
+        } else if !self.quiet {
+            for entry in entries.iter() {
+                println!(
+                    "{} {} {}",
+                    entry.key,
+                    human_size(entry.size).yellow(),
+                    human_age(entry.time).cyan()
+                );
+            }
+            print!(
+                "{} entries, {} total",
+                entries.len().to_string().yellow(),
+                human_size(total).yellow()
+            );
+            if let (Some(oldest), Some(newest)) = (oldest, newest) {
+                print!(
+                    ", oldest written {}, newest written {}",
+                    human_age(oldest).cyan(),
+                    human_age(newest).cyan()
+                );
+            }
+            println!();
+        }
+        Ok(())
+    }
+
+    async fn verify(&self, cache: &Path) -> Result<()> {
+        let stats = cacache::verify(cache)
+            .await
+            .as_diagnostic("cache::verify")?;
+        if self.json {
+            let output = serde_json::to_string_pretty(&json!({
+                "cache": cache.display().to_string(),
+                "verifiedContent": stats.verified_content,
+                "reclaimedCount": stats.reclaimed_count,
+                "reclaimedSize": stats.reclaimed_size,
+                "badContentCount": stats.bad_content_count,
+                "keptSize": stats.kept_size,
+                "totalEntries": stats.total_entries,
+                "rejectedEntries": stats.rejected_entries,
+            }))
+            .as_diagnostic("cache::verify::serialize")?;
+            if !self.quiet {
+                println!("{}", output);
+            }
+        } else if !self.quiet {
+            println!(
+                "{} entries, {} content blobs verified ({} kept)",
+                stats.total_entries.to_string().yellow(),
+                stats.verified_content.to_string().yellow(),
+                human_size(stats.kept_size as usize).yellow()
+            );
+            println!(
+                "{} blobs reclaimed ({}), {} corrupted, {} entries rejected",
+                stats.reclaimed_count.to_string().yellow(),
+                human_size(stats.reclaimed_size as usize).yellow(),
+                stats.bad_content_count.to_string().yellow(),
+                stats.rejected_entries.to_string().yellow()
+            );
+        }
+        self.evict(cache).await
+    }
+
+    async fn clean(&self, cache: &Path, key: Option<&str>) -> Result<()> {
+        if let Some(key) = key {
+            cacache::remove(cache, key)
+                .await
+                .as_diagnostic("cache::clean::remove")?;
+        } else if cache.exists() {
+            cacache::clear(cache)
+                .await
+                .as_diagnostic("cache::clean::clear")?;
+        }
+        if self.json && !self.quiet {
+            let output = serde_json::to_string_pretty(&json!({
+                "cache": cache.display().to_string(),
+                "removed": key,
+            }))
+            .as_diagnostic("cache::clean::serialize")?;
+            println!("{}", output);
+        }
+        Ok(())
+    }
+
+    async fn migrate(
+        &self,
+        cache: &Path,
+        format: ContentFormat,
+        index: Option<IndexFormat>,
+    ) -> Result<()> {
+        if let Some(index) = index {
+            cacache::set_index_format(cache, index)
+                .await
+                .as_diagnostic("cache::migrate::index")?;
+        }
+        let migrated = cacache::migrate_content(cache, format)
+            .await
+            .as_diagnostic("cache::migrate")?;
+        if self.json {
+            let output = serde_json::to_string_pretty(&json!({
+                "cache": cache.display().to_string(),
+                "format": format.to_string(),
+                "migrated": migrated,
+            }))
+            .as_diagnostic("cache::migrate::serialize")?;
+            if !self.quiet {
+                println!("{}", output);
+            }
+        } else if !self.quiet {
+            println!(
+                "Migrated {} entries to {} content",
+                migrated.to_string().yellow(),
+                format.to_string().bright_green()
+            );
+        }
+        Ok(())
+    }
+
+    async fn add(&self, cache: &Path, spec: &str) -> Result<()> {
+        let mut opts = RoggaOpts::new()
+            .add_registry("", self.registry.clone())
+            .client_opts(self.network.apply(OroClientOpts::new()));
+        for ScopeRegistry { scope, registry } in &self.scope_registry {
+            opts = opts.add_registry(scope, registry.clone());
+        }
+        for cred in RegistryCredential::from_settings(&self.registry_auth) {
+            if cred.always_auth {
+                opts = opts.always_auth(&cred.prefix);
+            }
+            opts = opts.registry_auth(cred.prefix, cred.auth);
+        }
+        let pkg = opts
+            .build()
+            .arg_request(spec, std::env::current_dir().as_diagnostic("cache::nocwd")?)
+            .await?
+            .resolve_with(&ClassicResolver::new())
+            .await?;
+        let sri = pkg.store_in_cache(cache).await?;
+        let version = match pkg.resolved() {
+            PackageResolution::Npm { version, .. } => version.to_string(),
+            _ => String::new(),
+        };
+        if self.json {
+            let output = serde_json::to_string_pretty(&json!({
+                "name": pkg.name(),
+                "version": version,
+                "integrity": sri.to_string(),
+            }))
+            .as_diagnostic("cache::add::serialize")?;
+            if !self.quiet {
+                println!("{}", output);
+            }
+        } else if !self.quiet {
+            println!(
+                "{}@{} {}",
+                pkg.name().bright_green(),
+                version.bright_green(),
+                sri.to_string().yellow()
+            );
+        }
+        self.evict(cache).await
+    }
+
+    async fn evict(&self, cache: &Path) -> Result<()> {
+        if let Some(max_size) = self.cache_max_size {
+            let stats = cacache::evict(cache, max_size)
+                .await
+                .as_diagnostic("cache::evict")?;
+            log::info!(
+                "Evicted {} entries from the cache, reclaiming {} bytes",
+                stats.evicted_entries,
+                stats.reclaimed_size
+            );
+        }
+        Ok(())
+    }
+}
+
+fn human_size(size: usize) -> String {
+    size.file_size(file_size_opts::DECIMAL)
+        .unwrap_or_else(|_| size.to_string())
+}
+
+fn human_age(time: u128) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(time);
+    let age = now.saturating_sub(time) as i64;
+    chrono_humanize::HumanTime::from(chrono::Duration::milliseconds(-age)).to_string()
+}
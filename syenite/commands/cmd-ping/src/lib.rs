@@ -2,8 +2,8 @@ use std::time::Instant;
 
 use async_trait::async_trait;
 use clap::Clap;
-use oro_client::{self, Method, OroClient};
-use oro_command::OroCommand;
+use oro_client::{self, Method, OroClientOpts};
+use oro_command::{NetworkOpts, OroCommand};
 use oro_config::OroConfigLayer;
 use oro_diagnostics::{AsDiagnostic, DiagnosticResult as Result};
 use serde_json::Value;
@@ -19,6 +19,9 @@ pub struct PingCmd {
     registry: Url,
     #[clap(from_global)]
     loglevel: log::LevelFilter,
+    #[clap(flatten)]
+    #[oro_config(flatten)]
+    network: NetworkOpts,
     #[clap(from_global)]
     json: bool,
     #[clap(from_global)]
@@ -32,7 +35,7 @@ impl OroCommand for PingCmd {
         if !self.quiet && !self.json {
             eprintln!("ping: {}", self.registry);
         }
-        let client = OroClient::new();
+        let client = self.network.apply(OroClientOpts::new()).build();
         let req = client.opts(
             Method::Get,
             self.registry.join("-/ping?write=true").unwrap(),
@@ -3,10 +3,11 @@ use std::path::PathBuf;
 
 use async_trait::async_trait;
 use clap::Clap;
-use node_maintainer::NodeMaintainerOptions;
-use oro_command::OroCommand;
+use node_maintainer::{LayoutMode, NodeMaintainerOptions};
+use oro_command::{NetworkOpts, OroCommand};
 use oro_config::OroConfigLayer;
 use oro_diagnostics::{AsDiagnostic, DiagnosticResult as Result};
+use rogga::{OroClientOpts, RegistryCredential, RegistrySetting, ScopeRegistry};
 use url::Url;
 
 #[derive(Debug, Clap, OroConfigLayer)]
@@ -20,6 +21,41 @@ pub struct PrimeCmd {
     )]
     registry: Url,
     #[clap(from_global)]
+    scope_registry: Vec<ScopeRegistry>,
+    // Per-registry credentials. These can hold secrets, so they're only
+    // read from config, never from the command line.
+    #[clap(skip)]
+    registry_auth: Vec<RegistrySetting>,
+    #[clap(
+        about = "Layout to use for node_modules (nested, isolated).",
+        default_value = "nested",
+        long
+    )]
+    layout: LayoutMode,
+    #[clap(
+        about = "Package cache to extract files through. Defaults to orogene's shared cache.",
+        long
+    )]
+    cache: Option<PathBuf>,
+    #[clap(
+        about = "Maximum size of the package cache, in bytes, enforced after priming.",
+        long
+    )]
+    cache_max_size: Option<u64>,
+    #[clap(
+        about = "Shared HTTP cache to fetch package tarballs from, and upload them to.",
+        long
+    )]
+    remote_cache: Option<Url>,
+    #[clap(
+        about = "Maximum number of packages to fetch at once. Defaults to 50.",
+        long
+    )]
+    concurrency: Option<usize>,
+    #[clap(flatten)]
+    #[oro_config(flatten)]
+    network: NetworkOpts,
+    #[clap(from_global)]
     json: bool,
     #[clap(from_global)]
     quiet: bool,
@@ -32,13 +68,45 @@ impl OroCommand for PrimeCmd {
         let root = self
             .root
             .unwrap_or_else(|| oro_pkg_root::pkg_root(&cwd).unwrap_or(cwd));
-        let mut nm = NodeMaintainerOptions::new()
+        let cache = match self.cache {
+            Some(cache) => cache,
+            None => oro_command::default_cache()?,
+        };
+        let mut opts = NodeMaintainerOptions::new()
             .registry(self.registry)
             .path(root.clone())
-            .init(root.display().to_string())
-            .await?;
+            .layout(self.layout)
+            .client_opts(self.network.apply(OroClientOpts::new()));
+        for ScopeRegistry { scope, registry } in &self.scope_registry {
+            opts = opts.scope_registry(scope, registry.clone());
+        }
+        for cred in RegistryCredential::from_settings(&self.registry_auth) {
+            if cred.always_auth {
+                opts = opts.always_auth(&cred.prefix);
+            }
+            opts = opts.registry_auth(cred.prefix, cred.auth);
+        }
+        if let Some(concurrency) = self.concurrency {
+            opts = opts.concurrency(concurrency);
+        }
+        opts = opts.cache(&cache);
+        if let Some(remote_cache) = &self.remote_cache {
+            opts = opts.remote_cache(remote_cache.clone());
+        }
+        let mut nm = opts.init(root.display().to_string()).await?;
         nm.resolve().await?;
         nm.render();
+        nm.extract().await?;
+        if let Some(max_size) = self.cache_max_size {
+            let stats = cacache::evict(&cache, max_size)
+                .await
+                .as_diagnostic("prime::cache::evict")?;
+            log::info!(
+                "Evicted {} packages from the cache, reclaiming {} bytes",
+                stats.evicted_entries,
+                stats.reclaimed_size
+            );
+        }
         Ok(())
     }
 }
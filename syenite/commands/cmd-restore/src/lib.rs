@@ -22,8 +22,12 @@ pub struct RestoreCmd {
         long
     )]
     registry: Url,
-    #[clap(about = "cache to fill up", long, short = 'C')]
-    cache: PathBuf,
+    #[clap(
+        about = "Package cache to fill up. Defaults to orogene's shared cache.",
+        long,
+        short = 'C'
+    )]
+    cache: Option<PathBuf>,
     #[clap(from_global)]
     loglevel: log::LevelFilter,
     #[clap(from_global)]
@@ -111,8 +115,13 @@ impl OroCommand for RestoreCmd {
     async fn execute(self) -> Result<()> {
         let pkglock: PkgLock =
             oro_tree::read("./package-lock.json").as_diagnostic("restore::read::packagelock")?;
+        let cache = match &self.cache {
+            Some(cache) => cache.clone(),
+            None => oro_command::default_cache()?,
+        };
         let rogga = RoggaOpts::new()
             .add_registry("", self.registry.clone())
+            .cache(cache)
             .build();
         let mut futs = Vec::new();
         for (name, dep) in pkglock.dependencies.iter() {
@@ -3,11 +3,14 @@ use clap::Clap;
 use colored::*;
 use humansize::{file_size_opts, FileSize};
 use oro_classic_resolver::ClassicResolver;
-use oro_command::OroCommand;
+use oro_command::{NetworkOpts, OroCommand};
 use oro_config::OroConfigLayer;
 use oro_diagnostics::{AsDiagnostic, DiagnosticResult as Result};
 use oro_manifest::{Bin, OroManifest, PersonField};
-use rogga::{Human, RoggaOpts, VersionMetadata};
+use rogga::{
+    Human, OroClientOpts, RegistryCredential, RegistrySetting, RoggaOpts, ScopeRegistry,
+    VersionMetadata,
+};
 use term_grid::{Cell, Direction, Filling, Grid, GridOptions};
 use url::Url;
 
@@ -20,6 +23,15 @@ pub struct ViewCmd {
     )]
     registry: Url,
     #[clap(from_global)]
+    scope_registry: Vec<ScopeRegistry>,
+    // Per-registry credentials. These can hold secrets, so they're only
+    // read from config, never from the command line.
+    #[clap(skip)]
+    registry_auth: Vec<RegistrySetting>,
+    #[clap(flatten)]
+    #[oro_config(flatten)]
+    network: NetworkOpts,
+    #[clap(from_global)]
     json: bool,
     #[clap(about = "Package spec to look up")]
     pkg: String,
@@ -28,9 +40,20 @@ pub struct ViewCmd {
 #[async_trait]
 impl OroCommand for ViewCmd {
     async fn execute(self) -> Result<()> {
-        let pkgreq = RoggaOpts::new()
+        let mut opts = RoggaOpts::new()
             .add_registry("", self.registry)
             .use_corgi(false)
+            .client_opts(self.network.apply(OroClientOpts::new()));
+        for ScopeRegistry { scope, registry } in &self.scope_registry {
+            opts = opts.add_registry(scope, registry.clone());
+        }
+        for cred in RegistryCredential::from_settings(&self.registry_auth) {
+            if cred.always_auth {
+                opts = opts.always_auth(&cred.prefix);
+            }
+            opts = opts.registry_auth(cred.prefix, cred.auth);
+        }
+        let pkgreq = opts
             .build()
             .arg_request(
                 &self.pkg,
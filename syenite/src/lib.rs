@@ -3,11 +3,13 @@ use std::path::PathBuf;
 
 use async_trait::async_trait;
 use clap::{ArgMatches, Clap, FromArgMatches, IntoApp};
-use directories::ProjectDirs;
+use directories::{BaseDirs, ProjectDirs};
 use oro_command::OroCommand;
 use oro_config::{OroConfig, OroConfigLayer, OroConfigOptions};
 use oro_diagnostics::{AsDiagnostic, DiagnosticResult as Result};
+use rogga::ScopeRegistry;
 
+use cmd_cache::CacheCmd;
 use cmd_ping::PingCmd;
 use cmd_prime::PrimeCmd;
 use cmd_restore::RestoreCmd;
@@ -39,6 +41,13 @@ pub struct Syenite {
     quiet: bool,
     #[clap(global = true, long, about = "Format output as JSON.")]
     json: bool,
+    #[clap(
+        global = true,
+        about = "Registry to use for a scope, as `@scope=url`. Can be given more than once.",
+        long,
+        number_of_values = 1
+    )]
+    scope_registry: Vec<ScopeRegistry>,
     #[clap(subcommand)]
     subcommand: OroCmd,
 }
@@ -92,6 +101,8 @@ impl Syenite {
                     ProjectDirs::from("", "", "orogene")
                         .map(|d| d.config_dir().to_owned().join("ororc.toml")),
                 )
+                .user_npmrc(user_npmrc())
+                .global_npmrc(global_npmrc())
                 .pkg_root(oro.root.clone())
                 .load()?
         };
@@ -104,6 +115,41 @@ impl Syenite {
     }
 }
 
+/// Looks up an npm config environment variable, which npm accepts in either
+/// case.
+fn npm_env(name: &str) -> Option<PathBuf> {
+    env::var_os(format!("npm_config_{}", name))
+        .or_else(|| env::var_os(format!("NPM_CONFIG_{}", name.to_uppercase())))
+        .map(PathBuf::from)
+}
+
+/// The user's `.npmrc`, found the same way npm finds it.
+fn user_npmrc() -> Option<PathBuf> {
+    npm_env("userconfig").or_else(|| BaseDirs::new().map(|d| d.home_dir().join(".npmrc")))
+}
+
+/// npm's global `npmrc`, which lives in `etc` under npm's prefix. Unless
+/// configured otherwise, that's the directory `node` is in on Windows, and
+/// its parent everywhere else.
+fn global_npmrc() -> Option<PathBuf> {
+    if let Some(file) = npm_env("globalconfig") {
+        return Some(file);
+    }
+    let prefix = match npm_env("prefix") {
+        Some(prefix) => prefix,
+        None => {
+            let node = which::which("node").ok()?;
+            let bin = node.parent()?;
+            if cfg!(windows) {
+                bin.to_owned()
+            } else {
+                bin.parent()?.to_owned()
+            }
+        }
+    };
+    Some(prefix.join("etc").join("npmrc"))
+}
+
 #[derive(Debug, Clap)]
 pub enum OroCmd {
     #[clap(
@@ -142,6 +188,13 @@ pub enum OroCmd {
         setting = clap::AppSettings::DeriveDisplayOrder,
     )]
     Shell(ShellCmd),
+    #[clap(
+        about = "Inspect and manage the package cache",
+        setting = clap::AppSettings::ColoredHelp,
+        setting = clap::AppSettings::DisableHelpSubcommand,
+        setting = clap::AppSettings::DeriveDisplayOrder,
+    )]
+    Cache(CacheCmd),
 }
 
 #[async_trait]
@@ -154,6 +207,7 @@ impl OroCommand for Syenite {
             OroCmd::Restore(restore) => restore.execute().await,
             OroCmd::View(view) => view.execute().await,
             OroCmd::Shell(shell) => shell.execute().await,
+            OroCmd::Cache(cache) => cache.execute().await,
         }
     }
 }
@@ -176,6 +230,9 @@ impl OroConfigLayer for Syenite {
             OroCmd::Shell(ref mut shell) => {
                 shell.layer_config(&args.subcommand_matches("shell").unwrap(), conf)
             }
+            OroCmd::Cache(ref mut cache) => {
+                cache.layer_config(&args.subcommand_matches("cache").unwrap(), conf)
+            }
         }
     }
 }